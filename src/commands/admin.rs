@@ -1,9 +1,19 @@
+use crate::commands::config::config;
+use crate::commands::maps::cooling_down;
+use crate::commands::panel::{post_panel, refresh_panel};
+use crate::commands::penalty::penalty;
+use crate::commands::pool::{pool, target_pool};
+use crate::commands::queue::ban_terms;
+use crate::commands::series::series;
+use crate::commands::start::{STARTING_MATCH_ID, UNKNOWN_MATCH_ID};
+use crate::commands::steamid::admin_steam_id;
 use crate::dathost::DathostClient;
-use crate::utils::clear_queue;
-use crate::{
-    utils::{reset_draft, write_to_file},
-    Context, State,
+use crate::storage::{MapEntry, QueueBan, DEFAULT_QUEUE};
+use crate::utils::{
+    clear_queue, clear_waitlist, lobby, parse_duration, promote_waitlist, promotion_message,
+    remove_from_queue, remove_from_waitlist, MAX_DURATION_DAYS,
 };
+use crate::{utils::reset_draft, Context, State};
 use anyhow::Result;
 use async_std::task;
 use chrono::DateTime;
@@ -11,8 +21,10 @@ use chrono::Datelike;
 use chrono::Duration as ChronoDuration;
 use chrono::Local;
 use chrono::TimeZone;
+use chrono::Utc;
 use core::time::Duration as CoreDuration;
-use poise::{command, serenity_prelude::User};
+use poise::command;
+use poise::serenity_prelude::{GuildChannel, User};
 use serenity::utils::MessageBuilder;
 
 #[command(
@@ -20,7 +32,19 @@ use serenity::utils::MessageBuilder;
     guild_only,
     ephemeral,
     default_member_permissions = "MODERATE_MEMBERS",
-    subcommands("map", "queue", "setup", "autoclear", "server", "team_size")
+    subcommands(
+        "map",
+        "queue",
+        "setup",
+        "autoclear",
+        "server",
+        "team_size",
+        "config",
+        "penalty",
+        "pool",
+        "series",
+        "admin_steam_id"
+    )
 )]
 pub(crate) async fn admin(_context: Context<'_>) -> Result<()> {
     Ok(())
@@ -31,7 +55,7 @@ pub(crate) async fn setup(_context: Context<'_>) -> Result<()> {
     Ok(())
 }
 
-#[command(slash_command, guild_only, ephemeral, subcommands("info"))]
+#[command(slash_command, guild_only, ephemeral, subcommands("info", "release"))]
 pub(crate) async fn server(_context: Context<'_>) -> Result<()> {
     Ok(())
 }
@@ -45,8 +69,48 @@ pub(crate) async fn server(_context: Context<'_>) -> Result<()> {
 pub(crate) async fn info(context: Context<'_>) -> Result<()> {
     let config = &context.data().config;
     let dathost_client = DathostClient::new(config).await?;
-    let server = dathost_client.get_server(&config.dathost.server_id).await?;
-    context.say(format!("{:#?}", server).to_string()).await?;
+    let busy_servers = context.data().storage.busy_servers()?;
+    let mut response = String::new();
+    for server_id in config.dathost.servers() {
+        let server = dathost_client.get_server(&server_id).await?;
+        match busy_servers.get(&server_id) {
+            Some(match_id) if match_id == UNKNOWN_MATCH_ID => response.push_str(&format!(
+                "Running a match with an unknown id, free it with `/admin server release {}`\n",
+                server_id
+            )),
+            Some(match_id) if match_id == STARTING_MATCH_ID => response.push_str(&format!(
+                "Starting a match, free it with `/admin server release {}` if it stays this way\n",
+                server_id
+            )),
+            Some(match_id) => response.push_str(&format!("Running match `{}`\n", match_id)),
+            None => response.push_str("Available\n"),
+        }
+        response.push_str(&format!("{:#?}\n", server));
+    }
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Mark a game server as free again")
+)]
+pub(crate) async fn release(
+    context: Context<'_>,
+    #[description = "DatHost server id"] server_id: String,
+) -> Result<()> {
+    if !context.data().config.dathost.servers().contains(&server_id) {
+        context
+            .say(format!("`{}` is not a configured server", server_id))
+            .await?;
+        return Ok(());
+    }
+    context.data().storage.release_server(&server_id)?;
+    context
+        .say(format!("Server `{}` is available again", server_id))
+        .await?;
     Ok(())
 }
 #[command(
@@ -55,7 +119,9 @@ pub(crate) async fn info(context: Context<'_>) -> Result<()> {
     description_localized("en-US", "Cancel the setup process")
 )]
 pub(crate) async fn cancel(context: Context<'_>) -> Result<()> {
-    reset_draft(&context).await?;
+    let lobby = lobby(&context).await?;
+    reset_draft(&lobby).await?;
+    refresh_panel(context.serenity_context(), &lobby).await;
     context.say("Setup canceled").await?;
     Ok(())
 }
@@ -66,7 +132,10 @@ pub(crate) async fn cancel(context: Context<'_>) -> Result<()> {
     description_localized("en-US", "Clear the queue")
 )]
 pub(crate) async fn clear(context: Context<'_>) -> Result<()> {
-    clear_queue(&context).await?;
+    let lobby = lobby(&context).await?;
+    clear_waitlist(&lobby).await?;
+    clear_queue(&lobby).await?;
+    refresh_panel(context.serenity_context(), &lobby).await;
     context.say("Queue cleared").await?;
     Ok(())
 }
@@ -74,7 +143,7 @@ pub(crate) async fn clear(context: Context<'_>) -> Result<()> {
     slash_command,
     guild_only,
     ephemeral,
-    subcommands("add_map", "remove_map")
+    subcommands("add_map", "edit_map", "remove_map", "end_map_cooldown")
 )]
 pub(crate) async fn map(_context: Context<'_>) -> Result<()> {
     Ok(())
@@ -88,35 +157,98 @@ pub(crate) async fn map(_context: Context<'_>) -> Result<()> {
 )]
 pub(crate) async fn add_map(
     context: Context<'_>,
-    #[description = "Map name"] map_name: String,
+    #[description = "Map name like de_dust2, or a workshop id"] map_name: String,
+    #[description = "Name shown in votes, defaults to the map name"] display_name: Option<String>,
+    #[description = "Image URL shown once the map is picked"] thumbnail_url: Option<String>,
+    #[description = "Map pool, defaults to the pool in use"] pool: Option<String>,
 ) -> Result<()> {
-    let maps = context.data().maps.lock().await.clone();
-    if maps.len() >= 26 {
-        context.say("Unable to add map, max amount reached").await?;
+    let lobby = lobby(&context).await?;
+    let Some(pool) = target_pool(&lobby, pool.as_deref()).await? else {
+        context.say("Map pool does not exist").await?;
         return Ok(());
-    }
-    if maps.contains(&map_name) {
+    };
+    if pool.maps.iter().any(|m| m.name == map_name) {
         context.say("Unable to add map, already exists").await?;
         return Ok(());
     }
-    let maps = {
-        let mut maps = context.data().maps.lock().await;
-        maps.push(String::from(&map_name));
-        maps.clone()
-    };
-    write_to_file(
-        String::from("data/maps.json"),
-        serde_json::to_string(&maps).unwrap(),
-    )
-    .await;
+    if matches!(thumbnail_url.as_deref(), Some(url) if !valid_url(url)) {
+        context.say("Thumbnail must be an http(s) URL").await?;
+        return Ok(());
+    }
+    let mut map = MapEntry::new(&map_name);
+    if let Some(display_name) = display_name {
+        map.display_name = display_name;
+    }
+    map.thumbnail_url = thumbnail_url;
+    lobby.storage.add_map(pool.id, &map)?;
+    lobby.reload_map_pools().await?;
     let response = MessageBuilder::new()
         .push("Added map: `")
-        .push(&map_name)
+        .push(&map.display_name)
+        .push("` to pool `")
+        .push(&pool.name)
         .push("`")
         .build();
     context.say(response).await?;
     Ok(())
 }
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "edit",
+    description_localized("en-US", "Change the display name, thumbnail or status of a map")
+)]
+pub(crate) async fn edit_map(
+    context: Context<'_>,
+    #[description = "Map name like de_dust2, or a workshop id"] map_name: String,
+    #[description = "Name shown in votes"] display_name: Option<String>,
+    #[description = "Image URL shown once the map is picked, `none` to remove it"]
+    thumbnail_url: Option<String>,
+    #[description = "Whether the map can be voted for"] enabled: Option<bool>,
+    #[description = "Map pool, defaults to the pool in use"] pool: Option<String>,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let Some(pool) = target_pool(&lobby, pool.as_deref()).await? else {
+        context.say("Map pool does not exist").await?;
+        return Ok(());
+    };
+    let Some(map) = pool.maps.iter().find(|m| m.name == map_name) else {
+        context
+            .say(format!(
+                "Map `{}` is not in the map pool `{}`",
+                map_name, pool.name
+            ))
+            .await?;
+        return Ok(());
+    };
+    let mut updated = map.clone();
+    if let Some(display_name) = display_name {
+        updated.display_name = display_name;
+    }
+    match thumbnail_url.as_deref() {
+        Some("none") => updated.thumbnail_url = None,
+        Some(url) if valid_url(url) => updated.thumbnail_url = Some(url.to_string()),
+        Some(_) => {
+            context.say("Thumbnail must be an http(s) URL").await?;
+            return Ok(());
+        }
+        None => {}
+    }
+    if let Some(enabled) = enabled {
+        updated.enabled = enabled;
+    }
+    lobby.storage.update_map(pool.id, &updated)?;
+    lobby.reload_map_pools().await?;
+    let response = MessageBuilder::new()
+        .push("Updated map `")
+        .push(&updated.name)
+        .push("`: ")
+        .push(describe_map(&updated))
+        .build();
+    context.say(response).await?;
+    Ok(())
+}
 #[command(
     slash_command,
     guild_only,
@@ -127,34 +259,116 @@ pub(crate) async fn add_map(
 pub(crate) async fn remove_map(
     context: Context<'_>,
     #[description = "Map name"] map_name: String,
+    #[description = "Map pool, defaults to the pool in use"] pool: Option<String>,
 ) -> Result<()> {
-    let maps = context.data().maps.lock().await.clone();
-    if !maps.contains(&map_name) {
+    let lobby = lobby(&context).await?;
+    let Some(pool) = target_pool(&lobby, pool.as_deref()).await? else {
+        context.say("Map pool does not exist").await?;
+        return Ok(());
+    };
+    if !pool.maps.iter().any(|m| m.name == map_name) {
         context
-            .say(format!("Map `{}` is not in the map pool", map_name))
+            .say(format!(
+                "Map `{}` is not in the map pool `{}`",
+                map_name, pool.name
+            ))
             .await?;
         return Ok(());
     }
-    let maps = {
-        let mut maps = context.data().maps.lock().await;
-        let index = maps.iter().position(|m| m == &map_name).unwrap();
-        maps.remove(index);
-        maps.clone()
+    lobby.storage.remove_map(pool.id, &map_name)?;
+    lobby.reload_map_pools().await?;
+    context
+        .say(format!(
+            "Removed map: `{}` from pool `{}`",
+            map_name, pool.name
+        ))
+        .await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "cooldown",
+    description_localized("en-US", "End the cooldown of a map, or of every map")
+)]
+pub(crate) async fn end_map_cooldown(
+    context: Context<'_>,
+    #[description = "Map name, leave empty for every map"] map_name: Option<String>,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let cooling = cooling_down(context.data(), guild_id)?;
+    let maps = match map_name {
+        Some(map_name) => {
+            let map_name = map_name.trim().to_lowercase();
+            if !cooling.contains(&map_name) {
+                context
+                    .say(format!("Map `{}` is not cooling down", map_name))
+                    .await?;
+                return Ok(());
+            }
+            vec![map_name]
+        }
+        None => cooling,
     };
-    write_to_file(
-        String::from("data/maps.json"),
-        serde_json::to_string(&maps).unwrap(),
-    )
-    .await;
-    context.say(format!("Removed map: `{}`", map_name)).await?;
+    if maps.is_empty() {
+        context.say("No map is cooling down").await?;
+        return Ok(());
+    }
+    let now = Utc::now();
+    for map in &maps {
+        context
+            .data()
+            .storage
+            .reset_map_cooldown(guild_id, map, now)?;
+    }
+    let maps: Vec<String> = maps.iter().map(|m| format!("`{}`", m)).collect();
+    context
+        .say(format!(
+            "Ended the cooldown of {}, they can be voted for again",
+            maps.join(", ")
+        ))
+        .await?;
     Ok(())
 }
 
+fn valid_url(url: &str) -> bool {
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// One line summary of a map for admins, e.g. `Dust II (disabled)`.
+fn describe_map(map: &MapEntry) -> String {
+    let mut description = map.display_name.clone();
+    if let Some(id) = map.workshop_id() {
+        description.push_str(&format!(" (workshop {})", id));
+    }
+    if map.thumbnail_url.is_some() {
+        description.push_str(" (thumbnail)");
+    }
+    if !map.enabled {
+        description.push_str(" (disabled)");
+    }
+    description
+}
+
 #[command(
     slash_command,
     guild_only,
     default_member_permissions = "MODERATE_MEMBERS",
-    subcommands("kick", "clear")
+    subcommands(
+        "kick",
+        "ban",
+        "unban",
+        "bans",
+        "clear",
+        "create_queue",
+        "delete_queue",
+        "bind_queue",
+        "unbind_queue",
+        "list_queues",
+        "panel_queue"
+    )
 )]
 pub(crate) async fn queue(_context: Context<'_>) -> Result<()> {
     Ok(())
@@ -166,7 +380,17 @@ pub(crate) async fn queue(_context: Context<'_>) -> Result<()> {
     description_localized("en-US", "Kick user from queue")
 )]
 pub(crate) async fn kick(context: Context<'_>, user: User) -> Result<()> {
-    let state = context.data().state.lock().await.clone();
+    let lobby = lobby(&context).await?;
+    if remove_from_waitlist(&lobby, user.id.0).await? {
+        let response = MessageBuilder::new()
+            .mention(&user)
+            .push(" has been removed from the waitlist.")
+            .build();
+        context.say(response).await?;
+        refresh_panel(context.serenity_context(), &lobby).await;
+        return Ok(());
+    }
+    let state = lobby.state.lock().await.clone();
     if state != State::Queue {
         context
             .send(|m| {
@@ -177,43 +401,370 @@ pub(crate) async fn kick(context: Context<'_>, user: User) -> Result<()> {
             .await?;
         return Ok(());
     }
-    let user_queue = {
-        let mut user_queue = context.data().user_queue.lock().await;
-        if !user_queue.contains(&user) {
-            let response = MessageBuilder::new()
-                .mention(context.author())
-                .push(" is not in the queue.")
-                .build();
-            context.send(|m| m.content(response)).await?;
+    if !remove_from_queue(&lobby, user.id.0).await? {
+        let response = MessageBuilder::new()
+            .mention(context.author())
+            .push(" is not in the queue.")
+            .build();
+        context.send(|m| m.content(response)).await?;
+        return Ok(());
+    }
+    let queue_size = lobby.user_queue.lock().await.len();
+    let max_queue_size = *lobby.team_size.lock().await * 2;
+    let response = MessageBuilder::new()
+        .mention(&user)
+        .push(" has been kicked. Queue size: ")
+        .push(queue_size.to_string())
+        .push(format!("/{}", max_queue_size))
+        .build();
+    context.say(response).await?;
+    let promoted = promote_waitlist(&lobby).await?;
+    if let Some(response) = promotion_message(&lobby, &promoted).await {
+        context.say(response).await?;
+    }
+    refresh_panel(context.serenity_context(), &lobby).await;
+
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    description_localized("en-US", "Ban user from joining the queue")
+)]
+pub(crate) async fn ban(
+    context: Context<'_>,
+    #[description = "User to ban"] user: User,
+    #[description = "How long the ban lasts, e.g. 3d or 12h. Permanent if not set"]
+    duration: Option<String>,
+    #[description = "Reason, shown to the user"] reason: Option<String>,
+) -> Result<()> {
+    let duration = match duration.as_deref().map(parse_duration) {
+        None => None,
+        Some(Some(duration)) => Some(duration),
+        Some(None) => {
+            context
+                .send(|m| {
+                    m.ephemeral(true).content(format!(
+                        "Duration must be like `3d`, `12h` or `1d12h`, and at most {} days",
+                        MAX_DURATION_DAYS
+                    ))
+                })
+                .await?;
             return Ok(());
         }
-        let index = user_queue.iter().position(|r| r.id == user.id).unwrap();
-        user_queue.remove(index);
-        user_queue.clone()
     };
+    let now = Utc::now();
+    let expires_at = match duration.map(|duration| now.checked_add_signed(duration)) {
+        None => None,
+        Some(Some(expires_at)) => Some(expires_at),
+        Some(None) => {
+            context
+                .send(|m| m.ephemeral(true).content("Ban duration is too long"))
+                .await?;
+            return Ok(());
+        }
+    };
+    let data = context.data();
+    let guild_id = context.guild_id().unwrap().0;
+    let ban = QueueBan {
+        guild_id,
+        user_id: user.id.0,
+        reason: reason
+            .map(|r| r.trim().to_string())
+            .filter(|r| !r.is_empty()),
+        banned_by: context.author().id.0,
+        created_at: now,
+        expires_at,
+    };
+    data.storage.ban_from_queue(&ban)?;
     let response = MessageBuilder::new()
         .mention(&user)
-        .push(" has been kicked. Queue size: ")
-        .push(user_queue.len().to_string())
-        .push("/10")
+        .push(" has been banned from the queue")
+        .push(ban_terms(&ban))
         .build();
     context.say(response).await?;
+    // a running setup is left alone, use `/admin setup cancel` if needed
+    for queue in data.storage.queues(guild_id)? {
+        let lobby = data.lobby_by_id(queue.id).await?;
+        let waitlisted = remove_from_waitlist(&lobby, user.id.0).await?;
+        let queued = !waitlisted
+            && *lobby.state.lock().await == State::Queue
+            && remove_from_queue(&lobby, user.id.0).await?;
+        if queued {
+            let promoted = promote_waitlist(&lobby).await?;
+            if let Some(response) = promotion_message(&lobby, &promoted).await {
+                context.say(response).await?;
+            }
+        }
+        if waitlisted || queued {
+            refresh_panel(context.serenity_context(), &lobby).await;
+        }
+    }
+    Ok(())
+}
 
+#[command(
+    slash_command,
+    guild_only,
+    description_localized("en-US", "Lift a queue ban")
+)]
+pub(crate) async fn unban(
+    context: Context<'_>,
+    #[description = "User to unban"] user: User,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let removed = context
+        .data()
+        .storage
+        .unban_from_queue(guild_id, user.id.0)?;
+    let response = match removed {
+        true => MessageBuilder::new()
+            .mention(&user)
+            .push(" can join the queue again")
+            .build(),
+        false => MessageBuilder::new()
+            .mention(&user)
+            .push(" is not banned")
+            .build(),
+    };
+    context.say(response).await?;
     Ok(())
 }
+
 #[command(
     slash_command,
     guild_only,
     ephemeral,
-    description_localized("en-US", "Enable autoclear feature")
+    description_localized("en-US", "List queue bans")
+)]
+pub(crate) async fn bans(context: Context<'_>) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let bans = context.data().storage.queue_bans(guild_id, Utc::now())?;
+    if bans.is_empty() {
+        context.say("No one is banned from the queue").await?;
+        return Ok(());
+    }
+    let mut response = MessageBuilder::new();
+    for ban in bans {
+        response.push_line(format!(
+            "- <@{}> by <@{}>{}",
+            ban.user_id,
+            ban.banned_by,
+            ban_terms(&ban)
+        ));
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "create",
+    description_localized("en-US", "Create a named queue")
+)]
+pub(crate) async fn create_queue(
+    context: Context<'_>,
+    #[description = "Queue name"] name: String,
+    #[description = "Channel to bind the queue to"]
+    #[channel_types("Text")]
+    channel: Option<GuildChannel>,
+) -> Result<()> {
+    let name = name.trim().to_lowercase();
+    let storage = &context.data().storage;
+    let guild_id = context.guild_id().unwrap().0;
+    if storage.queue_id(guild_id, &name)?.is_some() {
+        context
+            .say(format!("Queue `{}` already exists", name))
+            .await?;
+        return Ok(());
+    }
+    let queue_id = storage.create_queue(guild_id, &name)?;
+    let mut response = MessageBuilder::new();
+    response.push("Created queue `").push(&name).push("`");
+    if let Some(channel) = channel {
+        storage.bind_channel(queue_id, channel.id.0)?;
+        response.push(" in ").channel(channel.id);
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "delete",
+    description_localized("en-US", "Delete a named queue")
+)]
+pub(crate) async fn delete_queue(
+    context: Context<'_>,
+    #[description = "Queue name"] name: String,
+) -> Result<()> {
+    let name = name.trim().to_lowercase();
+    if name == DEFAULT_QUEUE {
+        context.say("The default queue cannot be deleted").await?;
+        return Ok(());
+    }
+    let data = context.data();
+    let Some(queue_id) = data
+        .storage
+        .queue_id(context.guild_id().unwrap().0, &name)?
+    else {
+        context
+            .say(format!("Queue `{}` does not exist", name))
+            .await?;
+        return Ok(());
+    };
+    let mut lobbies = data.lobbies.lock().await;
+    if let Some(lobby) = lobbies.get(&queue_id) {
+        if *lobby.state.lock().await != State::Queue {
+            context
+                .say(format!(
+                    "Queue `{}` has a setup in progress, use `/admin setup cancel` first",
+                    name
+                ))
+                .await?;
+            return Ok(());
+        }
+    }
+    data.storage.delete_queue(queue_id)?;
+    lobbies.remove(&queue_id);
+    context.say(format!("Deleted queue `{}`", name)).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "bind",
+    description_localized("en-US", "Bind a channel to a named queue")
+)]
+pub(crate) async fn bind_queue(
+    context: Context<'_>,
+    #[description = "Queue name"] name: String,
+    #[description = "Channel"]
+    #[channel_types("Text")]
+    channel: GuildChannel,
+) -> Result<()> {
+    let name = name.trim().to_lowercase();
+    let storage = &context.data().storage;
+    let Some(queue_id) = storage.queue_id(context.guild_id().unwrap().0, &name)? else {
+        context
+            .say(format!("Queue `{}` does not exist", name))
+            .await?;
+        return Ok(());
+    };
+    storage.bind_channel(queue_id, channel.id.0)?;
+    let response = MessageBuilder::new()
+        .channel(channel.id)
+        .push(" now uses queue `")
+        .push(&name)
+        .push("`")
+        .build();
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "unbind",
+    description_localized("en-US", "Unbind a channel from its queue")
+)]
+pub(crate) async fn unbind_queue(
+    context: Context<'_>,
+    #[description = "Channel"]
+    #[channel_types("Text")]
+    channel: GuildChannel,
+) -> Result<()> {
+    let response = if context.data().storage.unbind_channel(channel.id.0)? {
+        MessageBuilder::new()
+            .channel(channel.id)
+            .push(" now uses the default queue")
+            .build()
+    } else {
+        MessageBuilder::new()
+            .channel(channel.id)
+            .push(" is not bound to a queue")
+            .build()
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "list",
+    description_localized("en-US", "List the queues of this server")
+)]
+pub(crate) async fn list_queues(context: Context<'_>) -> Result<()> {
+    let data = context.data();
+    let guild_id = context.guild_id().unwrap().0;
+    data.storage.create_queue(guild_id, DEFAULT_QUEUE)?;
+    let mut response = MessageBuilder::new();
+    for queue in data.storage.queues(guild_id)? {
+        let size = data.storage.queue(queue.id)?.len();
+        response.push("`").push(&queue.name).push("` (");
+        response.push(size).push(" queued)");
+        if queue.channel_ids.is_empty() {
+            if queue.name == DEFAULT_QUEUE {
+                response.push(" - unbound channels");
+            }
+        } else {
+            response.push(" -");
+            for channel_id in &queue.channel_ids {
+                response.push(" ").channel(*channel_id);
+            }
+        }
+        response.push_line("");
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "panel",
+    description_localized("en-US", "Post a queue panel with join/leave buttons in this channel")
+)]
+pub(crate) async fn panel_queue(context: Context<'_>) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    post_panel(context.serenity_context(), &lobby, context.channel_id()).await?;
+    context.say("Queue panel posted").await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Enable autoclear feature for every queue of this server")
 )]
 pub(crate) async fn autoclear(context: Context<'_>) -> Result<()> {
-    let Some(autoclear_hour) = context.data().config.autoclear_hour else {
+    let lobby = lobby(&context).await?;
+    let guild_id = lobby.guild_id;
+    if context
+        .data()
+        .guild_config(guild_id)?
+        .autoclear_hour
+        .is_none()
+    {
         context.say("Autoclear config not set").await?;
         return Ok(());
-    };
-    context.say("Autoclear feature started").await?;
-    loop {
+    }
+    context
+        .say("Autoclear feature started for every queue of this server")
+        .await?;
+    // re-read every day so changes made with `/admin config` apply
+    while let Some(autoclear_hour) = context.data().guild_config(guild_id)?.autoclear_hour {
         let current: DateTime<Local> = Local::now();
         let mut autoclear: DateTime<Local> = Local
             .with_ymd_and_hms(
@@ -226,15 +777,32 @@ pub(crate) async fn autoclear(context: Context<'_>) -> Result<()> {
             )
             .unwrap();
         if autoclear.signed_duration_since(current).num_milliseconds() < 0 {
-            autoclear = autoclear + ChronoDuration::days(1)
+            autoclear += ChronoDuration::days(1)
         }
         let time_between: ChronoDuration = autoclear.signed_duration_since(current);
         task::sleep(CoreDuration::from_millis(
             time_between.num_milliseconds() as u64
         ))
         .await;
-        clear_queue(&context).await?;
+        // queues created since the command was run are cleared as well
+        let mut queue_ids: Vec<i64> = context
+            .data()
+            .storage
+            .queues(guild_id)?
+            .into_iter()
+            .map(|queue| queue.id)
+            .collect();
+        if !queue_ids.contains(&lobby.queue_id) {
+            queue_ids.push(lobby.queue_id);
+        }
+        for queue_id in queue_ids {
+            let lobby = context.data().lobby_by_id(queue_id).await?;
+            clear_waitlist(&lobby).await?;
+            clear_queue(&lobby).await?;
+            refresh_panel(context.serenity_context(), &lobby).await;
+        }
     }
+    Ok(())
 }
 
 #[command(
@@ -251,22 +819,40 @@ pub(crate) async fn team_size(
     #[max = 5]
     team_size: u8,
 ) -> Result<()> {
-    if team_size < 1 || team_size > 5 {
+    if !(1..=5).contains(&team_size) {
         let response = MessageBuilder::new()
             .push("Team size must be between 1 and 5")
             .build();
         context.say(response).await?;
         return Ok(());
     }
+    let lobby = lobby(&context).await?;
+    lobby.storage.set_team_size(lobby.queue_id, team_size)?;
     {
-        let mut team_size_lock = context.data().team_size.lock().await;
+        let mut team_size_lock = lobby.team_size.lock().await;
         *team_size_lock = team_size;
     }
-    let response = MessageBuilder::new()
+    let mut response = MessageBuilder::new();
+    response
         .push("Team size set to `")
-        .push(&team_size)
-        .push("`")
-        .build();
-    context.say(response).await?;
+        .push(team_size)
+        .push("`");
+    if let Some(pool) = lobby.map_pool().await {
+        response
+            .push(", map pool `")
+            .push(&pool.name)
+            .push("` is used");
+    }
+    context.say(response.build()).await?;
+    if *lobby.state.lock().await == State::Queue {
+        let promoted = promote_waitlist(&lobby).await?;
+        if let Some(response) = promotion_message(&lobby, &promoted).await {
+            context
+                .channel_id()
+                .say(&context.serenity_context().http, response)
+                .await?;
+        }
+    }
+    refresh_panel(context.serenity_context(), &lobby).await;
     Ok(())
 }
@@ -0,0 +1,320 @@
+use crate::{Context, GuildConfig};
+use anyhow::Result;
+use poise::command;
+use poise::serenity_prelude::{Channel, ChannelId, ChannelType, EmojiId, RoleId};
+use serenity::utils::MessageBuilder;
+
+/// A non-secret setting that can be changed at runtime. Values set this way are
+/// stored per guild and take precedence over `config.yaml`.
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum Setting {
+    #[name = "post_setup_msg"]
+    PostSetupMsg,
+    #[name = "autoclear_hour"]
+    AutoclearHour,
+    #[name = "team_a_channel_id"]
+    TeamAChannelId,
+    #[name = "team_b_channel_id"]
+    TeamBChannelId,
+    #[name = "emote_ct_id"]
+    EmoteCtId,
+    #[name = "emote_t_id"]
+    EmoteTId,
+    #[name = "emote_ct_name"]
+    EmoteCtName,
+    #[name = "emote_t_name"]
+    EmoteTName,
+    #[name = "assign_role_id"]
+    AssignRoleId,
+    #[name = "require_verified_steam_id"]
+    RequireVerifiedSteamId,
+    #[name = "map_veto"]
+    MapVeto,
+    #[name = "veto_order"]
+    VetoOrder,
+    #[name = "map_cooldown_matches"]
+    MapCooldownMatches,
+    #[name = "map_cooldown_hours"]
+    MapCooldownHours,
+    #[name = "ranked_map_vote"]
+    RankedMapVote,
+    #[name = "best_of_three"]
+    BestOfThree,
+}
+
+impl Setting {
+    const ALL: [Setting; 16] = [
+        Setting::PostSetupMsg,
+        Setting::AutoclearHour,
+        Setting::TeamAChannelId,
+        Setting::TeamBChannelId,
+        Setting::EmoteCtId,
+        Setting::EmoteTId,
+        Setting::EmoteCtName,
+        Setting::EmoteTName,
+        Setting::AssignRoleId,
+        Setting::RequireVerifiedSteamId,
+        Setting::MapVeto,
+        Setting::VetoOrder,
+        Setting::MapCooldownMatches,
+        Setting::MapCooldownHours,
+        Setting::RankedMapVote,
+        Setting::BestOfThree,
+    ];
+
+    fn key(&self) -> &'static str {
+        match self {
+            Setting::PostSetupMsg => "post_setup_msg",
+            Setting::AutoclearHour => "autoclear_hour",
+            Setting::TeamAChannelId => "team_a_channel_id",
+            Setting::TeamBChannelId => "team_b_channel_id",
+            Setting::EmoteCtId => "emote_ct_id",
+            Setting::EmoteTId => "emote_t_id",
+            Setting::EmoteCtName => "emote_ct_name",
+            Setting::EmoteTName => "emote_t_name",
+            Setting::AssignRoleId => "assign_role_id",
+            Setting::RequireVerifiedSteamId => "require_verified_steam_id",
+            Setting::MapVeto => "map_veto",
+            Setting::VetoOrder => "veto_order",
+            Setting::MapCooldownMatches => "map_cooldown_matches",
+            Setting::MapCooldownHours => "map_cooldown_hours",
+            Setting::RankedMapVote => "ranked_map_vote",
+            Setting::BestOfThree => "best_of_three",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<Setting> {
+        Setting::ALL.into_iter().find(|s| s.key() == key)
+    }
+
+    fn requirement(&self) -> &'static str {
+        match self {
+            Setting::PostSetupMsg => "at most 1000 characters long",
+            Setting::AutoclearHour => "an hour between 0 and 23",
+            Setting::TeamAChannelId | Setting::TeamBChannelId => "a voice channel of this server",
+            Setting::EmoteCtId | Setting::EmoteTId => "an emote of this server",
+            Setting::EmoteCtName | Setting::EmoteTName => "a single word",
+            Setting::AssignRoleId => "a role of this server",
+            Setting::RequireVerifiedSteamId
+            | Setting::MapVeto
+            | Setting::RankedMapVote
+            | Setting::BestOfThree => "`true` or `false`",
+            Setting::VetoOrder => "a sequence of A and B (the captains), e.g. `ABBA`",
+            Setting::MapCooldownMatches => "a number of matches between 0 (off) and 50",
+            Setting::MapCooldownHours => "a number of hours between 0 (off) and 720",
+        }
+    }
+
+    /// Writes a stored `value` into `config`.
+    pub fn apply(&self, config: &mut GuildConfig, value: &str) -> Result<()> {
+        match self {
+            Setting::PostSetupMsg => config.post_setup_msg = Some(value.to_string()),
+            Setting::AutoclearHour => config.autoclear_hour = Some(value.parse()?),
+            Setting::TeamAChannelId => config.team_a_channel_id = Some(value.parse()?),
+            Setting::TeamBChannelId => config.team_b_channel_id = Some(value.parse()?),
+            Setting::EmoteCtId => config.emote_ct_id = Some(value.parse()?),
+            Setting::EmoteTId => config.emote_t_id = Some(value.parse()?),
+            Setting::EmoteCtName => config.emote_ct_name = Some(value.to_string()),
+            Setting::EmoteTName => config.emote_t_name = Some(value.to_string()),
+            Setting::AssignRoleId => config.assign_role_id = Some(value.parse()?),
+            Setting::RequireVerifiedSteamId => {
+                config.require_verified_steam_id = Some(value.parse()?)
+            }
+            Setting::MapVeto => config.map_veto = Some(value.parse()?),
+            Setting::VetoOrder => config.veto_order = Some(value.to_string()),
+            Setting::MapCooldownMatches => config.map_cooldown_matches = Some(value.parse()?),
+            Setting::MapCooldownHours => config.map_cooldown_hours = Some(value.parse()?),
+            Setting::RankedMapVote => config.ranked_map_vote = Some(value.parse()?),
+            Setting::BestOfThree => config.best_of_three = Some(value.parse()?),
+        }
+        Ok(())
+    }
+
+    fn value(&self, config: &GuildConfig) -> Option<String> {
+        match self {
+            Setting::PostSetupMsg => config.post_setup_msg.clone(),
+            Setting::AutoclearHour => config.autoclear_hour.map(|h| h.to_string()),
+            Setting::TeamAChannelId => config.team_a_channel_id.map(|id| format!("<#{}>", id)),
+            Setting::TeamBChannelId => config.team_b_channel_id.map(|id| format!("<#{}>", id)),
+            Setting::EmoteCtId => config.emote_ct_id.map(|id| id.to_string()),
+            Setting::EmoteTId => config.emote_t_id.map(|id| id.to_string()),
+            Setting::EmoteCtName => config.emote_ct_name.clone(),
+            Setting::EmoteTName => config.emote_t_name.clone(),
+            Setting::AssignRoleId => config.assign_role_id.map(|id| format!("<@&{}>", id)),
+            Setting::RequireVerifiedSteamId => {
+                config.require_verified_steam_id.map(|r| r.to_string())
+            }
+            Setting::MapVeto => config.map_veto.map(|v| v.to_string()),
+            Setting::VetoOrder => config.veto_order.clone(),
+            Setting::MapCooldownMatches => config.map_cooldown_matches.map(|m| m.to_string()),
+            Setting::MapCooldownHours => config.map_cooldown_hours.map(|h| h.to_string()),
+            Setting::RankedMapVote => config.ranked_map_vote.map(|v| v.to_string()),
+            Setting::BestOfThree => config.best_of_three.map(|v| v.to_string()),
+        }
+    }
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    subcommands("show", "set", "reset")
+)]
+pub(crate) async fn config(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Show the current settings")
+)]
+pub(crate) async fn show(context: Context<'_>) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let config = context.data().guild_config(guild_id)?;
+    let overrides = context.data().storage.guild_settings(guild_id)?;
+    let mut response = MessageBuilder::new();
+    for setting in Setting::ALL {
+        response.push_bold(setting.key()).push(": ");
+        match setting.value(&config) {
+            Some(value) => response.push(value),
+            None => response.push_italic("not set"),
+        };
+        if overrides.contains_key(setting.key()) {
+            response.push(" (set with `/admin config`)");
+        }
+        response.push_line("");
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Change a setting")
+)]
+pub(crate) async fn set(
+    context: Context<'_>,
+    #[description = "Setting"] setting: Setting,
+    #[description = "New value (ids, mentions and emotes are accepted)"] value: String,
+) -> Result<()> {
+    let Some(value) = validate(&context, setting, value.trim()).await? else {
+        let response = MessageBuilder::new()
+            .push_mono(setting.key())
+            .push(" must be ")
+            .push(setting.requirement())
+            .build();
+        context.say(response).await?;
+        return Ok(());
+    };
+    let guild_id = context.guild_id().unwrap().0;
+    context
+        .data()
+        .storage
+        .set_guild_setting(guild_id, setting.key(), &value)?;
+    let response = MessageBuilder::new()
+        .push_mono(setting.key())
+        .push(" set to ")
+        .push_mono_safe(value)
+        .build();
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Reset a setting to its config.yaml value")
+)]
+pub(crate) async fn reset(
+    context: Context<'_>,
+    #[description = "Setting"] setting: Setting,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let removed = context
+        .data()
+        .storage
+        .remove_guild_setting(guild_id, setting.key())?;
+    let response = match removed {
+        true => format!("`{}` reset to its config.yaml value", setting.key()),
+        false => format!("`{}` was not changed with `/admin config`", setting.key()),
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+/// Checks `value` against the guild and returns it normalized for storage, or
+/// `None` if it does not meet [`Setting::requirement`].
+async fn validate(context: &Context<'_>, setting: Setting, value: &str) -> Result<Option<String>> {
+    let guild_id = context.guild_id().unwrap();
+    let valid = match setting {
+        Setting::PostSetupMsg => !value.is_empty() && value.chars().count() <= 1000,
+        Setting::AutoclearHour => matches!(value.parse::<u32>(), Ok(hour) if hour < 24),
+        Setting::RequireVerifiedSteamId
+        | Setting::MapVeto
+        | Setting::RankedMapVote
+        | Setting::BestOfThree => value.parse::<bool>().is_ok(),
+        Setting::MapCooldownMatches => matches!(value.parse::<u32>(), Ok(matches) if matches <= 50),
+        Setting::MapCooldownHours => matches!(value.parse::<u32>(), Ok(hours) if hours <= 720),
+        Setting::VetoOrder => {
+            !value.is_empty()
+                && value.len() <= 25
+                && value.chars().all(|c| matches!(c, 'A' | 'B' | 'a' | 'b'))
+        }
+        Setting::EmoteCtName | Setting::EmoteTName => {
+            !value.is_empty() && !value.contains(char::is_whitespace)
+        }
+        Setting::TeamAChannelId | Setting::TeamBChannelId => {
+            let Some(id) = parse_id(value) else {
+                return Ok(None);
+            };
+            match ChannelId(id).to_channel(context).await {
+                Ok(Channel::Guild(channel)) => {
+                    channel.guild_id == guild_id && channel.kind == ChannelType::Voice
+                }
+                _ => false,
+            }
+        }
+        Setting::EmoteCtId | Setting::EmoteTId => {
+            let Some(id) = parse_id(value) else {
+                return Ok(None);
+            };
+            guild_id.emoji(context, EmojiId(id)).await.is_ok()
+        }
+        Setting::AssignRoleId => {
+            let Some(id) = parse_id(value) else {
+                return Ok(None);
+            };
+            guild_id.roles(context).await?.contains_key(&RoleId(id))
+        }
+    };
+    if !valid {
+        return Ok(None);
+    }
+    Ok(Some(match setting {
+        Setting::TeamAChannelId
+        | Setting::TeamBChannelId
+        | Setting::EmoteCtId
+        | Setting::EmoteTId
+        | Setting::AssignRoleId => parse_id(value).unwrap().to_string(),
+        Setting::VetoOrder => value.to_uppercase(),
+        _ => value.to_string(),
+    }))
+}
+
+/// Accepts a raw id as well as a channel/role mention or an emote
+/// (`<#id>`, `<@&id>`, `<:name:id>`).
+fn parse_id(value: &str) -> Option<u64> {
+    value
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .rsplit(':')
+        .next()?
+        .trim_start_matches(['#', '@', '&'])
+        .parse()
+        .ok()
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use poise::{
+    command,
+    serenity_prelude::{ButtonStyle, InteractionResponseType, User, UserId},
+};
+use serenity::{
+    builder::{CreateActionRow, CreateButton},
+    futures::StreamExt,
+    utils::MessageBuilder,
+};
+
+use crate::storage::{MatchFilter, MatchRecord, MatchRecordTeam, Storage};
+use crate::Context;
+
+const PAGE_SIZE: u32 = 5;
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Show recently played matches")
+)]
+pub(crate) async fn history(
+    context: Context<'_>,
+    #[description = "Only show matches of this player"] player: Option<User>,
+    #[description = "Only show matches on this map"] map: Option<String>,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let storage = &context.data().storage;
+    let map = match map {
+        Some(map) => Some(resolve_map(storage.as_ref(), guild_id, &map)?),
+        None => None,
+    };
+    let filter = MatchFilter {
+        guild_id,
+        user_id: player.map(|u| u.id.0),
+        map,
+    };
+    let total = storage.match_count(&filter)?;
+    if total == 0 {
+        context.say("No matches found").await?;
+        return Ok(());
+    }
+    let pages = total.div_ceil(PAGE_SIZE);
+    let mut page = 0;
+    let content = format_matches(&storage.matches(&filter, PAGE_SIZE, 0)?, page, pages);
+    let msg = context
+        .send(|m| {
+            m.content(content)
+                .components(|c| c.add_action_row(create_page_action_row(page, pages)))
+        })
+        .await?;
+    if pages == 1 {
+        return Ok(());
+    }
+
+    let mut cib = msg
+        .message()
+        .await?
+        .await_component_interactions(context)
+        .timeout(Duration::from_secs(60 * 5))
+        .build();
+    while let Some(mci) = cib.next().await {
+        match mci.data.custom_id.as_str() {
+            "history_prev" => page = page.saturating_sub(1),
+            "history_next" => page = (page + 1).min(pages - 1),
+            _ => continue,
+        }
+        let matches = storage.matches(&filter, PAGE_SIZE, page * PAGE_SIZE)?;
+        let content = format_matches(&matches, page, pages);
+        mci.create_interaction_response(&context, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| {
+                    d.content(content)
+                        .components(|c| c.add_action_row(create_page_action_row(page, pages)))
+                })
+        })
+        .await?;
+    }
+    msg.edit(context, |m| m.components(|c| c)).await?;
+    Ok(())
+}
+
+/// Matches store the internal map name, so a display name of any of the
+/// guild's map pools is looked up first. Unknown maps are matched as given.
+fn resolve_map(storage: &dyn Storage, guild_id: u64, map: &str) -> Result<String> {
+    let map = map.trim();
+    for queue in storage.queues(guild_id)? {
+        for pool in storage.map_pools(queue.id)? {
+            if let Some(entry) = pool.maps.iter().find(|m| {
+                m.name.eq_ignore_ascii_case(map) || m.display_name.eq_ignore_ascii_case(map)
+            }) {
+                return Ok(entry.name.clone());
+            }
+        }
+    }
+    Ok(map.to_string())
+}
+
+fn format_matches(matches: &[MatchRecord], page: u32, pages: u32) -> String {
+    let mut response = MessageBuilder::new();
+    for record in matches {
+        let (team_a_side, team_b_side) = match record.team_b_start_side.as_str() {
+            "ct" => ("T", "CT"),
+            _ => ("CT", "T"),
+        };
+        response
+            .push_bold(format!("#{}", record.id))
+            .push(format!(" `{}` ", record.map))
+            .push_line(format!("<t:{}:f>", record.created_at.timestamp()))
+            .push_line(format_team(&record.team_a, team_a_side))
+            .push_line(format_team(&record.team_b, team_b_side));
+        if let Some(launched_by) = record.launched_by {
+            response.push("Launched by ").mention(&UserId(launched_by));
+            if let Some(dathost_match_id) = &record.dathost_match_id {
+                response.push(format!(" (DatHost match `{}`)", dathost_match_id));
+            }
+            response.push_line("");
+        }
+        response.push_line("");
+    }
+    response.push(format!("Page {}/{}", page + 1, pages));
+    response.build()
+}
+
+fn format_team(team: &MatchRecordTeam, side: &str) -> String {
+    let players: Vec<String> = team
+        .players
+        .iter()
+        .map(|p| {
+            if p.user_id == team.captain {
+                format!("@{} (c)", p.name)
+            } else {
+                format!("@{}", p.name)
+            }
+        })
+        .collect();
+    format!("**{}** ({}): {}", team.name, side, players.join(", "))
+}
+
+fn create_page_action_row(page: u32, pages: u32) -> CreateActionRow {
+    let mut ar = CreateActionRow::default();
+    let mut prev_button = CreateButton::default();
+    prev_button.custom_id("history_prev");
+    prev_button.label("Previous");
+    prev_button.style(ButtonStyle::Secondary);
+    prev_button.disabled(page == 0);
+    let mut next_button = CreateButton::default();
+    next_button.custom_id("history_next");
+    next_button.label("Next");
+    next_button.style(ButtonStyle::Secondary);
+    next_button.disabled(page + 1 >= pages);
+    ar.add_button(prev_button);
+    ar.add_button(next_button);
+    ar
+}
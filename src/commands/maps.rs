@@ -1,21 +1,185 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
 use poise::command;
 use serenity::utils::MessageBuilder;
 
-use crate::Context;
+use crate::commands::pool::target_pool;
+use crate::storage::{MatchFilter, MatchRecord};
+use crate::{utils::lobby, Context, Data};
+
+/// Matches looked at for map cooldowns, anything older never counts.
+const COOLDOWN_HISTORY: u32 = 100;
+
+/// Maps played within the last `map_cooldown_matches` matches or
+/// `map_cooldown_hours` hours of the guild, unless an admin ended their
+/// cooldown since.
+pub(crate) fn cooling_down(data: &Data, guild_id: u64) -> Result<Vec<String>> {
+    let config = data.guild_config(guild_id)?;
+    let matches = config.map_cooldown_matches.unwrap_or(0);
+    let hours = config.map_cooldown_hours.unwrap_or(0);
+    if matches == 0 && hours == 0 {
+        return Ok(Vec::new());
+    }
+    let resets = data.storage.map_cooldown_resets(guild_id)?;
+    let filter = MatchFilter {
+        guild_id,
+        user_id: None,
+        map: None,
+    };
+    let history = data
+        .storage
+        .matches(&filter, COOLDOWN_HISTORY.max(matches), 0)?;
+    Ok(cooling_maps(&history, &resets, matches, hours, Utc::now()))
+}
+
+/// Maps of `history`, newest first, that are still cooling down at `now`.
+fn cooling_maps(
+    history: &[MatchRecord],
+    resets: &HashMap<String, DateTime<Utc>>,
+    matches: u32,
+    hours: u32,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    let cutoff = now - ChronoDuration::hours(hours.into());
+    let mut cooling: Vec<String> = Vec::new();
+    // newest first, so the first match outside both limits ends the search
+    for (index, record) in history.iter().enumerate() {
+        if index as u32 >= matches && (hours == 0 || record.created_at <= cutoff) {
+            break;
+        }
+        let map = record.map.to_lowercase();
+        if matches!(resets.get(&map), Some(reset) if *reset >= record.created_at) {
+            continue;
+        }
+        if !cooling.contains(&map) {
+            cooling.push(map);
+        }
+    }
+    cooling
+}
 
 #[command(slash_command, guild_only, ephemeral)]
-pub(crate) async fn maps(context: Context<'_>) -> Result<()> {
-    let maps = context.data().maps.lock().await.clone();
-    let map_str: String = maps
-        .into_iter()
-        .map(|map| format!("- `{}`\n", map))
+pub(crate) async fn maps(
+    context: Context<'_>,
+    #[description = "Map pool, defaults to the pool in use"] pool: Option<String>,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let Some(pool) = target_pool(&lobby, pool.as_deref()).await? else {
+        context.say("Map pool does not exist").await?;
+        return Ok(());
+    };
+    let cooling = cooling_down(context.data(), lobby.guild_id)?;
+    let map_str: String = pool
+        .maps
+        .iter()
+        .filter(|map| map.enabled)
+        .map(|map| {
+            let name = match map.display_name == map.name {
+                true => format!("`{}`", map.name),
+                false => format!("{} (`{}`)", map.display_name, map.name),
+            };
+            match cooling.contains(&map.name.to_lowercase()) {
+                true => format!("- {} cooling down\n", name),
+                false => format!("- {}\n", name),
+            }
+        })
+        .collect();
+    let mut response = MessageBuilder::new();
+    response
+        .push_line(format!("Map pool `{}`:", pool.name))
+        .push(map_str);
+    let disabled: Vec<String> = pool
+        .maps
+        .iter()
+        .filter(|map| !map.enabled)
+        .map(|map| map.display_name.clone())
         .collect();
-    let response = MessageBuilder::new()
-        .push_line("Current map pool:")
-        .push(map_str)
-        .build();
-    context.say(response).await?;
+    if !disabled.is_empty() {
+        response.push_line(format!("Disabled: {}", disabled.join(", ")));
+    }
+    context.say(response.build()).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MatchRecordTeam;
+
+    fn team() -> MatchRecordTeam {
+        MatchRecordTeam {
+            name: String::new(),
+            captain: 1,
+            players: Vec::new(),
+        }
+    }
+
+    /// Matches on `maps`, newest first, played `hours_ago` before `now`.
+    fn history(now: DateTime<Utc>, maps: &[(&str, i64)]) -> Vec<MatchRecord> {
+        maps.iter()
+            .map(|(map, hours_ago)| MatchRecord {
+                id: 0,
+                guild_id: 1,
+                dathost_match_id: None,
+                map: map.to_string(),
+                team_a: team(),
+                team_b: team(),
+                team_b_start_side: "ct".to_string(),
+                launched_by: None,
+                created_at: now - ChronoDuration::hours(*hours_ago),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cooling_maps_by_match_count() {
+        let now = Utc::now();
+        let history = history(now, &[("de_Dust2", 1), ("de_mirage", 2), ("de_nuke", 3)]);
+        let resets = HashMap::new();
+        assert_eq!(
+            cooling_maps(&history, &resets, 2, 0, now),
+            vec!["de_dust2", "de_mirage"]
+        );
+        assert!(cooling_maps(&history, &resets, 0, 0, now).is_empty());
+    }
+
+    #[test]
+    fn cooling_maps_by_hours() {
+        let now = Utc::now();
+        let history = history(now, &[("de_dust2", 1), ("de_mirage", 5), ("de_nuke", 13)]);
+        let resets = HashMap::new();
+        assert_eq!(
+            cooling_maps(&history, &resets, 0, 12, now),
+            vec!["de_dust2", "de_mirage"]
+        );
+        // either limit keeps a map cooling down
+        assert_eq!(
+            cooling_maps(&history, &resets, 3, 2, now),
+            vec!["de_dust2", "de_mirage", "de_nuke"]
+        );
+    }
+
+    #[test]
+    fn cooling_maps_skips_matches_before_a_reset() {
+        let now = Utc::now();
+        let history = history(now, &[("de_dust2", 1), ("de_mirage", 2), ("de_dust2", 3)]);
+        // reset after the last dust2 match, mirage was reset before its match
+        let resets = HashMap::from([
+            ("de_dust2".to_string(), now),
+            ("de_mirage".to_string(), now - ChronoDuration::hours(4)),
+        ]);
+        assert_eq!(
+            cooling_maps(&history, &resets, 3, 0, now),
+            vec!["de_mirage"]
+        );
+        // a reset between two matches on the map only ends the older one
+        let resets = HashMap::from([("de_dust2".to_string(), now - ChronoDuration::minutes(150))]);
+        assert_eq!(
+            cooling_maps(&history, &resets, 3, 0, now),
+            vec!["de_dust2", "de_mirage"]
+        );
+    }
+}
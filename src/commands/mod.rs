@@ -1,6 +1,12 @@
 pub(crate) mod admin;
+pub(crate) mod config;
+pub(crate) mod history;
 pub(crate) mod maps;
+pub(crate) mod panel;
+pub(crate) mod penalty;
+pub(crate) mod pool;
 pub(crate) mod queue;
+pub(crate) mod series;
 pub(crate) mod start;
 pub(crate) mod stats;
 pub(crate) mod steamid;
@@ -0,0 +1,177 @@
+use crate::commands::queue::{
+    add_player, assign_queue_role, join_response, leave_response, remove_player, steam_id_error,
+    Joined, Left,
+};
+use crate::storage::{QueuePanel, DEFAULT_QUEUE};
+use crate::utils::{promote_waitlist, promotion_message};
+use crate::{Data, Lobby, State};
+use anyhow::Result;
+use poise::serenity_prelude::{
+    ButtonStyle, ChannelId, Context as SerenityContext, InteractionResponseType, MessageId,
+};
+use serenity::{
+    builder::{CreateActionRow, CreateButton, CreateEmbed},
+    http::Http,
+    model::application::interaction::message_component::MessageComponentInteraction,
+};
+
+const PANEL_JOIN: &str = "queue_panel_join";
+const PANEL_LEAVE: &str = "queue_panel_leave";
+
+/// Posts a new panel for `lobby` in `channel_id`, replacing its previous one.
+pub(crate) async fn post_panel(
+    http: impl AsRef<Http>,
+    lobby: &Lobby,
+    channel_id: ChannelId,
+) -> Result<()> {
+    let embed = create_panel_embed(lobby).await?;
+    let msg = channel_id
+        .send_message(&http, |m| {
+            m.set_embed(embed)
+                .components(|c| c.add_action_row(create_panel_action_row()))
+        })
+        .await?;
+    if let Some(old) = lobby.storage.queue_panel(lobby.queue_id)? {
+        if let Err(err) = ChannelId(old.channel_id)
+            .delete_message(&http, old.message_id)
+            .await
+        {
+            log::warn!("Unable to delete old queue panel: {}", err);
+        }
+    }
+    lobby.storage.set_queue_panel(&QueuePanel {
+        queue_id: lobby.queue_id,
+        channel_id: channel_id.0,
+        message_id: msg.id.0,
+    })
+}
+
+/// Redraws the panel of `lobby`, if it has one. Called after every change to
+/// the queue; failures are logged, the change itself already happened.
+pub(crate) async fn refresh_panel(http: impl AsRef<Http>, lobby: &Lobby) {
+    if let Err(err) = update_panel(http, lobby).await {
+        log::warn!("Unable to update queue panel: {:?}", err);
+    }
+}
+
+async fn update_panel(http: impl AsRef<Http>, lobby: &Lobby) -> Result<()> {
+    let Some(panel) = lobby.storage.queue_panel(lobby.queue_id)? else {
+        return Ok(());
+    };
+    let embed = create_panel_embed(lobby).await?;
+    ChannelId(panel.channel_id)
+        .edit_message(&http, MessageId(panel.message_id), |m| m.set_embed(embed))
+        .await?;
+    Ok(())
+}
+
+/// Handles the Join/Leave buttons of every panel. The panel is looked up by
+/// message, so buttons keep working after a restart.
+pub(crate) async fn handle_panel_interaction(
+    serenity: &SerenityContext,
+    data: &Data,
+    mci: &MessageComponentInteraction,
+) -> Result<()> {
+    let custom_id = mci.data.custom_id.as_str();
+    if custom_id != PANEL_JOIN && custom_id != PANEL_LEAVE {
+        return Ok(());
+    }
+    let Some(panel) = data.storage.queue_panel_by_message(mci.message.id.0)? else {
+        return Ok(());
+    };
+    let lobby = data.lobby_by_id(panel.queue_id).await?;
+    let mut changed = false;
+    let response = if custom_id == PANEL_JOIN {
+        match steam_id_error(data, lobby.guild_id, &mci.user).await? {
+            Some(response) => response,
+            None => {
+                let joined = add_player(&lobby, &mci.user, None).await?;
+                changed = matches!(joined, Joined::Queue | Joined::Waitlist(_));
+                join_response(&lobby, &mci.user, &joined, None).await
+            }
+        }
+    } else {
+        let left = remove_player(serenity, &lobby, mci.user.id.0).await?;
+        changed = matches!(left, Left::Queue | Left::Waitlist | Left::EarlyLeave(_));
+        leave_response(&lobby, &mci.user, &left).await
+    };
+    mci.create_interaction_response(serenity, |r| {
+        r.kind(InteractionResponseType::ChannelMessageWithSource)
+            .interaction_response_data(|d| d.ephemeral(true).content(response))
+    })
+    .await?;
+    if !changed {
+        return Ok(());
+    }
+    if custom_id == PANEL_JOIN {
+        assign_queue_role(serenity, data, lobby.guild_id, &mci.user).await?;
+    } else {
+        let promoted = promote_waitlist(&lobby).await?;
+        if let Some(response) = promotion_message(&lobby, &promoted).await {
+            mci.channel_id.say(serenity, response).await?;
+        }
+    }
+    refresh_panel(serenity, &lobby).await;
+    Ok(())
+}
+
+async fn create_panel_embed(lobby: &Lobby) -> Result<CreateEmbed> {
+    let user_queue = lobby.user_queue.lock().await.clone();
+    let waitlist = lobby.waitlist.lock().await.clone();
+    let queue_messages = lobby.queue_messages.lock().await.clone();
+    let expiries = lobby.storage.queue_expiries(lobby.queue_id)?;
+    let max_queue_size = *lobby.team_size.lock().await * 2;
+    let state = lobby.state.lock().await.clone();
+
+    let mut players = String::new();
+    for (position, user) in user_queue.iter().enumerate() {
+        players.push_str(&format!("{}. {}", position + 1, user));
+        if let Some(message) = queue_messages.get(user.id.as_u64()) {
+            players.push_str(&format!(": `{}`", message));
+        }
+        if let Some(expires_at) = expiries.get(user.id.as_u64()) {
+            players.push_str(&format!(" (leaves <t:{}:R>)", expires_at.timestamp()));
+        }
+        players.push('\n');
+    }
+    if players.is_empty() {
+        players.push_str("No one is queued yet");
+    }
+
+    let mut embed = CreateEmbed::default();
+    match lobby.name.as_str() {
+        DEFAULT_QUEUE => embed.title("Queue"),
+        name => embed.title(format!("Queue `{}`", name)),
+    };
+    embed
+        .description(players)
+        .field(
+            "Players",
+            format!("{}/{}", user_queue.len(), max_queue_size),
+            true,
+        )
+        .colour(0x2ecc71);
+    if !waitlist.is_empty() {
+        let waitlist: Vec<String> = waitlist.iter().map(|u| u.to_string()).collect();
+        embed.field("Waitlist", waitlist.join("\n"), true);
+    }
+    if state != State::Queue {
+        embed.field("Status", "Setup in progress", true);
+    }
+    Ok(embed)
+}
+
+fn create_panel_action_row() -> CreateActionRow {
+    let mut ar = CreateActionRow::default();
+    let mut join_button = CreateButton::default();
+    join_button.custom_id(PANEL_JOIN);
+    join_button.label("Join");
+    join_button.style(ButtonStyle::Success);
+    let mut leave_button = CreateButton::default();
+    leave_button.custom_id(PANEL_LEAVE);
+    leave_button.label("Leave");
+    leave_button.style(ButtonStyle::Danger);
+    ar.add_button(join_button);
+    ar.add_button(leave_button);
+    ar
+}
@@ -0,0 +1,244 @@
+use crate::storage::{Penalty, PenaltyKind, Storage};
+use crate::Context;
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use poise::command;
+use poise::serenity_prelude::User;
+use serenity::utils::MessageBuilder;
+
+/// Queue cooldown for the first, second, ... penalty within
+/// `PENALTY_WINDOW_DAYS`. Every further penalty gets the last one.
+const COOLDOWN_MINUTES: [i64; 5] = [30, 2 * 60, 12 * 60, 2 * 24 * 60, 7 * 24 * 60];
+/// Penalties older than this no longer escalate the cooldown.
+const PENALTY_WINDOW_DAYS: i64 = 30;
+
+/// Records a penalty for the user, with a cooldown depending on how many
+/// penalties they got recently.
+pub(crate) fn penalize(
+    storage: &dyn Storage,
+    guild_id: u64,
+    user_id: u64,
+    kind: PenaltyKind,
+) -> Result<Penalty> {
+    let now = Utc::now();
+    let window_start = now - ChronoDuration::days(PENALTY_WINDOW_DAYS);
+    let recent = storage
+        .penalties(guild_id, user_id)?
+        .iter()
+        .filter(|p| p.created_at > window_start)
+        .count();
+    let minutes = COOLDOWN_MINUTES[recent.min(COOLDOWN_MINUTES.len() - 1)];
+    let penalty = Penalty {
+        guild_id,
+        user_id,
+        kind,
+        created_at: now,
+        cooldown_until: now + ChronoDuration::minutes(minutes),
+    };
+    storage.add_penalty(&penalty)?;
+    log::info!(
+        "Penalized user {} in guild {} for {}",
+        user_id,
+        guild_id,
+        kind.key()
+    );
+    Ok(penalty)
+}
+
+/// End of the user's queue cooldown, if they are on one.
+pub(crate) fn cooldown(
+    storage: &dyn Storage,
+    guild_id: u64,
+    user_id: u64,
+) -> Result<Option<DateTime<Utc>>> {
+    let now = Utc::now();
+    Ok(storage
+        .penalties(guild_id, user_id)?
+        .into_iter()
+        .map(|p| p.cooldown_until)
+        .filter(|until| *until > now)
+        .max())
+}
+
+pub(crate) fn describe(kind: PenaltyKind) -> &'static str {
+    match kind {
+        PenaltyKind::MissedReadyCheck => "missed a ready check",
+        PenaltyKind::EarlyLeave => "left after the teams were posted",
+        PenaltyKind::NoShow => "did not show up for a match",
+    }
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    subcommands("report", "show", "forgive")
+)]
+pub(crate) async fn penalty(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Penalize a player who did not show up for their match")
+)]
+pub(crate) async fn report(
+    context: Context<'_>,
+    #[description = "Player"] user: User,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let penalty = penalize(
+        context.data().storage.as_ref(),
+        guild_id,
+        user.id.0,
+        PenaltyKind::NoShow,
+    )?;
+    let response = MessageBuilder::new()
+        .mention(&user)
+        .push(format!(
+            " was penalized and cannot join the queue until <t:{}:f>",
+            penalty.cooldown_until.timestamp()
+        ))
+        .build();
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized(
+        "en-US",
+        "Show the penalties of a player, or every player on a cooldown"
+    )
+)]
+pub(crate) async fn show(
+    context: Context<'_>,
+    #[description = "Player"] user: Option<User>,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let storage = &context.data().storage;
+    let mut response = MessageBuilder::new();
+    match user {
+        Some(user) => {
+            let penalties = storage.penalties(guild_id, user.id.0)?;
+            if penalties.is_empty() {
+                response.mention(&user).push(" has no penalties");
+            } else {
+                response.mention(&user).push_line(" penalties:");
+                for penalty in penalties {
+                    response.push_line(format!(
+                        "- <t:{}:d>: {}, cooldown until <t:{}:f>",
+                        penalty.created_at.timestamp(),
+                        describe(penalty.kind),
+                        penalty.cooldown_until.timestamp()
+                    ));
+                }
+            }
+        }
+        None => {
+            let mut penalties = storage.active_penalties(guild_id, Utc::now())?;
+            // only the cooldown ending last counts
+            let mut seen = Vec::new();
+            penalties.retain(|p| {
+                let first = !seen.contains(&p.user_id);
+                seen.push(p.user_id);
+                first
+            });
+            if penalties.is_empty() {
+                response.push("No player is on a cooldown");
+            } else {
+                response.push_line("Players on a cooldown:");
+                for penalty in penalties {
+                    response.push_line(format!(
+                        "- <@{}> until <t:{}:f> ({})",
+                        penalty.user_id,
+                        penalty.cooldown_until.timestamp(),
+                        describe(penalty.kind)
+                    ));
+                }
+            }
+        }
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Remove every penalty and cooldown of a player")
+)]
+pub(crate) async fn forgive(
+    context: Context<'_>,
+    #[description = "Player"] user: User,
+) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let removed = context
+        .data()
+        .storage
+        .forgive_penalties(guild_id, user.id.0)?;
+    let response = match removed {
+        0 => MessageBuilder::new()
+            .mention(&user)
+            .push(" has no penalties")
+            .build(),
+        removed => MessageBuilder::new()
+            .push(format!("Removed {} penalties of ", removed))
+            .mention(&user)
+            .build(),
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::SqliteStorage;
+
+    fn cooldown_minutes(penalty: &Penalty) -> i64 {
+        (penalty.cooldown_until - penalty.created_at).num_minutes()
+    }
+
+    #[test]
+    fn cooldowns_escalate_up_to_the_last_step() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let minutes: Vec<i64> = (0..COOLDOWN_MINUTES.len() + 2)
+            .map(|_| penalize(&storage, 1, 2, PenaltyKind::EarlyLeave).unwrap())
+            .map(|penalty| cooldown_minutes(&penalty))
+            .collect();
+        let last = COOLDOWN_MINUTES[COOLDOWN_MINUTES.len() - 1];
+        assert_eq!(minutes[..COOLDOWN_MINUTES.len()], COOLDOWN_MINUTES);
+        assert_eq!(minutes[COOLDOWN_MINUTES.len()..], [last, last]);
+        // other users and guilds start over
+        let other = penalize(&storage, 1, 3, PenaltyKind::NoShow).unwrap();
+        assert_eq!(cooldown_minutes(&other), COOLDOWN_MINUTES[0]);
+        let other = penalize(&storage, 4, 2, PenaltyKind::NoShow).unwrap();
+        assert_eq!(cooldown_minutes(&other), COOLDOWN_MINUTES[0]);
+    }
+
+    #[test]
+    fn old_penalties_do_not_escalate() {
+        let storage = SqliteStorage::open(":memory:").unwrap();
+        let created_at = Utc::now() - ChronoDuration::days(PENALTY_WINDOW_DAYS + 1);
+        storage
+            .add_penalty(&Penalty {
+                guild_id: 1,
+                user_id: 2,
+                kind: PenaltyKind::MissedReadyCheck,
+                created_at,
+                cooldown_until: created_at + ChronoDuration::minutes(COOLDOWN_MINUTES[0]),
+            })
+            .unwrap();
+        let penalty = penalize(&storage, 1, 2, PenaltyKind::MissedReadyCheck).unwrap();
+        assert_eq!(cooldown_minutes(&penalty), COOLDOWN_MINUTES[0]);
+        let until = cooldown(&storage, 1, 2).unwrap().unwrap();
+        // stored with second precision
+        assert_eq!(until.timestamp(), penalty.cooldown_until.timestamp());
+    }
+}
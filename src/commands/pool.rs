@@ -0,0 +1,243 @@
+use crate::storage::{MapPool, DEFAULT_MAP_POOL};
+use crate::utils::lobby;
+use crate::{Context, Lobby};
+use anyhow::Result;
+use poise::command;
+use serenity::utils::MessageBuilder;
+
+/// The pool named `name`, or the pool in use if `None`. The `default` pool is
+/// created the first time it is needed.
+pub(crate) async fn target_pool(lobby: &Lobby, name: Option<&str>) -> Result<Option<MapPool>> {
+    let name = match name {
+        Some(name) => name.trim().to_lowercase(),
+        None => match lobby.map_pool().await {
+            Some(pool) => return Ok(Some(pool)),
+            None => DEFAULT_MAP_POOL.to_string(),
+        },
+    };
+    if let Some(pool) = find_pool(lobby, &name).await {
+        return Ok(Some(pool));
+    }
+    if name != DEFAULT_MAP_POOL {
+        return Ok(None);
+    }
+    lobby.storage.create_map_pool(lobby.queue_id, &name)?;
+    lobby.reload_map_pools().await?;
+    Ok(find_pool(lobby, &name).await)
+}
+
+async fn find_pool(lobby: &Lobby, name: &str) -> Option<MapPool> {
+    lobby
+        .map_pools
+        .lock()
+        .await
+        .iter()
+        .find(|p| p.name == name)
+        .cloned()
+}
+
+/// Makes `pool_id` the only pool used automatically for `team_size`.
+async fn assign_team_size(lobby: &Lobby, pool_id: i64, team_size: Option<u8>) -> Result<()> {
+    let pools = lobby.map_pools.lock().await.clone();
+    for pool in pools {
+        if pool.id != pool_id && team_size.is_some() && pool.team_size == team_size {
+            lobby.storage.set_map_pool_team_size(pool.id, None)?;
+        }
+    }
+    lobby.storage.set_map_pool_team_size(pool_id, team_size)?;
+    lobby.reload_map_pools().await
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    subcommands("create", "delete", "list", "activate", "deactivate", "assign")
+)]
+pub(crate) async fn pool(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Create a map pool")
+)]
+pub(crate) async fn create(
+    context: Context<'_>,
+    #[description = "Pool name"] name: String,
+    #[description = "Use the pool automatically for this team size"]
+    #[min = 1]
+    #[max = 5]
+    team_size: Option<u8>,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let name = name.trim().to_lowercase();
+    if find_pool(&lobby, &name).await.is_some() {
+        context
+            .say(format!("Map pool `{}` already exists", name))
+            .await?;
+        return Ok(());
+    }
+    let pool_id = lobby.storage.create_map_pool(lobby.queue_id, &name)?;
+    lobby.reload_map_pools().await?;
+    let mut response = MessageBuilder::new();
+    response.push("Created map pool `").push(&name).push("`");
+    if let Some(team_size) = team_size {
+        assign_team_size(&lobby, pool_id, Some(team_size)).await?;
+        response.push(format!(" for {0}v{0}", team_size));
+    }
+    response
+        .push(", add maps with `/admin map add pool:")
+        .push(&name)
+        .push("`");
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Delete a map pool and its maps")
+)]
+pub(crate) async fn delete(
+    context: Context<'_>,
+    #[description = "Pool name"] name: String,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let name = name.trim().to_lowercase();
+    let Some(pool) = find_pool(&lobby, &name).await else {
+        context
+            .say(format!("Map pool `{}` does not exist", name))
+            .await?;
+        return Ok(());
+    };
+    lobby.storage.delete_map_pool(pool.id)?;
+    {
+        let mut active = lobby.active_map_pool.lock().await;
+        if active.as_deref() == Some(name.as_str()) {
+            lobby.storage.set_active_map_pool(lobby.queue_id, None)?;
+            *active = None;
+        }
+    }
+    lobby.reload_map_pools().await?;
+    context.say(format!("Deleted map pool `{}`", name)).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Show all map pools")
+)]
+pub(crate) async fn list(context: Context<'_>) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let pools = lobby.map_pools.lock().await.clone();
+    let active = lobby.active_map_pool.lock().await.clone();
+    let in_use = lobby.map_pool().await.map(|p| p.name);
+    let mut response = MessageBuilder::new();
+    if pools.is_empty() {
+        response.push("No map pools yet, `/admin map add` creates the `default` pool");
+    }
+    for pool in pools {
+        response.push("- `").push(&pool.name).push(format!(
+            "`: {} maps",
+            pool.maps.iter().filter(|m| m.enabled).count()
+        ));
+        if let Some(team_size) = pool.team_size {
+            response.push(format!(", used for {0}v{0}", team_size));
+        }
+        if active.as_deref() == Some(pool.name.as_str()) {
+            response.push(", activated");
+        }
+        if in_use.as_deref() == Some(pool.name.as_str()) {
+            response.push(" (in use)");
+        }
+        response.push_line("");
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Use a map pool regardless of the team size")
+)]
+pub(crate) async fn activate(
+    context: Context<'_>,
+    #[description = "Pool name"] name: String,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let name = name.trim().to_lowercase();
+    if find_pool(&lobby, &name).await.is_none() {
+        context
+            .say(format!("Map pool `{}` does not exist", name))
+            .await?;
+        return Ok(());
+    }
+    lobby
+        .storage
+        .set_active_map_pool(lobby.queue_id, Some(&name))?;
+    *lobby.active_map_pool.lock().await = Some(name.clone());
+    context
+        .say(format!(
+            "Map pool `{}` is used until `/admin pool deactivate`",
+            name
+        ))
+        .await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Go back to picking the map pool by team size")
+)]
+pub(crate) async fn deactivate(context: Context<'_>) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    lobby.storage.set_active_map_pool(lobby.queue_id, None)?;
+    *lobby.active_map_pool.lock().await = None;
+    let response = match lobby.map_pool().await {
+        Some(pool) => format!("Map pool `{}` is used now", pool.name),
+        None => "No map pool fits the team size, create one with `/admin pool create`".to_string(),
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Use a map pool automatically for a team size")
+)]
+pub(crate) async fn assign(
+    context: Context<'_>,
+    #[description = "Pool name"] name: String,
+    #[description = "Team size, leave empty to stop using the pool automatically"]
+    #[min = 1]
+    #[max = 5]
+    team_size: Option<u8>,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let name = name.trim().to_lowercase();
+    let Some(pool) = find_pool(&lobby, &name).await else {
+        context
+            .say(format!("Map pool `{}` does not exist", name))
+            .await?;
+        return Ok(());
+    };
+    assign_team_size(&lobby, pool.id, team_size).await?;
+    let response = match team_size {
+        Some(team_size) => format!("Map pool `{0}` is used for {1}v{1}", name, team_size),
+        None => format!("Map pool `{}` is no longer used for a team size", name),
+    };
+    context.say(response).await?;
+    Ok(())
+}
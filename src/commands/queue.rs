@@ -1,13 +1,39 @@
-use crate::{utils::write_to_file, Context, State};
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use crate::commands::panel::refresh_panel;
+use crate::commands::penalty::cooldown;
+use crate::commands::start::abandon_setup;
+use crate::storage::{QueueBan, QueueExpiry, DEFAULT_QUEUE};
+use crate::{
+    utils::{
+        join_party, leave_party, lobby, parse_duration, party_members, promote_waitlist,
+        promotion_message, remove_from_queue, remove_from_waitlist,
+    },
+    Context, Data, Lobby, State,
+};
 use anyhow::Result;
-use poise::{command, serenity_prelude::Guild};
-use serenity::utils::MessageBuilder;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use poise::{
+    command,
+    serenity_prelude::{
+        ButtonStyle, ChannelId, Context as SerenityContext, Guild, InteractionResponseType, User,
+    },
+};
+use serenity::{
+    builder::{CreateActionRow, CreateButton},
+    http::Http,
+    utils::MessageBuilder,
+};
+
+/// Longest duration accepted by `/queue join`.
+const MAX_QUEUE_HOURS: i64 = 24;
 
 #[command(
     slash_command,
     guild_only,
     ephemeral,
-    subcommands("join", "leave", "list")
+    subcommands("join", "leave", "list", "party")
 )]
 pub(crate) async fn queue(_context: Context<'_>) -> Result<()> {
     Ok(())
@@ -20,155 +46,497 @@ pub(crate) async fn queue(_context: Context<'_>) -> Result<()> {
 pub(crate) async fn join(
     context: Context<'_>,
     #[description = "Message"] message: Option<String>,
+    #[description = "How long to stay in the queue, e.g. 2h or 45m"] duration: Option<String>,
 ) -> Result<()> {
-    let steam_id_cache = &context.data().steam_id_cache.lock().await.clone();
-    if !steam_id_cache.contains_key(context.author().id.as_u64()) {
-        let response = MessageBuilder::new()
-            .push("SteamID not found for your discord user, \
-                    please use `/steamid` command to assign one. Example: `/steamid STEAM_0:1:12345678` ")
-            .push("https://steamid.io/ is an easy way to find your steamID for your account")
-            .build();
+    let guild_id = context.guild_id().unwrap().0;
+    if let Some(response) = steam_id_error(context.data(), guild_id, context.author()).await? {
         context
             .send(|m| m.ephemeral(true).content(response))
             .await?;
         return Ok(());
     }
 
-    let team_size = context.data().team_size.lock().await.clone();
-    let queue_max_size = team_size * 2;
+    let duration = match duration.as_deref().map(parse_duration) {
+        None => None,
+        Some(Some(duration)) if duration <= ChronoDuration::hours(MAX_QUEUE_HOURS) => {
+            Some(duration)
+        }
+        Some(_) => {
+            let response = format!(
+                "Duration must be like `2h`, `45m` or `1h30m` and at most {} hours",
+                MAX_QUEUE_HOURS
+            );
+            context
+                .send(|m| m.ephemeral(true).content(response))
+                .await?;
+            return Ok(());
+        }
+    };
 
-    let validation: Option<&str> = {
-        let user_queue = context.data().user_queue.lock().await;
-        match user_queue {
-            _ if user_queue.len() >= queue_max_size.into() => Some("Sorry, the queue is full"),
-            _ if user_queue.contains(context.author()) => Some("You are already in the queue"),
-            _ => None,
+    let lobby = lobby(&context).await?;
+    let expiry = duration.map(|duration| QueueExpiry {
+        queue_id: lobby.queue_id,
+        user_id: context.author().id.0,
+        channel_id: context.channel_id().0,
+        expires_at: Utc::now() + duration,
+    });
+    let joined = add_player(&lobby, context.author(), expiry.as_ref()).await?;
+    let response = join_response(&lobby, context.author(), &joined, expiry.as_ref()).await;
+    match joined {
+        Joined::AlreadyQueued
+        | Joined::AlreadyWaitlisted
+        | Joined::Cooldown(_)
+        | Joined::Banned(_) => {
+            context
+                .send(|m| m.ephemeral(true).content(response))
+                .await?;
+            return Ok(());
         }
+        Joined::Queue | Joined::Waitlist(_) => context.say(response).await?,
     };
-    if let Some(msg) = validation {
-        context
-            .send(|m| m.ephemeral(true).content(msg.to_string()))
-            .await?;
-        return Ok(());
+    if let Some(m) = message {
+        let mut end = m.len();
+        end = end.min(50);
+        let m = String::from(m[0..end].trim());
+        lobby
+            .storage
+            .set_queue_message(lobby.queue_id, *context.author().id.as_u64(), &m)?;
+        let mut queue_messages = lobby.queue_messages.lock().await;
+        queue_messages.insert(*context.author().id.as_u64(), m);
     }
+    refresh_panel(context.serenity_context(), &lobby).await;
 
-    let user_queue = {
-        let mut user_queue = context.data().user_queue.lock().await;
-        user_queue.push(context.author().clone());
-        user_queue.clone()
-    };
-    write_to_file(
-        String::from("data/queue.json"),
-        serde_json::to_string(&user_queue).unwrap(),
+    assign_queue_role(
+        context.serenity_context(),
+        context.data(),
+        lobby.guild_id,
+        context.author(),
     )
-    .await;
+    .await?;
+    Ok(())
+}
+#[command(
+    slash_command,
+    guild_only,
+    description_localized("en-US", "Leave the scrim queue")
+)]
+pub(crate) async fn leave(context: Context<'_>) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    let left = remove_player(context.serenity_context(), &lobby, context.author().id.0).await?;
+    let response = leave_response(&lobby, context.author(), &left).await;
+    match left {
+        Left::SetupStarted => context.say(response).await?,
+        Left::NotQueued => {
+            context
+                .send(|m| m.ephemeral(true).content(response))
+                .await?
+        }
+        Left::Queue | Left::Waitlist | Left::EarlyLeave(_) => {
+            context.say(response).await?;
+            refresh_panel(context.serenity_context(), &lobby).await;
+            let promoted = promote_waitlist(&lobby).await?;
+            if let Some(response) = promotion_message(&lobby, &promoted).await {
+                context.say(response).await?;
+                refresh_panel(context.serenity_context(), &lobby).await;
+            }
+            return Ok(());
+        }
+    };
+    Ok(())
+}
+
+#[command(slash_command, guild_only, subcommands("party_invite", "party_leave"))]
+pub(crate) async fn party(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    rename = "invite",
+    description_localized("en-US", "Invite a queued player to your party")
+)]
+pub(crate) async fn party_invite(
+    context: Context<'_>,
+    #[description = "Player to invite"] user: User,
+) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    if let Some(reason) = party_invite_error(&lobby, context.author(), &user).await {
+        context.send(|m| m.ephemeral(true).content(reason)).await?;
+        return Ok(());
+    }
     let response = MessageBuilder::new()
+        .mention(&user)
+        .push(", ")
         .mention(context.author())
-        .push(" has been added to the queue. Queue size: ")
-        .push(user_queue.len().to_string())
-        .push(format!("/{}", queue_max_size))
+        .push(" invited you to their party. Party members are always drafted to the same team.")
         .build();
-    context.say(response).await?;
-    let queue_messages = {
-        let queue_messages = &mut context.data().queue_messages.lock().await;
-        if let Some(m) = message {
-            let mut end = m.len();
-            end = end.min(50);
-            queue_messages.insert(
-                *context.author().id.as_u64(),
-                String::from(m[0..end].trim()),
-            );
-        }
-        queue_messages.clone()
-    };
-    write_to_file(
-        String::from("data/queue-messages.json"),
-        serde_json::to_string(&queue_messages).unwrap(),
-    )
-    .await;
-
-    let config = &context.data().config;
-    if let Some(role_id) = config.discord.assign_role_id {
-        if let Ok(value) = context
-            .author()
-            .has_role(&context, context.guild_id().unwrap(), role_id)
-            .await
-        {
-            if !value {
-                let guild = Guild::get(&context, context.guild_id().unwrap())
-                    .await
-                    .unwrap();
-                if let Ok(mut member) = guild.member(&context, &context.author().id).await {
-                    if let Err(err) = member.add_role(&context, role_id).await {
-                        eprintln!("assign_role_id exists but cannot add role to user, check bot permissions");
-                        eprintln!("{:?}", err);
-                    }
+    let reply = context
+        .send(|m| {
+            m.content(response)
+                .components(|c| c.add_action_row(create_party_invite_action_row()))
+        })
+        .await?;
+    let msg = reply.message().await?;
+    let invitee_id = user.id;
+    let mci = msg
+        .await_component_interaction(context.serenity_context())
+        .filter(move |mci| mci.user.id == invitee_id)
+        .timeout(Duration::from_secs(60 * 5))
+        .await;
+    let response = match &mci {
+        Some(mci) if mci.data.custom_id == "party_accept" => {
+            // the queue may have changed while the invite was open
+            match party_invite_error(&lobby, context.author(), &user).await {
+                Some(reason) => reason.to_string(),
+                None => {
+                    let leader_id = lobby
+                        .parties
+                        .lock()
+                        .await
+                        .get(&context.author().id.0)
+                        .copied()
+                        .unwrap_or(context.author().id.0);
+                    join_party(&lobby, leader_id, user.id.0).await?;
+                    MessageBuilder::new()
+                        .mention(&user)
+                        .push(" joined ")
+                        .mention(context.author())
+                        .push("'s party.")
+                        .build()
                 }
             }
         }
+        Some(_) => MessageBuilder::new()
+            .mention(&user)
+            .push(" declined the party invite.")
+            .build(),
+        None => "Party invite expired.".to_string(),
+    };
+    if let Some(mci) = &mci {
+        mci.create_interaction_response(context.serenity_context(), |r| {
+            r.kind(InteractionResponseType::DeferredUpdateMessage)
+        })
+        .await?;
     }
+    reply
+        .edit(context, |m| m.content(response).components(|c| c))
+        .await?;
     Ok(())
 }
+
 #[command(
     slash_command,
     guild_only,
-    description_localized("en-US", "Leave the scrim queue")
+    rename = "leave",
+    description_localized("en-US", "Leave your party")
 )]
-pub(crate) async fn leave(context: Context<'_>) -> Result<()> {
-    let state = &context.data().state.lock().await.clone();
-    if state != &State::Queue {
+pub(crate) async fn party_leave(context: Context<'_>) -> Result<()> {
+    let lobby = lobby(&context).await?;
+    if *lobby.state.lock().await != State::Queue {
         context
-            .say("Cannot `/leave` the queue after `/start`")
+            .send(|m| {
+                m.ephemeral(true)
+                    .content("Parties cannot change after `/start`")
+            })
             .await?;
         return Ok(());
     }
-    let validation = {
-        let user_queue = context.data().user_queue.lock().await;
-        match user_queue {
-            _ if !user_queue.contains(&context.author()) => {
-                Some("You are not in the queue. Use `/join` to join the queue.")
-            }
-            _ => None,
-        }
-    };
-    if let Some(msg) = validation {
-        context.send(|m| m.ephemeral(true).content(msg)).await?;
+    if !leave_party(&lobby, context.author().id.0).await? {
+        context
+            .send(|m| m.ephemeral(true).content("You are not in a party"))
+            .await?;
         return Ok(());
     }
-    let user_queue = {
-        let mut user_queue = context.data().user_queue.lock().await;
-        let index = user_queue
-            .iter()
-            .position(|r| r.id == context.author().id)
-            .unwrap();
-        user_queue.remove(index);
-        user_queue.clone()
-    };
-    write_to_file(
-        String::from("data/queue.json"),
-        serde_json::to_string(&user_queue).unwrap(),
-    )
-    .await;
-    let max_queue_size = context.data().team_size.lock().await.clone() * 2;
     let response = MessageBuilder::new()
         .mention(context.author())
-        .push(" has left the queue. Queue size: ")
-        .push(user_queue.len().to_string())
-        .push(format!("/{}", max_queue_size))
+        .push(" has left their party.")
         .build();
     context.say(response).await?;
-    let queued_msgs = {
-        let mut queued_msgs = context.data().queue_messages.lock().await;
-        if queued_msgs.get(context.author().id.as_u64()).is_some() {
-            queued_msgs.remove(context.author().id.as_u64());
+    Ok(())
+}
+
+/// Why `user` cannot join `inviter`'s party, if they cannot.
+async fn party_invite_error(lobby: &Lobby, inviter: &User, user: &User) -> Option<&'static str> {
+    if inviter.id == user.id {
+        return Some("You cannot invite yourself");
+    }
+    if *lobby.state.lock().await != State::Queue {
+        return Some("Parties cannot change after `/start`");
+    }
+    {
+        let user_queue = lobby.user_queue.lock().await;
+        let waitlist = lobby.waitlist.lock().await;
+        if !user_queue.contains(inviter) && !waitlist.contains(inviter) {
+            return Some("You must be in the queue to invite players to your party");
+        }
+        if !user_queue.contains(user) && !waitlist.contains(user) {
+            return Some("Only players in the queue can be invited to a party");
         }
-        queued_msgs.clone()
+    }
+    if lobby.parties.lock().await.contains_key(&user.id.0) {
+        return Some("That player is already in a party");
+    }
+    let party_size = party_members(lobby, inviter.id.0).await.len();
+    if party_size >= *lobby.team_size.lock().await as usize {
+        return Some("Your party is already as large as a team");
+    }
+    None
+}
+
+fn create_party_invite_action_row() -> CreateActionRow {
+    let mut ar = CreateActionRow::default();
+    let mut accept_button = CreateButton::default();
+    accept_button.custom_id("party_accept");
+    accept_button.label("Accept");
+    accept_button.style(ButtonStyle::Success);
+    let mut decline_button = CreateButton::default();
+    decline_button.custom_id("party_decline");
+    decline_button.label("Decline");
+    decline_button.style(ButtonStyle::Danger);
+    ar.add_button(accept_button);
+    ar.add_button(decline_button);
+    ar
+}
+
+/// Result of [`add_player`].
+pub(crate) enum Joined {
+    Queue,
+    /// Position on the waitlist, the queue was full.
+    Waitlist(usize),
+    AlreadyQueued,
+    AlreadyWaitlisted,
+    /// Penalized, cannot join before the end of the cooldown.
+    Cooldown(DateTime<Utc>),
+    Banned(QueueBan),
+}
+
+/// Adds `user` to the queue, or to the waitlist once the queue is full.
+pub(crate) async fn add_player(
+    lobby: &Lobby,
+    user: &User,
+    expiry: Option<&QueueExpiry>,
+) -> Result<Joined> {
+    let queue_max_size = (*lobby.team_size.lock().await * 2).into();
+    let mut user_queue = lobby.user_queue.lock().await;
+    let mut waitlist = lobby.waitlist.lock().await;
+    if user_queue.contains(user) {
+        return Ok(Joined::AlreadyQueued);
+    }
+    if waitlist.contains(user) {
+        return Ok(Joined::AlreadyWaitlisted);
+    }
+    let ban = lobby
+        .storage
+        .queue_ban(lobby.guild_id, user.id.0, Utc::now())?;
+    if let Some(ban) = ban {
+        return Ok(Joined::Banned(ban));
+    }
+    if let Some(until) = cooldown(lobby.storage.as_ref(), lobby.guild_id, user.id.0)? {
+        return Ok(Joined::Cooldown(until));
+    }
+    lobby
+        .storage
+        .set_queue_expiry(lobby.queue_id, user.id.0, expiry)?;
+    if user_queue.len() >= queue_max_size {
+        lobby.storage.add_to_waitlist(lobby.queue_id, user)?;
+        waitlist.push(user.clone());
+        return Ok(Joined::Waitlist(waitlist.len()));
+    }
+    lobby.storage.add_to_queue(lobby.queue_id, user)?;
+    user_queue.push(user.clone());
+    Ok(Joined::Queue)
+}
+
+pub(crate) async fn join_response(
+    lobby: &Lobby,
+    user: &User,
+    joined: &Joined,
+    expiry: Option<&QueueExpiry>,
+) -> String {
+    let mut response = MessageBuilder::new();
+    match joined {
+        Joined::AlreadyQueued => return "You are already in the queue".to_string(),
+        Joined::AlreadyWaitlisted => return "You are already on the waitlist".to_string(),
+        Joined::Cooldown(until) => {
+            return format!(
+                "You were penalized for dodging a match and cannot join the queue until <t:{}:f>",
+                until.timestamp()
+            )
+        }
+        Joined::Banned(ban) => return format!("You are banned from the queue{}", ban_terms(ban)),
+        Joined::Queue => {
+            let queue_size = lobby.user_queue.lock().await.len();
+            let max_queue_size = *lobby.team_size.lock().await * 2;
+            response
+                .mention(user)
+                .push(" has been added to the queue. Queue size: ")
+                .push(queue_size.to_string())
+                .push(format!("/{}", max_queue_size));
+        }
+        Joined::Waitlist(position) => {
+            response
+                .mention(user)
+                .push(" has been added to the waitlist, the queue is full. Waitlist position: ")
+                .push(position.to_string());
+        }
+    }
+    if let Some(expiry) = expiry {
+        response.push(format!(" Leaving <t:{}:R>.", expiry.expires_at.timestamp()));
+    }
+    response.build()
+}
+
+/// Describes how long `ban` lasts and why, e.g. ` until <time>. Reason: ...`.
+pub(crate) fn ban_terms(ban: &QueueBan) -> String {
+    let mut terms = match ban.expires_at {
+        Some(expires_at) => format!(" until <t:{}:f>", expires_at.timestamp()),
+        None => " permanently".to_string(),
     };
-    write_to_file(
-        String::from("data/queue-messages.json"),
-        serde_json::to_string(&queued_msgs.clone()).unwrap(),
-    )
-    .await;
+    if let Some(reason) = &ban.reason {
+        terms.push_str(&format!(". Reason: {}", reason));
+    }
+    terms
+}
+
+/// Result of [`remove_player`].
+pub(crate) enum Left {
+    Queue,
+    Waitlist,
+    /// Left after the teams were posted, penalized until the given time.
+    EarlyLeave(DateTime<Utc>),
+    /// Queued players cannot leave once `/start` was used, until the teams
+    /// are posted.
+    SetupStarted,
+    NotQueued,
+}
+
+/// Removes `user_id` from the waitlist or, unless a setup is running, the queue.
+/// Leaving after the teams were posted cancels the setup and is penalized.
+/// Promoting waitlisted players into a freed spot is up to the caller.
+pub(crate) async fn remove_player(
+    http: impl AsRef<Http>,
+    lobby: &Lobby,
+    user_id: u64,
+) -> Result<Left> {
+    if remove_from_waitlist(lobby, user_id).await? {
+        return Ok(Left::Waitlist);
+    }
+    if !lobby
+        .user_queue
+        .lock()
+        .await
+        .iter()
+        .any(|u| u.id.0 == user_id)
+    {
+        return Ok(Left::NotQueued);
+    }
+    let state = lobby.state.lock().await.clone();
+    // once a side is picked the server is being launched
+    let side_picked = !lobby.draft.lock().await.team_b_start_side.is_empty();
+    match state {
+        State::Queue => {}
+        // the teams are only final once the draft is over
+        State::MapVeto | State::SidePick if !side_picked => {
+            let penalty = abandon_setup(http, lobby, user_id).await?;
+            return Ok(Left::EarlyLeave(penalty.cooldown_until));
+        }
+        _ => return Ok(Left::SetupStarted),
+    }
+    remove_from_queue(lobby, user_id).await?;
+    Ok(Left::Queue)
+}
+
+pub(crate) async fn leave_response(lobby: &Lobby, user: &User, left: &Left) -> String {
+    match left {
+        Left::Queue => {
+            let queue_size = lobby.user_queue.lock().await.len();
+            let max_queue_size = *lobby.team_size.lock().await * 2;
+            MessageBuilder::new()
+                .mention(user)
+                .push(" has left the queue. Queue size: ")
+                .push(queue_size.to_string())
+                .push(format!("/{}", max_queue_size))
+                .build()
+        }
+        Left::Waitlist => MessageBuilder::new()
+            .mention(user)
+            .push(" has left the waitlist.")
+            .build(),
+        Left::EarlyLeave(until) => MessageBuilder::new()
+            .mention(user)
+            .push(" left after the teams were posted and the setup was canceled. ")
+            .mention(user)
+            .push(format!(
+                " cannot join the queue until <t:{}:f>.",
+                until.timestamp()
+            ))
+            .build(),
+        Left::SetupStarted => "Cannot `/leave` the queue after `/start`".to_string(),
+        Left::NotQueued => "You are not in the queue. Use `/join` to join the queue.".to_string(),
+    }
+}
+
+/// Why `user` cannot queue in the guild because of their SteamID, if they
+/// cannot: they have none, or the guild requires a verified one.
+pub(crate) async fn steam_id_error(
+    data: &Data,
+    guild_id: u64,
+    user: &User,
+) -> Result<Option<String>> {
+    let has_steam_id = data
+        .steam_id_cache
+        .lock()
+        .await
+        .contains_key(user.id.as_u64());
+    let verification_required = data
+        .guild_config(guild_id)?
+        .require_verified_steam_id
+        .unwrap_or(false);
+    if !has_steam_id && verification_required {
+        return Ok(Some(
+            "SteamID not found for your discord user, this server requires a verified one. \
+            Please use `/linksteam` to sign in through Steam."
+                .to_string(),
+        ));
+    }
+    if !has_steam_id {
+        return Ok(Some(MessageBuilder::new()
+            .push("SteamID not found for your discord user, \
+                please use `/steamid` command to assign one, e.g. with your steam profile link. Example: `/steamid https://steamcommunity.com/profiles/76561197984957085` ")
+            .push("https://steamid.io/ is an easy way to find your steamID for your account")
+            .build()));
+    }
+    if verification_required && !data.storage.steam_id_verified(user.id.0)? {
+        return Ok(Some(
+            "This server requires a verified SteamID, please use `/linksteam` to sign in through Steam."
+                .to_string(),
+        ));
+    }
+    Ok(None)
+}
+
+/// Gives `user` the configured `assign_role_id` role, if they do not have it yet.
+pub(crate) async fn assign_queue_role(
+    serenity: &SerenityContext,
+    data: &Data,
+    guild_id: u64,
+    user: &User,
+) -> Result<()> {
+    let config = data.guild_config(guild_id)?;
+    if let Some(role_id) = config.assign_role_id {
+        if let Ok(value) = user.has_role(serenity, guild_id, role_id).await {
+            if !value {
+                let guild = Guild::get(serenity, guild_id).await?;
+                if let Ok(mut member) = guild.member(serenity, &user.id).await {
+                    if let Err(err) = member.add_role(serenity, role_id).await {
+                        eprintln!("assign_role_id exists but cannot add role to user, check bot permissions");
+                        eprintln!("{:?}", err);
+                    }
+                }
+            }
+        }
+    }
     Ok(())
 }
 #[command(
@@ -178,22 +546,103 @@ pub(crate) async fn leave(context: Context<'_>) -> Result<()> {
     description_localized("en-US", "Display the queue")
 )]
 pub(crate) async fn list(context: Context<'_>) -> Result<()> {
-    let user_queue = &context.data().user_queue.lock().await.clone();
-    let queue_msgs = &context.data().queue_messages.lock().await.clone();
+    let lobby = lobby(&context).await?;
+    let user_queue = &lobby.user_queue.lock().await.clone();
+    let queue_msgs = &lobby.queue_messages.lock().await.clone();
+    let expiries = lobby.storage.queue_expiries(lobby.queue_id)?;
+    let parties = lobby.parties.lock().await.clone();
     let mut user_name = String::new();
     for u in user_queue {
         user_name.push_str(format!("\n- @{}", u.name).as_str());
         if let Some(value) = queue_msgs.get(u.id.as_u64()) {
             user_name.push_str(format!(": `{}`", value).as_str());
         }
+        if let Some(leader_id) = parties.get(u.id.as_u64()) {
+            if leader_id != u.id.as_u64() {
+                let leader = user_queue.iter().find(|l| l.id.0 == *leader_id);
+                if let Some(leader) = leader {
+                    user_name.push_str(format!(" (party of @{})", leader.name).as_str());
+                }
+            }
+        }
+        if let Some(expires_at) = expiries.get(u.id.as_u64()) {
+            user_name.push_str(format!(" (leaves <t:{}:R>)", expires_at.timestamp()).as_str());
+        }
     }
-    let max_queue_size = context.data().team_size.lock().await.clone() * 2;
-    let response = MessageBuilder::new()
+    let waitlist = lobby.waitlist.lock().await.clone();
+    if !waitlist.is_empty() {
+        user_name.push_str("\nWaitlist:");
+        for (position, u) in waitlist.iter().enumerate() {
+            user_name.push_str(format!("\n{}. @{}", position + 1, u.name).as_str());
+            if let Some(value) = queue_msgs.get(u.id.as_u64()) {
+                user_name.push_str(format!(": `{}`", value).as_str());
+            }
+        }
+    }
+    let max_queue_size = *lobby.team_size.lock().await * 2;
+    let mut response = MessageBuilder::new();
+    if lobby.name != DEFAULT_QUEUE {
+        response.push("`").push(&lobby.name).push("` ");
+    }
+    let response = response
         .push("Current queue size: ")
-        .push(&user_queue.len())
+        .push(user_queue.len())
         .push(format!("/{}", max_queue_size))
         .push(user_name)
         .build();
     context.say(response).await?;
     Ok(())
 }
+
+/// Removes timed queue and waitlist entries once they expire, posting a notice
+/// in the channel the player joined from. Runs for the lifetime of the bot.
+pub(crate) async fn expire_queue_entries(serenity: &SerenityContext, data: &Data) -> Result<()> {
+    if data.queue_expiry_started.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    loop {
+        if let Err(err) = remove_expired_entries(serenity, data).await {
+            log::error!("Unable to remove expired queue entries: {:?}", err);
+        }
+        tokio::time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+async fn remove_expired_entries(serenity: &SerenityContext, data: &Data) -> Result<()> {
+    for expiry in data.storage.expired_queue_entries(Utc::now())? {
+        let lobby = data.lobby_by_id(expiry.queue_id).await?;
+        let waitlisted = remove_from_waitlist(&lobby, expiry.user_id).await?;
+        if !waitlisted && *lobby.state.lock().await != State::Queue {
+            // the player is in a setup, try again once it is over
+            continue;
+        }
+        let queued = !waitlisted && remove_from_queue(&lobby, expiry.user_id).await?;
+        if !waitlisted && !queued {
+            // left on their own, or their match was launched
+            lobby
+                .storage
+                .set_queue_expiry(lobby.queue_id, expiry.user_id, None)?;
+            continue;
+        }
+        let channel_id = ChannelId(expiry.channel_id);
+        let mut response = MessageBuilder::new();
+        response.push(format!("<@{}>", expiry.user_id));
+        if waitlisted {
+            response.push("'s waitlist entry expired.");
+        } else {
+            let queue_size = lobby.user_queue.lock().await.len();
+            let max_queue_size = *lobby.team_size.lock().await * 2;
+            response
+                .push("'s queue entry expired. Queue size: ")
+                .push(queue_size)
+                .push(format!("/{}", max_queue_size));
+        }
+        channel_id.say(serenity, response.build()).await?;
+        let promoted = promote_waitlist(&lobby).await?;
+        if let Some(response) = promotion_message(&lobby, &promoted).await {
+            channel_id.say(serenity, response).await?;
+        }
+        refresh_panel(serenity, &lobby).await;
+    }
+    Ok(())
+}
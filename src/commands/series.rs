@@ -0,0 +1,417 @@
+use crate::commands::start::{launch_series_map, map_entry, team_a_won, team_names};
+use crate::dathost::DathostClient;
+use crate::storage::Series;
+use crate::{Context, Data};
+use anyhow::Result;
+use futures::lock::OwnedMutexGuard;
+use poise::command;
+use poise::serenity_prelude::{ChannelId, Context as SerenityContext};
+use reqwest::StatusCode;
+use serenity::utils::MessageBuilder;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+/// Maps a team has to win to take a best of three.
+const MAPS_TO_WIN: u32 = 2;
+const SERIES_CHANGED: &str =
+    "The series changed in the meantime, check `/admin series list` and try again";
+
+#[derive(poise::ChoiceParameter, Clone, Copy)]
+pub enum SeriesTeam {
+    #[name = "Team A"]
+    A,
+    #[name = "Team B"]
+    B,
+}
+
+/// How often the running series are checked for finished maps. DatHost's
+/// match end webhook usually ends them first if `webhook` is configured.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const WEBHOOK_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+/// Checks the running series regularly and launches the next map once
+/// DatHost reports the current one as finished.
+pub(crate) async fn watch_series(serenity: &SerenityContext, data: &Data) -> Result<()> {
+    if data.series_watch_started.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let interval = match data.config.webhook.is_some() {
+        true => WEBHOOK_CHECK_INTERVAL,
+        false => CHECK_INTERVAL,
+    };
+    loop {
+        if let Err(err) = check_series(serenity, data).await {
+            log::error!("Unable to check series: {:?}", err);
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+async fn check_series(serenity: &SerenityContext, data: &Data) -> Result<()> {
+    let running: Vec<Series> = data
+        .storage
+        .active_series()?
+        .into_iter()
+        .filter(|s| s.dathost_match_id.is_some())
+        .collect();
+    if running.is_empty() {
+        return Ok(());
+    }
+    let client = DathostClient::new(&data.config).await?;
+    for series in running {
+        check_map(serenity, data, &client, series).await?;
+    }
+    Ok(())
+}
+
+/// Called by the match end webhook, ends the map if `match_id` is played in
+/// a best of three.
+pub(crate) async fn match_ended(
+    serenity: &SerenityContext,
+    data: &Data,
+    match_id: &str,
+) -> Result<()> {
+    let Some(series) = data
+        .storage
+        .active_series()?
+        .into_iter()
+        .find(|s| s.dathost_match_id.as_deref() == Some(match_id))
+    else {
+        return Ok(());
+    };
+    let client = DathostClient::new(&data.config).await?;
+    check_map(serenity, data, &client, series).await
+}
+
+/// Ends the running map of `series` if DatHost reports it as finished.
+async fn check_map(
+    serenity: &SerenityContext,
+    data: &Data,
+    client: &DathostClient,
+    series: Series,
+) -> Result<()> {
+    let Some(match_id) = series.dathost_match_id.clone() else {
+        return Ok(());
+    };
+    let winner = match client.get_match(&match_id).await {
+        Ok(info) if !info.finished => return Ok(()),
+        Ok(info) => team_a_won(&series.draft, &info),
+        // the match is gone, its result has to be entered by hand
+        Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => None,
+        Err(err) => {
+            log::warn!(
+                "Unable to check match {} of series {}: {}",
+                match_id,
+                series.id,
+                err
+            );
+            return Ok(());
+        }
+    };
+    let winner = winner.map(|team_a| match team_a {
+        true => SeriesTeam::A,
+        false => SeriesTeam::B,
+    });
+    let _guard = lock_series(data, series.id).await;
+    // the webhook, the watcher or `/admin series advance` may have been first
+    let Some(series) = reload_series(data, &series)?
+        .filter(|s| s.dathost_match_id.as_deref() == Some(match_id.as_str()))
+    else {
+        return Ok(());
+    };
+    end_map(serenity, data, series, winner).await
+}
+
+/// Held while a result is recorded or a map is launched, so the watcher and
+/// the admin commands cannot act on the same map twice.
+async fn lock_series(data: &Data, series_id: i64) -> OwnedMutexGuard<()> {
+    let lock = data
+        .series_locks
+        .lock()
+        .await
+        .entry(series_id)
+        .or_default()
+        .clone();
+    lock.lock_owned().await
+}
+
+/// `series` as stored once its lock is held, `None` if it ended or moved on to
+/// another map in the meantime.
+fn reload_series(data: &Data, series: &Series) -> Result<Option<Series>> {
+    Ok(data.storage.active_series()?.into_iter().find(|s| {
+        s.id == series.id
+            && s.score_a == series.score_a
+            && s.score_b == series.score_b
+            && s.maps_launched == series.maps_launched
+    }))
+}
+
+/// Records the result of the running map and launches the next one, or ends
+/// the series once a team won two maps. Without a winner the series waits for
+/// `/admin series advance`.
+async fn end_map(
+    serenity: &SerenityContext,
+    data: &Data,
+    mut series: Series,
+    winner: Option<SeriesTeam>,
+) -> Result<()> {
+    let index = (series.score_a + series.score_b) as usize;
+    let map = current_map(data, &series).await?;
+    let (team_a, team_b) = team_names(data, &series.draft).await;
+    let channel_id = ChannelId(series.channel_id);
+    series.dathost_match_id = None;
+    let Some(winner) = winner else {
+        data.storage.update_series(&series)?;
+        let response = format!(
+            "Map {} `{}` of {} vs {} ended without a clear winner. An admin can enter the result with `/admin series advance`",
+            index + 1,
+            map,
+            team_a,
+            team_b
+        );
+        channel_id.say(serenity, response).await?;
+        return Ok(());
+    };
+    let winner_name = match winner {
+        SeriesTeam::A => {
+            series.score_a += 1;
+            &team_a
+        }
+        SeriesTeam::B => {
+            series.score_b += 1;
+            &team_b
+        }
+    };
+    let mut response = MessageBuilder::new();
+    response
+        .push_line(format!(
+            "**{}** won map {} `{}`",
+            winner_name,
+            index + 1,
+            map
+        ))
+        .push_line(format!(
+            "Series score: {} {} - {} {}",
+            team_a, series.score_a, series.score_b, team_b
+        ));
+    if series.score_a >= MAPS_TO_WIN || series.score_b >= MAPS_TO_WIN {
+        series.finished = true;
+        response.push_line(format!("**{}** wins the best of three", winner_name));
+    }
+    data.storage.update_series(&series)?;
+    channel_id.say(serenity, response.build()).await?;
+    if series.finished {
+        return Ok(());
+    }
+    launch_next_map(serenity, data, &mut series).await
+}
+
+async fn launch_next_map(
+    serenity: &SerenityContext,
+    data: &Data,
+    series: &mut Series,
+) -> Result<()> {
+    let launched = launch_series_map(serenity, data, series).await?;
+    data.storage.update_series(series)?;
+    if !launched {
+        let response = format!(
+            "Map {} could not be launched, an admin can retry with `/admin series launch` once a game server is free",
+            series.maps_launched + 1
+        );
+        ChannelId(series.channel_id).say(serenity, response).await?;
+    }
+    Ok(())
+}
+
+/// Display name of the map played next, or being played.
+async fn current_map(data: &Data, series: &Series) -> Result<String> {
+    let index = (series.score_a + series.score_b) as usize;
+    let Some(map) = series.draft.series_maps.get(index) else {
+        return Ok(String::from("?"));
+    };
+    let lobby = data.lobby_by_id(series.queue_id).await?;
+    Ok(map_entry(&lobby, map).await.display_name)
+}
+
+/// Whether the map after the last result is running.
+fn map_running(series: &Series) -> bool {
+    series.maps_launched > series.score_a + series.score_b
+}
+
+/// The running series `series_id`, or the only running series of the guild.
+/// Tells the admin what is wrong if there is none.
+async fn target_series(context: &Context<'_>, series_id: Option<i64>) -> Result<Option<Series>> {
+    let guild_id = context.guild_id().unwrap().0;
+    let mut running: Vec<Series> = context
+        .data()
+        .storage
+        .active_series()?
+        .into_iter()
+        .filter(|s| s.guild_id == guild_id)
+        .collect();
+    let count = running.len();
+    let series = match series_id {
+        Some(series_id) => running.into_iter().find(|s| s.id == series_id),
+        None if count == 1 => running.pop(),
+        None => None,
+    };
+    if series.is_none() {
+        let response = match (series_id, count) {
+            (Some(series_id), _) => format!("Series `{}` is not running", series_id),
+            (None, 0) => "No best of three is running".to_string(),
+            (None, _) => {
+                "Several series are running, pick one with the `series` option, see `/admin series list`"
+                    .to_string()
+            }
+        };
+        context.say(response).await?;
+    }
+    Ok(series)
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    subcommands("list", "advance", "launch", "cancel")
+)]
+pub(crate) async fn series(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Show the best of three series that are running")
+)]
+pub(crate) async fn list(context: Context<'_>) -> Result<()> {
+    let guild_id = context.guild_id().unwrap().0;
+    let running: Vec<Series> = context
+        .data()
+        .storage
+        .active_series()?
+        .into_iter()
+        .filter(|s| s.guild_id == guild_id)
+        .collect();
+    let mut response = MessageBuilder::new();
+    if running.is_empty() {
+        response.push("No best of three is running");
+    }
+    for series in running {
+        let (team_a, team_b) = team_names(context.data(), &series.draft).await;
+        let map = current_map(context.data(), &series).await?;
+        let status = match (map_running(&series), &series.dathost_match_id) {
+            (true, Some(_)) => "running",
+            (true, None) => "waiting for `/admin series advance`",
+            (false, _) => "waiting for `/admin series launch`",
+        };
+        response.push_line(format!(
+            "- `{}`: {} {} - {} {}, map {} `{}` {}",
+            series.id,
+            team_a,
+            series.score_a,
+            series.score_b,
+            team_b,
+            series.score_a + series.score_b + 1,
+            map,
+            status
+        ));
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Enter the winner of the running map of a best of three")
+)]
+pub(crate) async fn advance(
+    context: Context<'_>,
+    #[description = "Team that won the map"] winner: SeriesTeam,
+    #[description = "Series id, needed if several are running"] series: Option<i64>,
+) -> Result<()> {
+    let Some(series) = target_series(&context, series).await? else {
+        return Ok(());
+    };
+    let _guard = lock_series(context.data(), series.id).await;
+    let Some(series) = reload_series(context.data(), &series)? else {
+        context.say(SERIES_CHANGED).await?;
+        return Ok(());
+    };
+    if !map_running(&series) {
+        context
+            .say("The next map is not launched yet, use `/admin series launch`")
+            .await?;
+        return Ok(());
+    }
+    context.say("Result entered").await?;
+    end_map(
+        context.serenity_context(),
+        context.data(),
+        series,
+        Some(winner),
+    )
+    .await
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Launch the next map of a best of three")
+)]
+pub(crate) async fn launch(
+    context: Context<'_>,
+    #[description = "Series id, needed if several are running"] series: Option<i64>,
+) -> Result<()> {
+    let Some(series) = target_series(&context, series).await? else {
+        return Ok(());
+    };
+    let _guard = lock_series(context.data(), series.id).await;
+    let Some(mut series) = reload_series(context.data(), &series)? else {
+        context.say(SERIES_CHANGED).await?;
+        return Ok(());
+    };
+    if map_running(&series) {
+        context
+            .say("A map of this series is running, enter its result with `/admin series advance`")
+            .await?;
+        return Ok(());
+    }
+    context.say("Launching the next map").await?;
+    launch_next_map(context.serenity_context(), context.data(), &mut series).await
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "End a best of three without a winner")
+)]
+pub(crate) async fn cancel(
+    context: Context<'_>,
+    #[description = "Series id, needed if several are running"] series: Option<i64>,
+) -> Result<()> {
+    let Some(series) = target_series(&context, series).await? else {
+        return Ok(());
+    };
+    let _guard = lock_series(context.data(), series.id).await;
+    let Some(mut series) = reload_series(context.data(), &series)? else {
+        context.say(SERIES_CHANGED).await?;
+        return Ok(());
+    };
+    series.finished = true;
+    series.dathost_match_id = None;
+    context.data().storage.update_series(&series)?;
+    let (team_a, team_b) = team_names(context.data(), &series.draft).await;
+    let response = format!(
+        "The best of three {} vs {} was canceled at {} - {}",
+        team_a, team_b, series.score_a, series.score_b
+    );
+    ChannelId(series.channel_id).say(context, &response).await?;
+    context.say(response).await?;
+    Ok(())
+}
@@ -1,45 +1,94 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
+use crate::commands::maps::cooling_down;
+use crate::commands::panel::refresh_panel;
+use crate::commands::penalty::penalize;
 use crate::dathost::DathostClient;
-use crate::utils::clear_queue;
+use crate::storage::{MapEntry, MatchPlayer, MatchRecord, MatchRecordTeam, Series, SetupSession};
+use crate::storage::{Penalty, PenaltyKind};
+use crate::utils::{clear_queue, leave_party, party_members, promotion_message, remove_from_queue};
 use crate::{
-    utils::{get_api_client, list_teams, reset_draft, user_in_queue, Stats},
-    Context, State,
+    utils::{get_api_client, list_teams, lobby, reset_draft, steam_id_2, user_in_queue, Stats},
+    webhook, Config, Context, Data, Draft, Lobby, State, VetoBan,
 };
 use anyhow::{anyhow, Result};
+use chrono::{Duration as ChronoDuration, Utc};
 use poise::{
     command,
-    serenity_prelude::{ButtonStyle, InteractionResponseType, ReactionType, User},
+    serenity_prelude::{
+        ButtonStyle, ChannelId, GuildId, InteractionResponseType, Message, MessageId, ReactionType,
+        User,
+    },
 };
 use rand::Rng;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use serenity::{
     builder::{CreateActionRow, CreateButton, CreateSelectMenu, CreateSelectMenuOption},
-    futures::StreamExt,
-    model::application::interaction::message_component::MessageComponentInteraction,
+    cache::Cache,
+    client::Context as SerenityContext,
+    collector::{ComponentInteractionCollector, ComponentInteractionCollectorBuilder},
+    futures::{future::join_all, StreamExt},
+    http::{CacheHttp, Http},
+    model::application::{
+        component::ActionRowComponent, interaction::message_component::MessageComponentInteraction,
+    },
+    model::channel::MessageFlags,
     utils::MessageBuilder,
 };
-use steamid::{AccountType, Instance, SteamId, Universe};
 
-trait ParseWithDefaults: Sized {
-    fn parse<S: AsRef<str>>(value: S) -> Result<Self>;
-}
+/// How long a captain has to ban a map before one is banned at random.
+const VETO_TURN_TIME: Duration = Duration::from_secs(30);
+/// Captains take turns in this order unless `veto_order` is set.
+const DEFAULT_VETO_ORDER: &str = "AB";
+/// Custom id prefix of the veto buttons, followed by the map name.
+const VETO_PREFIX: &str = "veto:";
+/// Custom id prefix of the veto selects used when the maps do not fit in
+/// buttons, followed by the select index.
+const VETO_SELECT_PREFIX: &str = "veto_select:";
+/// Discord select menus take at most 25 options.
+const MAX_SELECT_OPTIONS: usize = 25;
+/// Messages take at most 5 action rows, the map vote keeps one for its buttons.
+const MAP_VOTE_ROWS: usize = 4;
+/// Custom id prefix of the map vote selects, followed by `:` and the select
+/// index.
+const MAP_SELECT_PREFIX: &str = "map_select";
+/// Selects per page once a list of maps needs more than the 5 selects of a
+/// message, the last row then holds the page buttons.
+const PAGE_SELECTS: usize = 4;
+/// Custom id prefixes of the page buttons of the map vote, the veto and the
+/// runoff, followed by the page the button shows.
+const MAP_PAGE_PREFIX: &str = "map_page:";
+const VETO_PAGE_PREFIX: &str = "veto_page:";
+const RUNOFF_PAGE_PREFIX: &str = "runoff_page:";
+/// Custom id prefix of the runoff selects, followed by the select index.
+const RUNOFF_SELECT_PREFIX: &str = "runoff_select:";
+/// How many maps a player can rank in a ranked map vote.
+const MAX_RANKS: usize = 3;
+/// Custom id prefix of the ranked vote selects, followed by the rank index.
+const RANK_PREFIX: &str = "map_rank:";
+/// How long the runoff between maps tied in the vote lasts.
+const RUNOFF_TIME: Duration = Duration::from_secs(30);
+/// Stored for a busy server whose match id DatHost did not return. The server
+/// stays busy until an admin releases it with `/admin server release`.
+pub(crate) const UNKNOWN_MATCH_ID: &str = "unknown";
+/// Stored for a server reserved by a launch until DatHost returns its match id.
+pub(crate) const STARTING_MATCH_ID: &str = "starting";
 
-impl ParseWithDefaults for SteamId {
-    fn parse<S: AsRef<str>>(value: S) -> Result<Self> {
-        let mut steamid =
-            SteamId::parse_steam2id(value, AccountType::Individual, Instance::Desktop)?;
-        steamid.set_universe(Universe::Public);
-        Ok(steamid)
-    }
-}
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MatchTeam {
     name: String,
 }
 #[derive(Serialize, Deserialize, Debug)]
 pub struct MatchSettings {
-    map: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    map: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    workshop_map_id: Option<String>,
     password: String,
     connect_time: i32,
     match_begin_countdown: i32,
@@ -59,6 +108,35 @@ pub struct StartMatch {
     webhooks: MatchWebhooks,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StartMatchResponse {
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchInfoResponse {
+    pub id: String,
+    pub finished: bool,
+    #[serde(default)]
+    pub cancel_reason: Option<String>,
+    #[serde(default)]
+    pub team1: Option<MatchInfoTeam>,
+    #[serde(default)]
+    pub team2: Option<MatchInfoTeam>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchInfoTeam {
+    #[serde(default)]
+    pub stats: Option<MatchTeamStats>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MatchTeamStats {
+    #[serde(default)]
+    pub score: Option<u32>,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ServerInfoResponse {
     pub game: Option<String>,
@@ -67,6 +145,10 @@ pub struct ServerInfoResponse {
     pub ports: Ports,
     pub location: Option<String>,
     pub custom_domain: Option<String>,
+    #[serde(default)]
+    pub booting: bool,
+    #[serde(default)]
+    pub players_online: u32,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ports {
@@ -78,11 +160,11 @@ enum Team {
     Team1,
     Team2,
 }
-impl Team {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Team {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
-            Team::Team1 => "team1".to_string(),
-            Team::Team2 => "team2".to_string(),
+            Team::Team1 => write!(f, "team1"),
+            Team::Team2 => write!(f, "team2"),
         }
     }
 }
@@ -91,19 +173,84 @@ struct Player {
     pub steam_id_64: String,
     pub team: String,
 }
+/// Everything the setup flow needs to drive the setup message. It does not
+/// borrow the `/start` invocation, so a persisted session can be resumed after a
+/// restart.
+pub(crate) struct SetupContext<'a> {
+    serenity: &'a SerenityContext,
+    data: &'a Data,
+    lobby: Arc<Lobby>,
+    channel_id: ChannelId,
+    message_id: MessageId,
+    started_by: Option<u64>,
+}
+
+impl SetupContext<'_> {
+    fn data(&self) -> &Data {
+        self.data
+    }
+
+    fn collect_interactions(&self, timeout: Duration) -> ComponentInteractionCollector {
+        ComponentInteractionCollectorBuilder::new(self.serenity)
+            .message_id(self.message_id)
+            .timeout(timeout)
+            .build()
+    }
+
+    /// Like [`Self::collect_interactions`], but also collects the pages of
+    /// maps shown to single players, see [`show_player_page`]. Only their
+    /// components with a custom id accepted by `page_id` are collected.
+    fn collect_paged_interactions(
+        &self,
+        timeout: Duration,
+        page_id: fn(&str) -> bool,
+    ) -> ComponentInteractionCollector {
+        let message_id = self.message_id;
+        ComponentInteractionCollectorBuilder::new(self.serenity)
+            .channel_id(self.channel_id)
+            .filter(move |mci| {
+                mci.message.id == message_id
+                    || (mci
+                        .message
+                        .flags
+                        .is_some_and(|flags| flags.contains(MessageFlags::EPHEMERAL))
+                        && page_id(&mci.data.custom_id))
+            })
+            .timeout(timeout)
+            .build()
+    }
+}
+
+impl AsRef<Http> for SetupContext<'_> {
+    fn as_ref(&self) -> &Http {
+        &self.serenity.http
+    }
+}
+
+impl CacheHttp for SetupContext<'_> {
+    fn http(&self) -> &Http {
+        &self.serenity.http
+    }
+
+    fn cache(&self) -> Option<&Arc<Cache>> {
+        Some(&self.serenity.cache)
+    }
+}
+
 #[command(
     slash_command,
     guild_only,
     description_localized("en-US", "Start scrim setup")
 )]
 pub(crate) async fn start(context: Context<'_>) -> Result<()> {
-    let in_queue = user_in_queue(&context, None).await?;
+    let in_queue = user_in_queue(&context).await?;
     if !in_queue {
         return Ok(());
     }
 
-    let queue = context.data().user_queue.lock().await.clone();
-    let team_size = context.data().team_size.lock().await.clone();
+    let lobby = lobby(&context).await?;
+    let queue = lobby.user_queue.lock().await.clone();
+    let team_size = *lobby.team_size.lock().await;
     let max_queue_size = (team_size * 2).into();
     if queue.len() < max_queue_size {
         context
@@ -113,7 +260,7 @@ pub(crate) async fn start(context: Context<'_>) -> Result<()> {
     }
 
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = lobby.state.lock().await;
         if *state != State::Queue {
             context
                 .send(|m| m.ephemeral(true).content("Setup has already started"))
@@ -122,7 +269,7 @@ pub(crate) async fn start(context: Context<'_>) -> Result<()> {
         }
         *state = State::Ready;
     }
-    let content = list_ready(&context).await?;
+    let content = list_ready(&lobby).await?;
 
     let msg = context
         .send(|m| {
@@ -131,116 +278,690 @@ pub(crate) async fn start(context: Context<'_>) -> Result<()> {
         })
         .await?;
 
-    let mut cib = msg
-        .message()
-        .await?
-        .await_component_interactions(&context)
-        .timeout(Duration::from_secs(60 * 3))
-        .build();
+    let setup = SetupContext {
+        serenity: context.serenity_context(),
+        data: context.data(),
+        lobby,
+        channel_id: context.channel_id(),
+        message_id: msg.message().await?.id,
+        started_by: Some(context.author().id.0),
+    };
+    save_session(&setup).await?;
+    run_setup(&setup).await
+}
+
+/// Picks up the setup sessions that were interrupted by a restart, continuing
+/// each from the phase it was persisted in on its original setup message.
+pub(crate) async fn resume_setups(serenity: &SerenityContext, data: &Data) -> Result<()> {
+    let sessions = data.storage.setup_sessions()?;
+    let results = join_all(
+        sessions
+            .into_iter()
+            .map(|session| resume_setup(serenity, data, session)),
+    )
+    .await;
+    for result in results {
+        if let Err(err) = result {
+            log::error!("Unable to resume setup session: {:?}", err);
+        }
+    }
+    Ok(())
+}
+
+async fn resume_setup(
+    serenity: &SerenityContext,
+    data: &Data,
+    session: SetupSession,
+) -> Result<()> {
+    let lobby = data.lobby_by_id(session.queue_id).await?;
+    {
+        let mut state = lobby.state.lock().await;
+        if *state != State::Queue {
+            // already running, e.g. a `Ready` event after a gateway reconnect
+            return Ok(());
+        }
+        *state = session.state;
+    }
+    *lobby.draft.lock().await = session.draft;
+    *lobby.ready_queue.lock().await = session.ready_queue;
+    let setup = SetupContext {
+        serenity,
+        data,
+        lobby,
+        channel_id: ChannelId(session.channel_id),
+        message_id: MessageId(session.message_id),
+        started_by: session.started_by,
+    };
+    log::info!(
+        "Resuming setup session on message {} in channel {}",
+        setup.message_id,
+        setup.channel_id
+    );
+    run_setup(&setup).await
+}
+
+/// Cancels the running setup of `lobby` because `user_id` left after the teams
+/// were posted. The player is removed from the queue and penalized.
+pub(crate) async fn abandon_setup(
+    http: impl AsRef<Http>,
+    lobby: &Lobby,
+    user_id: u64,
+) -> Result<Penalty> {
+    let session = lobby
+        .storage
+        .setup_sessions()?
+        .into_iter()
+        .find(|s| s.queue_id == lobby.queue_id);
+    reset_draft(lobby).await?;
+    remove_from_queue(lobby, user_id).await?;
+    let penalty = penalize(
+        lobby.storage.as_ref(),
+        lobby.guild_id,
+        user_id,
+        PenaltyKind::EarlyLeave,
+    )?;
+    if let Some(session) = session {
+        let response = MessageBuilder::new()
+            .push(format!("<@{}>", user_id))
+            .push(" left after the teams were posted, setup canceled. ")
+            .push("Use `/start` once the queue is full again.")
+            .build();
+        ChannelId(session.channel_id)
+            .edit_message(&http, MessageId(session.message_id), |m| {
+                m.content(response).components(|c| c)
+            })
+            .await?;
+    }
+    Ok(penalty)
+}
+
+/// Whether the setup was canceled while waiting for input, e.g. with
+/// `/admin setup cancel` or by a player leaving.
+fn setup_canceled(context: &SetupContext<'_>) -> Result<bool> {
+    Ok(!context
+        .data()
+        .storage
+        .setup_sessions()?
+        .iter()
+        .any(|s| s.message_id == context.message_id.0))
+}
+
+async fn save_session(context: &SetupContext<'_>) -> Result<()> {
+    let session = SetupSession {
+        guild_id: context.lobby.guild_id,
+        queue_id: context.lobby.queue_id,
+        channel_id: context.channel_id.0,
+        message_id: context.message_id.0,
+        state: context.lobby.state.lock().await.clone(),
+        draft: context.lobby.draft.lock().await.clone(),
+        ready_queue: context.lobby.ready_queue.lock().await.clone(),
+        started_by: context.started_by,
+    };
+    context.data().storage.save_setup_session(&session)
+}
+
+async fn run_setup(context: &SetupContext<'_>) -> Result<()> {
+    refresh_panel(context, &context.lobby).await;
+    let result = run_setup_phases(context).await;
+    refresh_panel(context, &context.lobby).await;
+    result
+}
+
+async fn run_setup_phases(context: &SetupContext<'_>) -> Result<()> {
+    if *context.lobby.state.lock().await == State::Ready {
+        let completed = run_ready_check(context).await?;
+        if !completed || setup_canceled(context)? {
+            return Ok(());
+        }
+        let config = context.data().guild_config(context.lobby.guild_id)?;
+        let best_of_three = config.best_of_three.unwrap_or(false);
+        if best_of_three || config.map_veto.unwrap_or(false) {
+            {
+                let mut state = context.lobby.state.lock().await;
+                *state = State::DraftTypePick;
+            }
+            let content = match best_of_three {
+                true => "The captains ban and pick the maps of the best of three once the teams are set.\n\nSelect draft option:",
+                false => "The captains ban maps once the teams are set.\n\nSelect draft option:",
+            };
+            context
+                .channel_id
+                .edit_message(context, context.message_id, |m| {
+                    m.content(content)
+                        .components(|c| c.add_action_row(create_draft_type_action_row()))
+                })
+                .await?;
+        } else {
+            {
+                let mut state = context.lobby.state.lock().await;
+                *state = State::MapPick;
+            }
+            let content = map_vote_content(context).await?;
+            let rows = map_vote_rows(context).await?;
+            context
+                .channel_id
+                .edit_message(context, context.message_id, |f| {
+                    f.content(content).components(|c| c.set_action_rows(rows))
+                })
+                .await?;
+        }
+        save_session(context).await?;
+    }
+    if *context.lobby.state.lock().await == State::MapPick {
+        let completed = run_map_vote(context).await?;
+        if !completed {
+            return Ok(());
+        }
+    }
+    let completed = run_draft(context).await?;
+    if !completed {
+        return Ok(());
+    }
+
+    start_server(context).await?;
+
+    Ok(())
+}
+
+async fn run_ready_check(context: &SetupContext<'_>) -> Result<bool> {
+    let mut cib = context.collect_interactions(Duration::from_secs(60 * 3));
+    // players re-added to the queue during the ready check are not penalized twice
+    let mut penalized: Vec<u64> = Vec::new();
     loop {
         let opt = cib.next().await;
         match opt {
             Some(mci) => {
-                let completed = handle_ready(&context, &mci).await?;
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+                if !mci_user_in_queue(context, &mci).await? {
+                    continue;
+                }
+                let completed = handle_ready(context, &mci).await?;
+                save_session(context).await?;
                 if completed {
-                    break;
+                    return Ok(true);
                 }
             }
             None => {
-                msg.edit(context, |m| {
-                    m.content("Start process timed out. Start again when all users are present using `/start`")
-                        .components(|c| c)
-                }).await?;
-                reset_draft(&context).await?;
-                return Ok(());
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+                let ready_queue = context.lobby.ready_queue.lock().await.clone();
+                let unready: Vec<User> = context
+                    .lobby
+                    .user_queue
+                    .lock()
+                    .await
+                    .iter()
+                    .filter(|u| !ready_queue.contains(u))
+                    .cloned()
+                    .collect();
+                for user in unready {
+                    if penalized.contains(&user.id.0) {
+                        continue;
+                    }
+                    penalized.push(user.id.0);
+                    penalize(
+                        context.data().storage.as_ref(),
+                        context.lobby.guild_id,
+                        user.id.0,
+                        PenaltyKind::MissedReadyCheck,
+                    )?;
+                }
+                let replaced = replace_unready(context).await?;
+                let dropped = drop_unready(context).await?;
+                let mut response = MessageBuilder::new();
+                for (user, replacement) in &replaced {
+                    response
+                        .mention(user)
+                        .push(" did not ready up and was replaced by ")
+                        .mention(replacement)
+                        .push_line(" from the waitlist.");
+                }
+                for user in &dropped {
+                    response
+                        .mention(user)
+                        .push_line(" did not ready up and was removed from the queue.");
+                }
+                response.push_line("Players who do not ready up in time get a queue cooldown.");
+                if dropped.is_empty() {
+                    response.push("Please ready up above.");
+                } else {
+                    let config = context.data().guild_config(context.lobby.guild_id)?;
+                    if let Some(role_id) = config.assign_role_id {
+                        response.role(role_id).push(" ");
+                    }
+                    response.push(format!(
+                        "{} open spot(s) in the queue, use `/queue join` to fill in. ",
+                        dropped.len()
+                    ));
+                    response.push("The ready check continues once the queue is full.");
+                }
+                context
+                    .channel_id
+                    .send_message(context, |m| m.content(response.build()))
+                    .await?;
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+                save_session(context).await?;
+                refresh_panel(context, &context.lobby).await;
+                if !dropped.is_empty() && !wait_for_refill(context).await? {
+                    if setup_canceled(context)? {
+                        return Ok(false);
+                    }
+                    context.channel_id.edit_message(context, context.message_id, |m| {
+                        m.content("Start process timed out, the queue was not refilled. Start again when all users are present using `/start`")
+                            .components(|c| c)
+                    }).await?;
+                    reset_draft(&context.lobby).await?;
+                    return Ok(false);
+                }
+                let content = list_ready(&context.lobby).await?;
+                context
+                    .channel_id
+                    .edit_message(context, context.message_id, |m| m.content(content))
+                    .await?;
+                let ready_count = context.lobby.ready_queue.lock().await.len();
+                if ready_count == context.lobby.user_queue.lock().await.len() {
+                    return Ok(true);
+                }
+                cib = context.collect_interactions(Duration::from_secs(60 * 3));
             }
         }
     }
-    {
-        let mut state = context.data().state.lock().await;
-        *state = State::MapPick;
-    }
-    let map_list = context.data().maps.lock().await.clone();
-    msg.edit(context, |f| {
-        f.content("Map vote phase: vote for 1 or more maps")
-            .components(|c| {
-                c.add_action_row(create_map_action_row(map_list.clone()))
-                    .add_action_row(create_map_vote_action_row())
-            })
-    })
-    .await?;
+}
 
-    let mut cib = msg
-        .message()
-        .await?
-        .await_component_interactions(&context)
-        .timeout(Duration::from_secs(60))
-        .build();
+/// Removes the players who are still not ready once the waitlist ran out,
+/// their spots are filled through `/queue join`.
+async fn drop_unready(context: &SetupContext<'_>) -> Result<Vec<User>> {
+    let ready_queue = context.lobby.ready_queue.lock().await.clone();
+    let unready: Vec<User> = context
+        .lobby
+        .user_queue
+        .lock()
+        .await
+        .iter()
+        .filter(|u| !ready_queue.contains(u))
+        .cloned()
+        .collect();
+    for user in &unready {
+        remove_from_queue(&context.lobby, user.id.0).await?;
+    }
+    Ok(unready)
+}
+
+/// Waits for new players to fill the spots of dropped ones, while players keep
+/// readying up. Returns `false` if the queue was not full in time.
+async fn wait_for_refill(context: &SetupContext<'_>) -> Result<bool> {
+    let mut cib = context.collect_interactions(Duration::from_secs(60 * 10));
+    let mut shown = None;
+    loop {
+        let queue_len = context.lobby.user_queue.lock().await.len();
+        let max_queue_size: usize = (*context.lobby.team_size.lock().await * 2).into();
+        if queue_len >= max_queue_size {
+            return Ok(true);
+        }
+        if shown != Some(queue_len) {
+            // list new players so they can ready up right away
+            let content = MessageBuilder::new()
+                .push(list_ready(&context.lobby).await?)
+                .push(format!(
+                    "Waiting for {} more player(s) to join the queue...",
+                    max_queue_size - queue_len
+                ))
+                .build();
+            context
+                .channel_id
+                .edit_message(context, context.message_id, |m| m.content(content))
+                .await?;
+            shown = Some(queue_len);
+        }
+        match tokio::time::timeout(Duration::from_secs(5), cib.next()).await {
+            Ok(Some(mci)) => {
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+                if !mci_user_in_queue(context, &mci).await? {
+                    continue;
+                }
+                handle_ready(context, &mci).await?;
+                save_session(context).await?;
+                // the response replaced the waiting note
+                shown = None;
+            }
+            Ok(None) => return Ok(false),
+            Err(_) => {
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+            }
+        }
+    }
+}
+
+/// Returns `false` if the setup was canceled during the vote.
+async fn run_map_vote(context: &SetupContext<'_>) -> Result<bool> {
+    let mut cib = context.collect_paged_interactions(Duration::from_secs(60), map_vote_id);
     loop {
+        if everyone_voted(context).await {
+            break;
+        }
         let opt = cib.next().await;
         match opt {
             Some(mci) => {
-                handle_map_pick(&context, &mci).await?;
+                if setup_canceled(context)? {
+                    return Ok(false);
+                }
+                if !handle_map_pick(context, &mci).await? {
+                    continue;
+                }
+                save_session(context).await?;
+                let content = map_vote_content(context).await?;
+                context
+                    .channel_id
+                    .edit_message(context, context.message_id, |m| m.content(content))
+                    .await?;
             }
             None => {
                 break;
             }
         }
     }
-    let selected_map = calc_selected_map(&context).await?;
+    if setup_canceled(context)? {
+        return Ok(false);
+    }
+    let (selected_map, tally) = calc_selected_map(context).await?;
     {
-        let mut draft = context.data().draft.lock().await;
-        draft.selected_map = selected_map.clone();
+        let mut draft = context.lobby.draft.lock().await;
+        draft.selected_map = selected_map.name.clone();
     };
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = context.lobby.state.lock().await;
         *state = State::DraftTypePick;
     }
-    msg.edit(context, |m| {
-        m.components(|c| c.add_action_row(create_draft_type_action_row()))
-            .content(format!(
-                "Map vote has concluded. `{}` will be played.\n\nSelect draft option:",
-                selected_map
-            ))
+    context
+        .channel_id
+        .edit_message(context, context.message_id, |m| {
+            if let Some(thumbnail_url) = &selected_map.thumbnail_url {
+                m.embed(|e| e.title(&selected_map.display_name).image(thumbnail_url));
+            }
+            m.components(|c| c.add_action_row(create_draft_type_action_row()))
+                .content(format!(
+                    "Map vote has concluded. `{}` will be played.\n\n{}\nSelect draft option:",
+                    selected_map.display_name, tally
+                ))
+        })
+        .await?;
+    save_session(context).await?;
+    Ok(true)
+}
+
+/// The part of the map vote shown in the setup message.
+struct MapVotePage {
+    maps: Vec<MapEntry>,
+    cooling: Vec<MapEntry>,
+    ranked: bool,
+    /// Position of the first shown map among all playable maps.
+    first: usize,
+    total: usize,
+    page: usize,
+    pages: usize,
+}
+
+/// Page `page` of the map vote. Pools that do not fit in the selects of one
+/// message are split into pages, a ranked vote needs a select per rank and
+/// shows one select worth of maps per page.
+async fn map_vote_page(context: &SetupContext<'_>, page: usize) -> Result<MapVotePage> {
+    let ranked = context
+        .data()
+        .guild_config(context.lobby.guild_id)?
+        .ranked_map_vote
+        .unwrap_or(false);
+    let (map_list, cooling) = playable_maps(context).await?;
+    let page_size = match ranked {
+        true => MAX_SELECT_OPTIONS,
+        false => MAX_SELECT_OPTIONS * MAP_VOTE_ROWS,
+    };
+    let pages = map_list.len().div_ceil(page_size).max(1);
+    let page = page.min(pages - 1);
+    let maps = map_list
+        .chunks(page_size)
+        .nth(page)
+        .unwrap_or_default()
+        .to_vec();
+    Ok(MapVotePage {
+        maps,
+        cooling,
+        ranked,
+        first: page * page_size,
+        total: map_list.len(),
+        page,
+        pages,
     })
-    .await?;
-    let mut cib = msg
-        .message()
-        .await?
-        .await_component_interactions(&context)
-        .timeout(Duration::from_secs(60 * 10))
-        .build();
-    while let Some(mci) = cib.next().await {
-        let in_queue = user_in_queue(&context, Some(&mci)).await?;
-        if !in_queue {
-            return Ok(());
+}
+
+/// The selects of the first page followed by the Vote button. The other pages
+/// are shown to each player on their own, see [`handle_map_page`].
+async fn map_vote_rows(context: &SetupContext<'_>) -> Result<Vec<CreateActionRow>> {
+    let page = map_vote_page(context, 0).await?;
+    let mut rows = create_map_action_row(page.maps, &page.cooling, page.ranked);
+    rows.push(create_map_vote_action_row(page.page, page.pages));
+    Ok(rows)
+}
+
+/// How to vote, the votes so far and who still has to press Vote.
+async fn map_vote_content(context: &SetupContext<'_>) -> Result<String> {
+    let page = map_vote_page(context, 0).await?;
+    let ranked = page.ranked;
+    let maps = context.lobby.maps().await;
+    let draft = context.lobby.draft.lock().await.clone();
+    let queue = context.lobby.user_queue.lock().await.clone();
+    let mut content = MessageBuilder::new();
+    let outcome = match ranked {
+        true => {
+            content.push_line(format!(
+                "Map vote phase: rank up to {} maps, the least popular maps are eliminated until one has a majority",
+                MAX_RANKS.min(page.total)
+            ));
+            // only first choices are shown until the vote closes
+            let firsts: HashMap<u64, Vec<String>> = draft
+                .map_votes
+                .iter()
+                .map(|(user_id, ranks)| {
+                    let first = ranks.iter().find(|m| !m.is_empty()).cloned();
+                    (*user_id, first.into_iter().collect())
+                })
+                .collect();
+            tally_votes(&firsts, &maps, "First choices")
         }
-        let state = context.data().state.lock().await.clone();
-        match state {
-            State::CaptainPick => handle_captain_pick(&context, &mci).await?,
-            State::DraftTypePick => handle_draft_type(&context, &mci).await?,
-            State::Draft => handle_draft(&context, &mci).await?,
-            State::SidePick => {
-                let completed = handle_sidepick(&context, &mci).await?;
-                if completed {
-                    break;
+        false => {
+            content.push_line("Map vote phase: vote for 1 or more maps");
+            tally_votes(&draft.map_votes, &maps, "Votes")
+        }
+    };
+    if page.pages > 1 {
+        content.push_line(format!(
+            "Showing maps {}-{} of {}, `Next maps` shows you the others. Picks on every page count",
+            page.first + 1,
+            page.first + page.maps.len(),
+            page.total
+        ));
+    }
+    if !outcome.tally.is_empty() {
+        content.push_line("").push(outcome.tally);
+    }
+    let waiting: Vec<&User> = queue
+        .iter()
+        .filter(|u| !draft.map_voted.contains(&u.id.0))
+        .collect();
+    if !waiting.is_empty() {
+        content.push("Waiting for: ");
+        for (i, user) in waiting.iter().enumerate() {
+            if i > 0 {
+                content.push(", ");
+            }
+            content.mention(*user);
+        }
+    }
+    Ok(content.build())
+}
+
+/// Whether every queued player pressed Vote.
+async fn everyone_voted(context: &SetupContext<'_>) -> bool {
+    let voted = context.lobby.draft.lock().await.map_voted.clone();
+    context
+        .lobby
+        .user_queue
+        .lock()
+        .await
+        .iter()
+        .all(|u| voted.contains(&u.id.0))
+}
+
+async fn run_draft(context: &SetupContext<'_>) -> Result<bool> {
+    {
+        let state = context.lobby.state.lock().await.clone();
+        let draft = context.lobby.draft.lock().await.clone();
+        if state == State::SidePick && !draft.team_b_start_side.is_empty() {
+            // sides were picked before a restart, only the launch is missing
+            return Ok(true);
+        }
+    }
+    let mut cib = context.collect_interactions(Duration::from_secs(60 * 10));
+    // number of bans and start of the current veto turn
+    let mut veto_turn = (usize::MAX, Instant::now());
+    loop {
+        let vetoing = *context.lobby.state.lock().await == State::MapVeto;
+        let next = match vetoing {
+            true => {
+                let bans = context.lobby.draft.lock().await.veto.len();
+                if bans != veto_turn.0 {
+                    veto_turn = (bans, Instant::now());
+                }
+                let left = VETO_TURN_TIME.saturating_sub(veto_turn.1.elapsed());
+                match tokio::time::timeout(left, cib.next()).await {
+                    Ok(next) => next,
+                    Err(_) => {
+                        if setup_canceled(context)? {
+                            return Ok(false);
+                        }
+                        auto_ban(context).await?;
+                        save_session(context).await?;
+                        continue;
+                    }
                 }
             }
+            false => cib.next().await,
+        };
+        let Some(mci) = next else {
+            break;
+        };
+        if setup_canceled(context)? {
+            return Ok(false);
+        }
+        let in_queue = mci_user_in_queue(context, &mci).await?;
+        if !in_queue {
+            continue;
+        }
+        let state = context.lobby.state.lock().await.clone();
+        let mut completed = false;
+        match state {
+            State::CaptainPick => handle_captain_pick(context, &mci).await?,
+            State::DraftTypePick => handle_draft_type(context, &mci).await?,
+            State::Draft => handle_draft(context, &mci).await?,
+            State::MapVeto => handle_veto(context, &mci).await?,
+            State::SidePick => completed = handle_sidepick(context, &mci).await?,
             _ => return Err(anyhow!("Something went wrong")),
         };
+        save_session(context).await?;
+        if completed {
+            return Ok(true);
+        }
+    }
+    if setup_canceled(context)? {
+        return Ok(false);
     }
+    context
+        .channel_id
+        .edit_message(context, context.message_id, |m| {
+            m.content("Setup timed out. Use `/start` to start over.")
+                .components(|c| c)
+        })
+        .await?;
+    reset_draft(&context.lobby).await?;
+    Ok(false)
+}
 
-    start_server(&context).await?;
+async fn mci_user_in_queue(
+    context: &SetupContext<'_>,
+    mci: &MessageComponentInteraction,
+) -> Result<bool> {
+    let queue = context.lobby.user_queue.lock().await.clone();
+    if !queue.iter().any(|u| u.id == mci.user.id) {
+        mci.create_interaction_response(context, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.ephemeral(true).content("You are not in the queue")
+                })
+        })
+        .await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
 
-    Ok(())
+/// Swaps players who did not ready up for players from the front of the
+/// waitlist, as long as there are any. Returns the `(removed, replacement)`
+/// pairs.
+async fn replace_unready(context: &SetupContext<'_>) -> Result<Vec<(User, User)>> {
+    let lobby = &context.lobby;
+    let ready_queue = lobby.ready_queue.lock().await.clone();
+    let mut user_queue = lobby.user_queue.lock().await;
+    let mut waitlist = lobby.waitlist.lock().await;
+    let unready: Vec<User> = user_queue
+        .iter()
+        .filter(|u| !ready_queue.contains(u))
+        .cloned()
+        .collect();
+    let mut replaced = Vec::new();
+    for user in unready {
+        if waitlist.is_empty() {
+            break;
+        }
+        let replacement = waitlist.remove(0);
+        lobby.storage.remove_from_queue(lobby.queue_id, user.id.0)?;
+        lobby
+            .storage
+            .remove_queue_message(lobby.queue_id, user.id.0)?;
+        lobby
+            .storage
+            .set_queue_expiry(lobby.queue_id, user.id.0, None)?;
+        leave_party(lobby, user.id.0).await?;
+        lobby
+            .storage
+            .remove_from_waitlist(lobby.queue_id, replacement.id.0)?;
+        lobby.storage.add_to_queue(lobby.queue_id, &replacement)?;
+        user_queue.retain(|u| u.id != user.id);
+        user_queue.push(replacement.clone());
+        lobby.queue_messages.lock().await.remove(user.id.as_u64());
+        replaced.push((user, replacement));
+    }
+    Ok(replaced)
 }
 
-async fn handle_ready(context: &Context<'_>, mci: &MessageComponentInteraction) -> Result<bool> {
+async fn handle_ready(
+    context: &SetupContext<'_>,
+    mci: &MessageComponentInteraction,
+) -> Result<bool> {
     {
-        let mut queue = context.data().ready_queue.lock().await;
+        let mut queue = context.lobby.ready_queue.lock().await;
         match mci.data.custom_id.as_str() {
             "ready" => {
-                queue.push(mci.user.clone());
+                if !queue.contains(&mci.user) {
+                    queue.push(mci.user.clone());
+                }
             }
             "unready" => {
                 if let Some(pos) = queue.iter().position(|u| u.id == mci.user.id) {
@@ -250,23 +971,23 @@ async fn handle_ready(context: &Context<'_>, mci: &MessageComponentInteraction)
             _ => return Err(anyhow!("Unable to parse ready button response")),
         };
     };
-    let content = list_ready(context).await?;
+    let content = list_ready(&context.lobby).await?;
     mci.create_interaction_response(&context, |r| {
         r.kind(InteractionResponseType::UpdateMessage)
             .interaction_response_data(|d| d.content(content))
     })
     .await?;
-    let ready_queue = context.data().ready_queue.lock().await.clone();
-    let user_queue = context.data().user_queue.lock().await.clone();
+    let ready_queue = context.lobby.ready_queue.lock().await.clone();
+    let user_queue = context.lobby.user_queue.lock().await.clone();
     if ready_queue.len() != user_queue.len() {
         return Ok(false);
     }
     Ok(true)
 }
 
-async fn list_ready(context: &Context<'_>) -> Result<String> {
-    let ready_queue = context.data().ready_queue.lock().await.clone();
-    let queue = context.data().user_queue.lock().await.clone();
+async fn list_ready(lobby: &Lobby) -> Result<String> {
+    let ready_queue = lobby.ready_queue.lock().await.clone();
+    let queue = lobby.user_queue.lock().await.clone();
     let ready_list: String = queue
         .into_iter()
         .map(|u| {
@@ -280,9 +1001,13 @@ async fn list_ready(context: &Context<'_>) -> Result<String> {
         .build())
 }
 
-async fn handle_sidepick(context: &Context<'_>, mci: &MessageComponentInteraction) -> Result<bool> {
-    let draft = context.data().draft.lock().await.clone();
-    if mci.user != draft.captain_b.unwrap() {
+async fn handle_sidepick(
+    context: &SetupContext<'_>,
+    mci: &MessageComponentInteraction,
+) -> Result<bool> {
+    let draft = context.lobby.draft.lock().await.clone();
+    let chooser = side_chooser(&draft)?;
+    if mci.user != chooser {
         mci.create_interaction_response(context, |m| {
             m.interaction_response_data(|d| {
                 d.ephemeral(true)
@@ -293,27 +1018,103 @@ async fn handle_sidepick(context: &Context<'_>, mci: &MessageComponentInteractio
         return Ok(false);
     }
     let option = &mci.data.custom_id;
-    {
-        let mut draft = context.data().draft.lock().await;
-        draft.team_b_start_side = option.clone();
+    // sides are stored as team B's starting side
+    let side = match (Some(&chooser) == draft.captain_a.as_ref(), option.as_str()) {
+        (true, "ct") => "t".to_string(),
+        (true, _) => "ct".to_string(),
+        (false, _) => option.clone(),
+    };
+    if draft.series_maps.is_empty() {
+        {
+            let mut draft = context.lobby.draft.lock().await;
+            draft.team_b_start_side = side;
+        };
+        mci.create_interaction_response(&context, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d.components(|c| c))
+        })
+        .await?;
+        return Ok(true);
+    }
+    let draft = {
+        let mut draft = context.lobby.draft.lock().await;
+        draft.series_sides.push(side);
+        if draft.series_sides.len() == draft.series_maps.len() {
+            draft.selected_map = draft.series_maps[0].clone();
+            draft.team_b_start_side = draft.series_sides[0].clone();
+        }
+        draft.clone()
     };
+    let summary = series_summary(context, &draft).await;
+    if draft.team_b_start_side.is_empty() {
+        show_sidepick(context, Some(mci), summary).await?;
+        return Ok(false);
+    }
     mci.create_interaction_response(&context, |r| {
         r.kind(InteractionResponseType::UpdateMessage)
-            .interaction_response_data(|d| d.components(|c| c))
+            .interaction_response_data(|d| d.content(summary).components(|c| c))
     })
     .await?;
     Ok(true)
 }
 
-async fn handle_draft(context: &Context<'_>, mci: &MessageComponentInteraction) -> Result<()> {
-    let draft = context.data().draft.lock().await.clone();
+/// The captain picking the starting side on the next map: the captain who did
+/// not pick it in a best of three, otherwise captain B.
+fn side_chooser(draft: &Draft) -> Result<User> {
+    let picked_by = draft
+        .series_maps
+        .get(draft.series_sides.len())
+        .and_then(|map| draft.veto.iter().find(|ban| ban.pick && ban.map == *map))
+        .map(|ban| ban.captain.clone());
+    let chooser = match picked_by {
+        Some(captain) if Some(&captain) == draft.captain_b.as_ref() => draft.captain_a.clone(),
+        _ => draft.captain_b.clone(),
+    };
+    chooser.ok_or_else(|| anyhow!("Side pick without captains"))
+}
+
+/// The teams and the maps of a best of three, with the starting sides picked
+/// so far.
+async fn series_summary(context: &SetupContext<'_>, draft: &Draft) -> String {
+    let (_, team_b) = team_names(context.data(), draft).await;
+    let names = context.data().team_names.lock().await.clone();
+    let mut summary = MessageBuilder::new();
+    summary
+        .push_line(list_teams(draft, &names))
+        .push_line("Best of three:");
+    for (index, map) in draft.series_maps.iter().enumerate() {
+        let map = map_entry(&context.lobby, map).await;
+        summary.push(format!("{}. `{}`", index + 1, map.display_name));
+        if let Some(side) = draft.series_sides.get(index) {
+            summary.push(format!(", {} starts on {}", team_b, side.to_uppercase()));
+        }
+        summary.push_line("");
+    }
+    summary.build()
+}
+
+/// Names of team A and team B: the captains' team names, or `Team <captain>`.
+pub(crate) async fn team_names(data: &Data, draft: &Draft) -> (String, String) {
+    let names = data.team_names.lock().await.clone();
+    let name = |captain: &Option<User>| match captain {
+        Some(captain) => names
+            .get(captain.id.as_u64())
+            .cloned()
+            .unwrap_or_else(|| format!("Team {}", captain.name)),
+        None => String::new(),
+    };
+    (name(&draft.captain_a), name(&draft.captain_b))
+}
+
+async fn handle_draft(context: &SetupContext<'_>, mci: &MessageComponentInteraction) -> Result<()> {
+    let draft = context.lobby.draft.lock().await.clone();
     if draft.current_picker.is_none() {
         {
-            let mut draft = context.data().draft.lock().await;
+            let mut draft = context.lobby.draft.lock().await;
             draft.current_picker = draft.captain_a.clone();
         }
     }
-    if mci.user.id != draft.current_picker.unwrap().id {
+    if mci.user.id != draft.current_picker.as_ref().unwrap().id {
         mci.create_interaction_response(context, |m| {
             m.interaction_response_data(|d| {
                 d.ephemeral(true)
@@ -323,42 +1124,58 @@ async fn handle_draft(context: &Context<'_>, mci: &MessageComponentInteraction)
         .await?;
         return Ok(());
     }
-    let user_id = mci.data.values.get(0).unwrap();
+    let user_id = mci.data.values.first().unwrap();
     let user_id = user_id.parse::<u64>()?;
-    let queue = context.data().user_queue.lock().await.clone();
-    let user = queue.iter().find(|u| u.id.0 == user_id).unwrap().clone();
-    let action_msg = MessageBuilder::new()
-        .mention(&mci.user)
-        .push(" picked ")
-        .mention(&user)
-        .build();
-    let draft = {
-        let mut draft = context.data().draft.lock().await;
-        if draft.captain_a.as_ref().unwrap().id == mci.user.id {
-            draft.team_a.push(user);
-            draft.current_picker = draft.captain_b.clone();
-        } else {
-            draft.team_b.push(user);
-            draft.current_picker = draft.captain_a.clone();
+    let is_team_a = draft.captain_a.as_ref().unwrap().id == mci.user.id;
+    let capacity = team_capacity(context).await;
+    let units = remaining_units(context, &draft).await;
+    let (slots, other_slots) = team_slots(&draft, capacity, is_team_a);
+    let Some(unit) = pickable_units(&units, slots, other_slots)
+        .into_iter()
+        .find(|unit| unit.iter().any(|u| u.id.0 == user_id))
+    else {
+        mci.create_interaction_response(context, |m| {
+            m.interaction_response_data(|d| {
+                d.ephemeral(true)
+                    .content("That party does not fit on your team anymore")
+            })
+        })
+        .await?;
+        return Ok(());
+    };
+    let picked: Vec<String> = unit.iter().map(|u| u.to_string()).collect();
+    let action_msg = MessageBuilder::new()
+        .mention(&mci.user)
+        .push(" picked ")
+        .push(picked.join(" + "))
+        .build();
+    let units: Vec<Vec<User>> = units.into_iter().filter(|u| *u != unit).collect();
+    let draft = {
+        let mut draft = context.lobby.draft.lock().await;
+        if is_team_a {
+            draft.team_a.extend(unit);
+        } else {
+            draft.team_b.extend(unit);
         }
+        draft.current_picker = next_picker(&draft, &units, capacity, !is_team_a);
         draft.clone()
     };
     let team_names = context.data().team_names.lock().await.clone();
-    if draft.team_a.len() + draft.team_b.len() != queue.len() {
+    if !units.is_empty() {
         let resp = MessageBuilder::new()
             .push_line(action_msg)
             .push_line("")
             .push_line(list_teams(&draft, &team_names))
             .push("It is ")
-            .mention(&draft.current_picker.unwrap())
+            .mention(draft.current_picker.as_ref().unwrap())
             .push(" turn to pick:")
             .build();
-        let remaining_users = get_remaining_users(context).await?;
+        let options = pick_options(&draft, &units, capacity);
         mci.create_interaction_response(&context, |r| {
             r.kind(InteractionResponseType::UpdateMessage)
                 .interaction_response_data(|d| {
                     d.content(resp)
-                        .components(|c| c.add_action_row(create_user_action_row(remaining_users)))
+                        .components(|c| c.add_action_row(create_user_action_row(options)))
                 })
         })
         .await?;
@@ -369,90 +1186,818 @@ async fn handle_draft(context: &Context<'_>, mci: &MessageComponentInteraction)
     Ok(())
 }
 
+/// Moves on once the teams are set: to the side pick, or to the map veto if
+/// no map was voted for.
 async fn init_sidepick_state(
-    context: &Context<'_>,
+    context: &SetupContext<'_>,
     mci: &MessageComponentInteraction,
     msg_prefix: Option<String>,
+) -> Result<()> {
+    if context.lobby.draft.lock().await.selected_map.is_empty() {
+        return show_veto(context, Some(mci)).await;
+    }
+    show_sidepick(context, Some(mci), msg_prefix.unwrap_or_default()).await
+}
+
+async fn show_sidepick(
+    context: &SetupContext<'_>,
+    mci: Option<&MessageComponentInteraction>,
+    msg_prefix: String,
 ) -> Result<()> {
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = context.lobby.state.lock().await;
         *state = State::SidePick;
     }
-    let draft = context.data().draft.lock().await.clone();
-    let resp = MessageBuilder::new()
-        .push_line(msg_prefix.unwrap_or(String::new()))
-        .push_line("")
-        .mention(&draft.captain_b.unwrap())
+    let draft = context.lobby.draft.lock().await.clone();
+    let mut resp = MessageBuilder::new();
+    resp.push_line(msg_prefix).push_line("");
+    let index = draft.series_sides.len();
+    let map = match draft.series_maps.get(index) {
+        Some(map) => {
+            resp.push(format!(
+                "Map {} of {}: ",
+                index + 1,
+                draft.series_maps.len()
+            ));
+            map_entry(&context.lobby, map).await
+        }
+        None => selected_map(&context.lobby, &draft).await,
+    };
+    resp.mention(&side_chooser(&draft)?)
         .push(" select starting side on `")
-        .push(draft.selected_map)
-        .push("`")
-        .build();
-    mci.create_interaction_response(&context, |r| {
-        r.kind(InteractionResponseType::UpdateMessage)
-            .interaction_response_data(|d| {
-                d.content(resp)
-                    .components(|c| c.add_action_row(create_sidepick_action_row()))
+        .push(map.display_name)
+        .push("`");
+    update_setup_message(
+        context,
+        mci,
+        resp.build(),
+        vec![create_sidepick_action_row()],
+    )
+    .await
+}
+
+/// Shows `content` and `rows` on the setup message, as the response to `mci`
+/// if an interaction caused the change.
+async fn update_setup_message(
+    context: &SetupContext<'_>,
+    mci: Option<&MessageComponentInteraction>,
+    content: String,
+    rows: Vec<CreateActionRow>,
+) -> Result<()> {
+    match mci {
+        Some(mci) => {
+            mci.create_interaction_response(&context, |r| {
+                r.kind(InteractionResponseType::UpdateMessage)
+                    .interaction_response_data(|d| {
+                        d.content(content).components(|c| c.set_action_rows(rows))
+                    })
             })
-    })
-    .await?;
+            .await?
+        }
+        None => {
+            context
+                .channel_id
+                .edit_message(context, context.message_id, |m| {
+                    m.content(content).components(|c| c.set_action_rows(rows))
+                })
+                .await?;
+        }
+    }
     Ok(())
 }
 
-async fn get_remaining_users(context: &Context<'_>) -> Result<Vec<User>> {
-    let draft = context.data().draft.lock().await.clone();
-    let remaining_users: Vec<User> = context
+/// Enabled maps of the pool in use, split into the ones that can be played and
+/// the ones cooling down. If every map is cooling down, all of them can be
+/// played.
+async fn playable_maps(context: &SetupContext<'_>) -> Result<(Vec<MapEntry>, Vec<MapEntry>)> {
+    let cooling = cooling_down(context.data(), context.lobby.guild_id)?;
+    let (cooling, playable): (Vec<MapEntry>, Vec<MapEntry>) = context
+        .lobby
+        .maps()
+        .await
+        .into_iter()
+        .filter(|m| m.enabled)
+        .partition(|m| cooling.contains(&m.name.to_lowercase()));
+    if playable.is_empty() {
+        return Ok((cooling, Vec::new()));
+    }
+    Ok((playable, cooling))
+}
+
+/// Playable maps that are neither banned nor picked yet, in pool order.
+async fn veto_remaining(context: &SetupContext<'_>, draft: &Draft) -> Result<Vec<MapEntry>> {
+    let (mut maps, _) = playable_maps(context).await?;
+    maps.retain(|m| !draft.veto.iter().any(|ban| ban.map == m.name));
+    Ok(maps)
+}
+
+/// Whether the veto builds a best of three. Needs the `best_of_three` setting
+/// and at least three maps.
+fn series_veto(context: &SetupContext<'_>, draft: &Draft, remaining: usize) -> Result<bool> {
+    let best_of_three = context
         .data()
-        .user_queue
-        .lock()
+        .guild_config(context.lobby.guild_id)?
+        .best_of_three
+        .unwrap_or(false);
+    Ok(best_of_three && remaining + draft.veto.len() >= 3)
+}
+
+/// Whether the current turn of a best of three veto picks a map. Captains ban
+/// until five maps are left, pick one map each, then ban until the decider is
+/// left.
+fn veto_turn_picks(draft: &Draft, remaining: usize) -> bool {
+    let picks = draft.veto.iter().filter(|ban| ban.pick).count();
+    let bans = draft.veto.len() - picks;
+    let pool = remaining + draft.veto.len();
+    picks < 2 && bans >= pool.saturating_sub(5)
+}
+
+/// The captain whose turn it is to ban, following the `veto_order` setting.
+fn veto_captain(context: &SetupContext<'_>, draft: &Draft) -> Result<User> {
+    let order = context
+        .data()
+        .guild_config(context.lobby.guild_id)?
+        .veto_order;
+    veto_captain_in_order(order.as_deref(), draft)
+}
+
+/// The captain whose turn it is in `order`, [`DEFAULT_VETO_ORDER`] if unset.
+fn veto_captain_in_order(order: Option<&str>, draft: &Draft) -> Result<User> {
+    let order = order
+        .filter(|order| !order.is_empty())
+        .unwrap_or(DEFAULT_VETO_ORDER);
+    let turn = order.as_bytes()[draft.veto.len() % order.len()];
+    let captain = match turn.to_ascii_uppercase() {
+        b'B' => draft.captain_b.clone(),
+        _ => draft.captain_a.clone(),
+    };
+    captain.ok_or_else(|| anyhow!("Map veto without captains"))
+}
+
+/// Shows whose turn it is to ban or pick, or moves on to the side pick once a
+/// single map is left.
+async fn show_veto(
+    context: &SetupContext<'_>,
+    mci: Option<&MessageComponentInteraction>,
+) -> Result<()> {
+    let draft = context.lobby.draft.lock().await.clone();
+    let remaining = veto_remaining(context, &draft).await?;
+    let series = series_veto(context, &draft, remaining.len())?;
+    let team_names = context.data().team_names.lock().await.clone();
+    let mut resp = MessageBuilder::new();
+    resp.push_line(list_teams(&draft, &team_names));
+    if !draft.veto.is_empty() {
+        resp.push_line(veto_summary(context, &draft).await);
+    }
+    if remaining.len() <= 1 {
+        let Some(map) = remaining.into_iter().next() else {
+            return Err(anyhow!("No maps left after the map veto"));
+        };
+        {
+            let mut draft = context.lobby.draft.lock().await;
+            if series {
+                let mut maps: Vec<String> = draft
+                    .veto
+                    .iter()
+                    .filter(|ban| ban.pick)
+                    .map(|ban| ban.map.clone())
+                    .collect();
+                maps.push(map.name.clone());
+                draft.selected_map = maps[0].clone();
+                draft.series_maps = maps;
+            } else {
+                draft.selected_map = map.name.clone();
+            }
+        }
+        match series {
+            true => resp.push(format!("`{}` is the decider", map.display_name)),
+            false => resp.push(format!("`{}` will be played", map.display_name)),
+        };
+        return show_sidepick(context, mci, resp.build()).await;
+    }
+    {
+        let mut state = context.lobby.state.lock().await;
+        *state = State::MapVeto;
+    }
+    let captain = veto_captain(context, &draft)?;
+    let pick = series && veto_turn_picks(&draft, remaining.len());
+    let deadline = Utc::now() + ChronoDuration::from_std(VETO_TURN_TIME)?;
+    let turn = match pick {
+        true => "pick a map, a random one is picked",
+        false => "ban a map, a random one is banned",
+    };
+    resp.mention(&captain)
+        .push(format!(" {} <t:{}:R>", turn, deadline.timestamp()));
+    update_setup_message(
+        context,
+        mci,
+        resp.build(),
+        create_veto_action_rows(&remaining, pick, 0),
+    )
+    .await
+}
+
+async fn veto_summary(context: &SetupContext<'_>, draft: &Draft) -> String {
+    let maps = context.lobby.maps().await;
+    let mut summary = MessageBuilder::new();
+    summary.push_line("Map veto:");
+    for (turn, ban) in draft.veto.iter().enumerate() {
+        let name = maps
+            .iter()
+            .find(|m| m.name == ban.map)
+            .map_or(ban.map.as_str(), |m| m.display_name.as_str());
+        let action = if ban.pick { "picked" } else { "banned" };
+        summary
+            .push(format!("{}. ", turn + 1))
+            .mention(&ban.captain)
+            .push(format!(" {} `{}`", action, name));
+        if ban.auto {
+            summary.push(" (out of time)");
+        }
+        summary.push_line("");
+    }
+    summary.build()
+}
+
+async fn handle_veto(context: &SetupContext<'_>, mci: &MessageComponentInteraction) -> Result<()> {
+    let draft = context.lobby.draft.lock().await.clone();
+    let captain = veto_captain(context, &draft)?;
+    if mci.user.id != captain.id {
+        mci.create_interaction_response(context, |m| {
+            m.interaction_response_data(|d| {
+                d.ephemeral(true)
+                    .content("It is not your turn in the map veto")
+            })
+        })
+        .await?;
+        return Ok(());
+    }
+    let remaining = veto_remaining(context, &draft).await?;
+    let pick =
+        series_veto(context, &draft, remaining.len())? && veto_turn_picks(&draft, remaining.len());
+    if let Some(page) = mci.data.custom_id.strip_prefix(VETO_PAGE_PREFIX) {
+        // only the captain on turn can page, so the page can be shared
+        let rows = create_veto_action_rows(&remaining, pick, page.parse()?);
+        mci.create_interaction_response(context, |r| {
+            r.kind(InteractionResponseType::UpdateMessage)
+                .interaction_response_data(|d| d.components(|c| c.set_action_rows(rows)))
+        })
+        .await?;
+        return Ok(());
+    }
+    let map_name = match mci.data.custom_id.strip_prefix(VETO_PREFIX) {
+        Some(map_name) => map_name,
+        None if mci.data.custom_id.starts_with(VETO_SELECT_PREFIX) => {
+            match mci.data.values.first() {
+                Some(map_name) => map_name.as_str(),
+                None => return Ok(()),
+            }
+        }
+        None => return Ok(()),
+    };
+    // select values are lowercased, buttons carry the name as is
+    let Some(map) = remaining
+        .iter()
+        .find(|m| m.name.eq_ignore_ascii_case(map_name))
+    else {
+        mci.create_interaction_response(context, |m| {
+            m.interaction_response_data(|d| {
+                d.ephemeral(true)
+                    .content("That map is already banned or picked")
+            })
+        })
+        .await?;
+        return Ok(());
+    };
+    context.lobby.draft.lock().await.veto.push(VetoBan {
+        map: map.name.clone(),
+        captain,
+        auto: false,
+        pick,
+    });
+    show_veto(context, Some(mci)).await
+}
+
+/// Bans, or picks, a random map for the captain who ran out of time.
+async fn auto_ban(context: &SetupContext<'_>) -> Result<()> {
+    let draft = context.lobby.draft.lock().await.clone();
+    let captain = veto_captain(context, &draft)?;
+    let remaining = veto_remaining(context, &draft).await?;
+    if remaining.is_empty() {
+        return Err(anyhow!("No maps left after the map veto"));
+    }
+    let pick =
+        series_veto(context, &draft, remaining.len())? && veto_turn_picks(&draft, remaining.len());
+    let map = &remaining[rand::thread_rng().gen_range(0..remaining.len())];
+    context.lobby.draft.lock().await.veto.push(VetoBan {
+        map: map.name.clone(),
+        captain,
+        auto: true,
+        pick,
+    });
+    show_veto(context, None).await
+}
+
+/// A button per map, or selects of up to 25 maps each once the maps do not
+/// fit in the 25 buttons of a message, showing page `page` of them.
+fn create_veto_action_rows(maps: &[MapEntry], pick: bool, page: usize) -> Vec<CreateActionRow> {
+    if maps.len() > MAX_SELECT_OPTIONS {
+        let (shown, page, pages) = select_page(maps, page);
+        let mut rows: Vec<CreateActionRow> = shown
+            .chunks(MAX_SELECT_OPTIONS)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut ar = CreateActionRow::default();
+                let mut menu = CreateSelectMenu::default();
+                menu.custom_id(format!("{}{}", VETO_SELECT_PREFIX, index));
+                menu.placeholder(match pick {
+                    true => "Pick a map",
+                    false => "Ban a map",
+                });
+                let options = chunk
+                    .iter()
+                    .map(|map| create_menu_option(&map.display_name, &map.name))
+                    .collect();
+                menu.options(|f| f.set_options(options));
+                ar.add_select_menu(menu);
+                ar
+            })
+            .collect();
+        if pages > 1 {
+            rows.push(create_page_action_row(VETO_PAGE_PREFIX, page, pages));
+        }
+        return rows;
+    }
+    maps.chunks(5)
+        .map(|chunk| {
+            let mut ar = CreateActionRow::default();
+            for map in chunk {
+                let mut button = CreateButton::default();
+                button.custom_id(format!("{}{}", VETO_PREFIX, map.name));
+                button.label(&map.display_name);
+                button.style(match pick {
+                    true => ButtonStyle::Success,
+                    false => ButtonStyle::Danger,
+                });
+                ar.add_button(button);
+            }
+            ar
+        })
+        .collect()
+}
+
+/// Players not drafted yet, grouped by party in queue order. A party is always
+/// picked as a whole.
+async fn remaining_units(context: &SetupContext<'_>, draft: &Draft) -> Vec<Vec<User>> {
+    let parties = context.lobby.parties.lock().await.clone();
+    let mut units: Vec<Vec<User>> = Vec::new();
+    for user in context.lobby.user_queue.lock().await.iter() {
+        if draft.team_a.contains(user) || draft.team_b.contains(user) {
+            continue;
+        }
+        let leader = parties.get(user.id.as_u64());
+        let unit = units
+            .iter_mut()
+            .find(|unit| leader.is_some() && parties.get(unit[0].id.as_u64()) == leader);
+        match unit {
+            Some(unit) => unit.push(user.clone()),
+            None => units.push(vec![user.clone()]),
+        }
+    }
+    units
+}
+
+/// `user` followed by the members of their party that are in the queue.
+async fn queued_party(context: &SetupContext<'_>, user: &User) -> Vec<User> {
+    let members = party_members(&context.lobby, user.id.0).await;
+    let mut party = vec![user.clone()];
+    party.extend(
+        context
+            .lobby
+            .user_queue
+            .lock()
+            .await
+            .iter()
+            .filter(|u| u.id != user.id && members.contains(&u.id.0))
+            .cloned(),
+    );
+    party
+}
+
+/// Most players a team can have, the first team takes the odd one out.
+async fn team_capacity(context: &SetupContext<'_>) -> usize {
+    context.lobby.user_queue.lock().await.len().div_ceil(2)
+}
+
+/// Free slots on team A (or B), followed by the free slots on the other team.
+fn team_slots(draft: &Draft, capacity: usize, team_a: bool) -> (usize, usize) {
+    let slots_a = capacity.saturating_sub(draft.team_a.len());
+    let slots_b = capacity.saturating_sub(draft.team_b.len());
+    match team_a {
+        true => (slots_a, slots_b),
+        false => (slots_b, slots_a),
+    }
+}
+
+/// Whether `units` can be spread over two teams with `slots_a` and `slots_b`
+/// free slots without splitting a party.
+fn can_split(units: &[Vec<User>], slots_a: usize, slots_b: usize) -> bool {
+    let total: usize = units.iter().map(Vec::len).sum();
+    if total > slots_a + slots_b {
+        return false;
+    }
+    let mut reachable = vec![false; total + 1];
+    reachable[0] = true;
+    for unit in units {
+        for size in (unit.len()..=total).rev() {
+            if reachable[size - unit.len()] {
+                reachable[size] = true;
+            }
+        }
+    }
+    (total.saturating_sub(slots_b)..=slots_a.min(total)).any(|size| reachable[size])
+}
+
+/// Units a team with `slots` free slots can pick while every other unit still
+/// fits somewhere.
+fn pickable_units(units: &[Vec<User>], slots: usize, other_slots: usize) -> Vec<Vec<User>> {
+    units
+        .iter()
+        .enumerate()
+        .filter(|(i, unit)| {
+            if unit.len() > slots {
+                return false;
+            }
+            let rest: Vec<Vec<User>> = units
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| j != i)
+                .map(|(_, unit)| unit.clone())
+                .collect();
+            can_split(&rest, slots - unit.len(), other_slots)
+        })
+        .map(|(_, unit)| unit.clone())
+        .collect()
+}
+
+/// The captain to pick next. That is captain A if `prefer_a` (captain B
+/// otherwise), unless only the other captain has a valid pick left.
+fn next_picker(
+    draft: &Draft,
+    units: &[Vec<User>],
+    capacity: usize,
+    prefer_a: bool,
+) -> Option<User> {
+    let (slots_a, slots_b) = team_slots(draft, capacity, true);
+    let a_can_pick = !pickable_units(units, slots_a, slots_b).is_empty();
+    let b_can_pick = !pickable_units(units, slots_b, slots_a).is_empty();
+    if (prefer_a && a_can_pick) || (!prefer_a && !b_can_pick) {
+        draft.captain_a.clone()
+    } else {
+        draft.captain_b.clone()
+    }
+}
+
+/// Units the current picker of `draft` can choose from.
+fn pick_options(draft: &Draft, units: &[Vec<User>], capacity: usize) -> Vec<Vec<User>> {
+    let is_team_a = draft.current_picker == draft.captain_a;
+    let (slots, other_slots) = team_slots(draft, capacity, is_team_a);
+    pickable_units(units, slots, other_slots)
+}
+
+/// The pool entry of the map the draft is played on. Falls back to a plain
+/// entry if the map was removed from the pool since the vote.
+async fn selected_map(lobby: &Lobby, draft: &Draft) -> MapEntry {
+    map_entry(lobby, &draft.selected_map).await
+}
+
+/// The pool entry of `map`, or a plain entry if it is not in the pool.
+pub(crate) async fn map_entry(lobby: &Lobby, map: &str) -> MapEntry {
+    lobby
+        .maps()
         .await
-        .clone()
         .into_iter()
-        .filter(|user| !draft.team_a.contains(user) && !draft.team_b.contains(user))
-        .collect();
-    Ok(remaining_users)
+        .find(|m| m.name.eq_ignore_ascii_case(map))
+        .unwrap_or_else(|| MapEntry::new(map))
 }
 
-async fn calc_selected_map(context: &Context<'_>) -> Result<String> {
-    let votes = context.data().draft.lock().await.clone().map_votes;
-    let vote_map = votes
-        .into_values()
-        .fold(HashMap::<String, i32>::new(), |mut accum, item| {
-            for m in item {
-                if let Some(c) = accum.get(&m) {
-                    let nc = c + 1;
-                    accum.insert(m, nc);
-                } else {
-                    accum.insert(m, 1);
+/// Decides the map vote, holding a runoff if maps are tied. Returns the map and
+/// the tally shown to the players.
+async fn calc_selected_map(context: &SetupContext<'_>) -> Result<(MapEntry, String)> {
+    let votes = context.lobby.draft.lock().await.clone().map_votes;
+    let ranked = context
+        .data()
+        .guild_config(context.lobby.guild_id)?
+        .ranked_map_vote
+        .unwrap_or(false);
+    let maps = context.lobby.maps().await;
+    let outcome = match ranked {
+        true => instant_runoff(&votes, &maps),
+        false => tally_votes(&votes, &maps, "Votes"),
+    };
+    let mut tally = outcome.tally;
+    let map = match outcome.winners.len() {
+        0 => return Err(anyhow!("No map votes were submitted")),
+        1 => outcome.winners[0].clone(),
+        _ => {
+            let (map, runoff_tally) = run_runoff(context, &outcome.winners, &maps).await?;
+            tally.push_str(&runoff_tally);
+            map
+        }
+    };
+    // votes hold the lowercased option values, the pool has the actual name
+    let map = maps
+        .into_iter()
+        .find(|m| m.name.eq_ignore_ascii_case(&map))
+        .unwrap_or_else(|| MapEntry::new(&map));
+    Ok((map, tally))
+}
+
+/// Result of counting map votes.
+struct VoteOutcome {
+    /// Maps tied for the most votes, a single one if the vote is decided.
+    winners: Vec<String>,
+    /// The counts, one line per round.
+    tally: String,
+}
+
+fn display_name<'a>(maps: &'a [MapEntry], map: &'a str) -> &'a str {
+    maps.iter()
+        .find(|m| m.name.eq_ignore_ascii_case(map))
+        .map_or(map, |m| m.display_name.as_str())
+}
+
+/// `counts` as `Dust II 4, Mirage 2`, most votes first.
+fn format_counts(counts: &[(String, usize)], maps: &[MapEntry]) -> String {
+    counts
+        .iter()
+        .map(|(map, count)| format!("{} {}", display_name(maps, map), count))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Votes per map, most votes first, ties by name.
+fn count_votes<'a>(votes: impl Iterator<Item = &'a String>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = Vec::new();
+    for map in votes {
+        match counts.iter_mut().find(|(m, _)| m == map) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((map.clone(), 1)),
+        }
+    }
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+/// Plain vote, every map a player picked counts once.
+fn tally_votes(votes: &HashMap<u64, Vec<String>>, maps: &[MapEntry], label: &str) -> VoteOutcome {
+    let counts = count_votes(votes.values().flatten().filter(|m| !m.is_empty()));
+    let Some(max) = counts.first().map(|(_, count)| *count) else {
+        return VoteOutcome {
+            winners: Vec::new(),
+            tally: String::new(),
+        };
+    };
+    VoteOutcome {
+        winners: counts
+            .iter()
+            .filter(|(_, count)| *count == max)
+            .map(|(map, _)| map.clone())
+            .collect(),
+        tally: format!("{}: {}\n", label, format_counts(&counts, maps)),
+    }
+}
+
+/// Ranked vote: each ballot counts for its highest ranked map still in the
+/// running, and the maps with the fewest votes are eliminated until one has a
+/// majority.
+fn instant_runoff(votes: &HashMap<u64, Vec<String>>, maps: &[MapEntry]) -> VoteOutcome {
+    let ballots: Vec<Vec<&String>> = votes
+        .values()
+        .map(|ranks| {
+            let mut ballot = Vec::new();
+            for map in ranks {
+                if !map.is_empty() && !ballot.contains(&map) {
+                    ballot.push(map);
                 }
             }
-            accum
-        });
-    let Some(max) = vote_map.values().max() else {
-        return Err(anyhow!("No map votes were submitted"));
-    };
-    let max_maps: Vec<String> = vote_map
+            ballot
+        })
+        .filter(|ballot| !ballot.is_empty())
+        .collect();
+    let mut remaining: Vec<&String> = Vec::new();
+    for map in ballots.iter().flatten() {
+        if !remaining.contains(map) {
+            remaining.push(*map);
+        }
+    }
+    let mut tally = String::new();
+    for round in 1.. {
+        let firsts: Vec<&String> = ballots
+            .iter()
+            .filter_map(|ballot| ballot.iter().find(|m| remaining.contains(*m)).copied())
+            .collect();
+        let mut counts = count_votes(firsts.iter().copied());
+        // maps only ranked lower still take part in the round
+        for map in &remaining {
+            if !counts.iter().any(|(m, _)| m == *map) {
+                counts.push(((*map).clone(), 0));
+            }
+        }
+        let (Some((leader, max)), Some((_, min))) = (counts.first(), counts.last()) else {
+            break;
+        };
+        tally.push_str(&format!(
+            "Round {}: {}",
+            round,
+            format_counts(&counts, maps)
+        ));
+        if max * 2 > firsts.len() {
+            tally.push_str(&format!(
+                ", `{}` has a majority\n",
+                display_name(maps, leader)
+            ));
+            return VoteOutcome {
+                winners: vec![leader.clone()],
+                tally,
+            };
+        }
+        if max == min {
+            tally.push_str(", tied\n");
+            return VoteOutcome {
+                winners: counts.into_iter().map(|(map, _)| map).collect(),
+                tally,
+            };
+        }
+        let eliminated: Vec<String> = counts
+            .iter()
+            .filter(|(_, count)| count == min)
+            .map(|(map, _)| map.clone())
+            .collect();
+        let names: Vec<String> = eliminated
+            .iter()
+            .map(|map| format!("`{}`", display_name(maps, map)))
+            .collect();
+        tally.push_str(&format!(", {} eliminated\n", names.join(", ")));
+        remaining.retain(|map| !eliminated.contains(*map));
+    }
+    VoteOutcome {
+        winners: Vec::new(),
+        tally,
+    }
+}
+
+/// Lets the queue vote once more between the tied maps. A tie after the
+/// runoff is broken at random. Returns the map and the runoff tally.
+async fn run_runoff(
+    context: &SetupContext<'_>,
+    tied: &[String],
+    maps: &[MapEntry],
+) -> Result<(String, String)> {
+    let names: Vec<String> = tied
         .iter()
-        .filter(|item| item.1 >= max)
-        .map(|item| item.0.clone())
+        .map(|map| format!("`{}`", display_name(maps, map)))
+        .collect();
+    let deadline = Utc::now() + ChronoDuration::from_std(RUNOFF_TIME)?;
+    let rows = create_runoff_action_rows(tied, maps, 0);
+    context
+        .channel_id
+        .edit_message(context, context.message_id, |m| {
+            m.content(format!(
+                "Map vote is tied between {}, pick one of them. The runoff ends <t:{}:R>",
+                names.join(", "),
+                deadline.timestamp()
+            ))
+            .components(|c| c.set_action_rows(rows))
+        })
+        .await?;
+
+    let mut votes: HashMap<u64, Vec<String>> = HashMap::new();
+    let mut cib = context.collect_paged_interactions(RUNOFF_TIME, runoff_id);
+    while let Some(mci) = cib.next().await {
+        if !mci_user_in_queue(context, &mci).await? {
+            continue;
+        }
+        if let Some(page) = mci.data.custom_id.strip_prefix(RUNOFF_PAGE_PREFIX) {
+            let rows = create_runoff_action_rows(tied, maps, page.parse()?);
+            show_player_page(context, &mci, "More tied maps".to_string(), rows).await?;
+            continue;
+        }
+        if !mci.data.custom_id.starts_with(RUNOFF_SELECT_PREFIX) {
+            continue;
+        }
+        votes.insert(mci.user.id.0, mci.data.values.clone());
+        mci.create_interaction_response(context, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true).content("Runoff vote submitted"))
+        })
+        .await?;
+    }
+
+    let outcome = tally_votes(&votes, maps, "Runoff");
+    let mut tally = match outcome.tally.is_empty() {
+        true => "Runoff: no votes\n".to_string(),
+        false => outcome.tally,
+    };
+    let mut winners = outcome.winners;
+    if winners.is_empty() {
+        winners = tied.to_vec();
+    }
+    let map = match winners.len() {
+        1 => winners.remove(0),
+        len => {
+            let map = winners.remove(rand::thread_rng().gen_range(0..len));
+            tally.push_str(&format!(
+                "Still tied, `{}` was picked at random\n",
+                display_name(maps, &map)
+            ));
+            map
+        }
+    };
+    Ok((map, tally))
+}
+
+/// Selects of the maps tied in the vote, page `page` of them if there are too
+/// many for one message. A pick in any of the selects is the player's single
+/// runoff vote.
+fn create_runoff_action_rows(
+    tied: &[String],
+    maps: &[MapEntry],
+    page: usize,
+) -> Vec<CreateActionRow> {
+    let (shown, page, pages) = select_page(tied, page);
+    let mut rows: Vec<CreateActionRow> = shown
+        .chunks(MAX_SELECT_OPTIONS)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut ar = CreateActionRow::default();
+            let mut menu = CreateSelectMenu::default();
+            menu.custom_id(format!("{}{}", RUNOFF_SELECT_PREFIX, index));
+            menu.placeholder("Pick a map");
+            let options: Vec<CreateSelectMenuOption> = chunk
+                .iter()
+                .map(|map| create_menu_option(display_name(maps, map), map))
+                .collect();
+            menu.options(|f| f.set_options(options));
+            ar.add_select_menu(menu);
+            ar
+        })
         .collect();
-    if max_maps.len() == 1 {
-        return Ok(max_maps.first().unwrap().to_string());
+    if pages > 1 {
+        rows.push(create_page_action_row(RUNOFF_PAGE_PREFIX, page, pages));
     }
+    rows
+}
 
-    let map = &max_maps
-        .get(rand::thread_rng().gen_range(0..max_maps.len()))
-        .unwrap();
+/// Page `page` of `items` for selects of 25 options each, with the page shown
+/// and the number of pages. Everything fits in the 5 rows of a message up to
+/// 125 options, beyond that a page has 4 selects and a row of page buttons.
+fn select_page<T>(items: &[T], page: usize) -> (&[T], usize, usize) {
+    if items.len() <= MAX_SELECT_OPTIONS * 5 {
+        return (items, 0, 1);
+    }
+    let page_size = MAX_SELECT_OPTIONS * PAGE_SELECTS;
+    let pages = items.len().div_ceil(page_size);
+    let page = page.min(pages - 1);
+    let start = page * page_size;
+    (
+        &items[start..(start + page_size).min(items.len())],
+        page,
+        pages,
+    )
+}
 
-    Ok(map.to_string())
+/// Shows a page of maps to the player who pressed a page button: in a new
+/// message only they see when paging from the setup message, so the page does
+/// not change for everyone, or in that message when paging from there.
+async fn show_player_page(
+    context: &SetupContext<'_>,
+    mci: &MessageComponentInteraction,
+    content: String,
+    rows: Vec<CreateActionRow>,
+) -> Result<()> {
+    let kind = match mci.message.id == context.message_id {
+        true => InteractionResponseType::ChannelMessageWithSource,
+        false => InteractionResponseType::UpdateMessage,
+    };
+    mci.create_interaction_response(context, |r| {
+        r.kind(kind).interaction_response_data(|d| {
+            d.ephemeral(true)
+                .content(content)
+                .components(|c| c.set_action_rows(rows))
+        })
+    })
+    .await?;
+    Ok(())
 }
 
 async fn handle_draft_type(
-    context: &Context<'_>,
+    context: &SetupContext<'_>,
     mci: &Arc<MessageComponentInteraction>,
 ) -> Result<()> {
     let option = &mci.data.custom_id;
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = context.lobby.state.lock().await;
         match option.as_str() {
             "autodraft" => {
                 handle_autodraft(context, mci).await?;
@@ -487,78 +2032,252 @@ fn create_captain_action_row() -> CreateActionRow {
     ar
 }
 
-pub async fn handle_map_pick(
-    context: &Context<'_>,
+/// Whether `custom_id` belongs to a component of the map vote.
+fn map_vote_id(custom_id: &str) -> bool {
+    custom_id == "vote"
+        || [MAP_PAGE_PREFIX, MAP_SELECT_PREFIX, RANK_PREFIX]
+            .iter()
+            .any(|prefix| custom_id.starts_with(prefix))
+}
+
+/// Whether `custom_id` belongs to a component of the runoff vote.
+fn runoff_id(custom_id: &str) -> bool {
+    custom_id.starts_with(RUNOFF_PAGE_PREFIX) || custom_id.starts_with(RUNOFF_SELECT_PREFIX)
+}
+
+/// Records a map pick or a press of Vote. Returns whether the vote changed.
+pub(crate) async fn handle_map_pick(
+    context: &SetupContext<'_>,
     mci: &Arc<MessageComponentInteraction>,
-) -> Result<()> {
-    let in_queue = user_in_queue(context, Some(mci)).await?;
+) -> Result<bool> {
+    if !map_vote_id(&mci.data.custom_id) {
+        return Ok(false);
+    }
+    let in_queue = mci_user_in_queue(context, mci).await?;
     if !in_queue {
-        return Ok(());
+        return Ok(false);
     }
 
-    if mci.data.custom_id != "vote" {
-        let maps_selected = &mci.data.values;
-        {
-            let mut draft = context.data().draft.lock().await;
-            draft
-                .map_votes
-                .insert(mci.user.clone(), maps_selected.clone());
-        };
+    if let Some(page) = mci.data.custom_id.strip_prefix(MAP_PAGE_PREFIX) {
+        handle_map_page(context, mci, page.parse()?).await?;
+        return Ok(false);
+    }
+    if let Some(rank) = mci.data.custom_id.strip_prefix(RANK_PREFIX) {
+        // ranked votes keep one map per rank, empty for ranks not picked yet
+        let rank: usize = rank.parse()?;
+        let map = mci.data.values.first().cloned().unwrap_or_default();
+        let mut draft = context.lobby.draft.lock().await;
+        let ranks = draft.map_votes.entry(mci.user.id.0).or_default();
+        if ranks.len() <= rank {
+            ranks.resize(rank + 1, String::new());
+        }
+        ranks[rank] = map;
+    } else if mci.data.custom_id.starts_with(MAP_SELECT_PREFIX) {
+        // a select only replaces the picks among its own maps
+        let shown = select_values(&mci.message, &mci.data.custom_id);
+        let user_id = mci.user.id.0;
+        let mut draft = context.lobby.draft.lock().await;
+        let votes = draft.map_votes.entry(user_id).or_default();
+        votes.retain(|map| !shown.contains(map));
+        votes.extend(mci.data.values.iter().cloned());
+        if votes.is_empty() {
+            draft.map_votes.remove(&user_id);
+            draft.map_voted.retain(|id| *id != user_id);
+        }
     }
 
     if mci.data.custom_id == "vote" {
+        let submitted = {
+            let mut draft = context.lobby.draft.lock().await;
+            let picked = draft.map_votes.contains_key(&mci.user.id.0);
+            if picked && !draft.map_voted.contains(&mci.user.id.0) {
+                draft.map_voted.push(mci.user.id.0);
+            }
+            picked
+        };
+        let response = match submitted {
+            true => "Map vote submitted",
+            false => "Pick at least one map before voting",
+        };
         mci.create_interaction_response(context, |r| {
             r.kind(InteractionResponseType::ChannelMessageWithSource)
-                .interaction_response_data(|d| d.ephemeral(true).content("Map vote submitted"))
+                .interaction_response_data(|d| d.ephemeral(true).content(response))
         })
         .await?;
-    } else {
-        mci.create_interaction_response(context, |r| {
-            r.kind(InteractionResponseType::DeferredUpdateMessage)
-                .interaction_response_data(|d| d)
+        return Ok(submitted);
+    }
+    mci.create_interaction_response(context, |r| {
+        r.kind(InteractionResponseType::DeferredUpdateMessage)
+            .interaction_response_data(|d| d)
+    })
+    .await?;
+
+    Ok(true)
+}
+
+/// Shows the player page `page` of the map vote on their own. Votes are kept
+/// per player, so picks made on every page count.
+async fn handle_map_page(
+    context: &SetupContext<'_>,
+    mci: &Arc<MessageComponentInteraction>,
+    page: usize,
+) -> Result<()> {
+    let page = map_vote_page(context, page).await?;
+    let content = format!(
+        "Maps {}-{} of {}, press Vote in the setup message once you picked",
+        page.first + 1,
+        page.first + page.maps.len(),
+        page.total
+    );
+    let mut rows = create_map_action_row(page.maps, &[], page.ranked);
+    rows.push(create_page_action_row(
+        MAP_PAGE_PREFIX,
+        page.page,
+        page.pages,
+    ));
+    show_player_page(context, mci, content, rows).await
+}
+
+/// Values of the select `custom_id` in `message`.
+fn select_values(message: &Message, custom_id: &str) -> Vec<String> {
+    message
+        .components
+        .iter()
+        .flat_map(|row| &row.components)
+        .filter_map(|component| match component {
+            ActionRowComponent::SelectMenu(menu) => Some(menu),
+            _ => None,
         })
-        .await?;
+        .filter(|menu| menu.custom_id.as_deref() == Some(custom_id))
+        .flat_map(|menu| menu.options.iter().map(|option| option.value.clone()))
+        .collect()
+}
+
+/// Map selects of up to 25 maps each, or one select per rank for a ranked
+/// vote, followed by greyed out buttons for the maps cooling down. Leaves room
+/// for the vote button.
+pub fn create_map_action_row(
+    map_list: Vec<MapEntry>,
+    cooling: &[MapEntry],
+    ranked: bool,
+) -> Vec<CreateActionRow> {
+    let mut rows = match ranked {
+        true => create_rank_action_rows(&map_list),
+        false => {
+            let chunks: Vec<&[MapEntry]> = map_list.chunks(MAX_SELECT_OPTIONS).collect();
+            let split = chunks.len() > 1;
+            chunks
+                .into_iter()
+                .take(MAP_VOTE_ROWS)
+                .enumerate()
+                .map(|(index, chunk)| create_map_select_row(index, chunk, split))
+                .collect()
+        }
+    };
+    for chunk in cooling.chunks(5).take(MAP_VOTE_ROWS - rows.len()) {
+        let mut ar = CreateActionRow::default();
+        for map in chunk {
+            let mut button = CreateButton::default();
+            button.custom_id(format!("cooldown:{}", map.name));
+            button.label(format!("{} (cooling down)", map.display_name));
+            button.style(ButtonStyle::Secondary);
+            button.disabled(true);
+            ar.add_button(button);
+        }
+        rows.push(ar);
     }
+    rows
+}
 
-    Ok(())
+fn create_rank_action_rows(map_list: &[MapEntry]) -> Vec<CreateActionRow> {
+    const RANK_NAMES: [&str; MAX_RANKS] = ["1st choice", "2nd choice", "3rd choice"];
+    RANK_NAMES
+        .iter()
+        .take(map_list.len())
+        .enumerate()
+        .map(|(rank, placeholder)| {
+            let mut ar = CreateActionRow::default();
+            let mut menu = CreateSelectMenu::default();
+            menu.custom_id(format!("{}{}", RANK_PREFIX, rank));
+            menu.placeholder(*placeholder);
+            let options = map_list
+                .iter()
+                .map(|map| create_menu_option(&map.display_name, &map.name))
+                .collect();
+            menu.options(|f| f.set_options(options));
+            ar.add_select_menu(menu);
+            ar
+        })
+        .collect()
 }
 
-pub fn create_map_action_row(map_list: Vec<String>) -> CreateActionRow {
+fn create_map_select_row(index: usize, map_list: &[MapEntry], split: bool) -> CreateActionRow {
     let mut ar = CreateActionRow::default();
     let mut menu = CreateSelectMenu::default();
-    menu.custom_id("map_select");
-    menu.placeholder("Pick maps");
-    let map_len = map_list.len();
+    menu.custom_id(format!("{}:{}", MAP_SELECT_PREFIX, index));
+    match (split, map_list.first(), map_list.last()) {
+        (true, Some(first), Some(last)) => menu.placeholder(format!(
+            "Pick maps: {} to {}",
+            first.display_name, last.display_name
+        )),
+        _ => menu.placeholder("Pick maps"),
+    };
     let mut options = Vec::new();
-    for map_name in map_list {
-        options.push(create_menu_option(
-            &map_name,
-            &map_name.to_ascii_lowercase(),
-        ))
+    for map in map_list {
+        options.push(create_menu_option(&map.display_name, &map.name))
     }
     menu.options(|f| f.set_options(options));
-    menu.min_values(1);
-    menu.max_values(map_len.try_into().unwrap());
+    // emptying one select keeps the picks made in the others
+    menu.min_values(0);
+    menu.max_values(map_list.len().try_into().unwrap());
     ar.add_select_menu(menu);
     ar
 }
-pub fn create_map_vote_action_row() -> CreateActionRow {
+/// The Vote button, with page buttons if the maps need more than one page.
+pub fn create_map_vote_action_row(page: usize, pages: usize) -> CreateActionRow {
     let mut ar = CreateActionRow::default();
     let mut button = CreateButton::default();
     button.label("Vote");
     button.style(ButtonStyle::Success);
     button.custom_id("vote");
     ar.add_button(button);
+    if pages > 1 {
+        add_page_buttons(&mut ar, MAP_PAGE_PREFIX, page, pages);
+    }
+    ar
+}
+fn create_page_action_row(prefix: &str, page: usize, pages: usize) -> CreateActionRow {
+    let mut ar = CreateActionRow::default();
+    add_page_buttons(&mut ar, prefix, page, pages);
     ar
 }
-pub fn create_user_action_row(user_list: Vec<User>) -> CreateActionRow {
+/// Previous and Next buttons, their custom ids are `prefix` followed by the
+/// page they show.
+fn add_page_buttons(ar: &mut CreateActionRow, prefix: &str, page: usize, pages: usize) {
+    let mut prev = CreateButton::default();
+    prev.label("Previous maps");
+    prev.style(ButtonStyle::Secondary);
+    prev.custom_id(format!("{}{}", prefix, page.saturating_sub(1)));
+    prev.disabled(page == 0);
+    ar.add_button(prev);
+    let mut next = CreateButton::default();
+    next.label("Next maps");
+    next.style(ButtonStyle::Secondary);
+    next.custom_id(format!("{}{}", prefix, page + 1));
+    next.disabled(page + 1 >= pages);
+    ar.add_button(next);
+}
+pub fn create_user_action_row(units: Vec<Vec<User>>) -> CreateActionRow {
     let mut ar = CreateActionRow::default();
     let mut menu = CreateSelectMenu::default();
     menu.custom_id("user_select");
     menu.placeholder("Pick user");
-    let options: Vec<CreateSelectMenuOption> = user_list
+    let options: Vec<CreateSelectMenuOption> = units
         .iter()
-        .map(|u| create_menu_option(&u.name, &u.id.0.to_string()))
+        .map(|unit| {
+            let names: Vec<&str> = unit.iter().map(|u| u.name.as_str()).collect();
+            create_menu_option(&names.join(" + "), &unit[0].id.0.to_string())
+        })
         .collect();
     menu.options(|f| f.set_options(options));
     ar.add_select_menu(menu);
@@ -625,14 +2344,14 @@ pub fn create_ready_check_action_row() -> CreateActionRow {
 }
 
 async fn handle_captain_pick(
-    context: &Context<'_>,
+    context: &SetupContext<'_>,
     mci: &MessageComponentInteraction,
 ) -> Result<()> {
-    let in_queue = user_in_queue(&context, Some(&mci)).await?;
+    let in_queue = mci_user_in_queue(context, mci).await?;
     if !in_queue {
         return Ok(());
     }
-    let draft = context.data().draft.lock().await.clone();
+    let draft = context.lobby.draft.lock().await.clone();
     if let Some(user) = &draft.captain_a {
         if user.id == mci.user.id {
             mci.create_interaction_response(context, |m| {
@@ -655,19 +2374,51 @@ async fn handle_captain_pick(
             return Ok(());
         }
     }
-    let draft = {
-        let mut draft = context.data().draft.lock().await;
-        match draft.captain_a {
-            Some(_) => {
-                draft.captain_b = Some(mci.user.clone());
-                draft.team_b.push(mci.user.clone());
-            }
-            None => {
-                draft.captain_a = Some(mci.user.clone());
-                draft.team_a.push(mci.user.clone());
-                draft.current_picker = Some(mci.user.clone())
-            }
+    let party = queued_party(context, &mci.user).await;
+    if let Some(captain_a) = &draft.captain_a {
+        if party.contains(captain_a) {
+            mci.create_interaction_response(context, |m| {
+                m.interaction_response_data(|d| {
+                    d.ephemeral(true)
+                        .content("A member of your party is already a captain")
+                })
+            })
+            .await?;
+            return Ok(());
         }
+    }
+    let capacity = team_capacity(context).await;
+    let mut candidate = draft.clone();
+    match candidate.captain_a {
+        Some(_) => {
+            candidate.captain_b = Some(mci.user.clone());
+            candidate.team_b.extend(party);
+        }
+        None => {
+            candidate.captain_a = Some(mci.user.clone());
+            candidate.team_a.extend(party);
+            candidate.current_picker = Some(mci.user.clone())
+        }
+    }
+    let units = remaining_units(context, &candidate).await;
+    let (slots_a, slots_b) = team_slots(&candidate, capacity, true);
+    let fits = candidate.team_a.len() <= capacity && candidate.team_b.len() <= capacity;
+    if !fits || !can_split(&units, slots_a, slots_b) {
+        mci.create_interaction_response(context, |m| {
+            m.interaction_response_data(|d| {
+                d.ephemeral(true)
+                    .content("Your party does not fit on a team with the remaining players")
+            })
+        })
+        .await?;
+        return Ok(());
+    }
+    if candidate.captain_b.is_some() {
+        candidate.current_picker = next_picker(&candidate, &units, capacity, true);
+    }
+    let draft = {
+        let mut draft = context.lobby.draft.lock().await;
+        *draft = candidate;
         draft.clone()
     };
     if draft.captain_a.is_none() || draft.captain_b.is_none() {
@@ -686,13 +2437,12 @@ async fn handle_captain_pick(
         return Ok(());
     }
 
-    let queue = context.data().user_queue.lock().await.clone().len();
-    if draft.team_a.len() + draft.team_b.len() == queue {
+    if units.is_empty() {
         init_sidepick_state(context, mci, None).await?;
         return Ok(());
     }
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = context.lobby.state.lock().await;
         *state = State::Draft;
     }
     let team_names = context.data().team_names.lock().await.clone();
@@ -700,15 +2450,15 @@ async fn handle_captain_pick(
         .push_line("Draft phase starting.")
         .push_line(list_teams(&draft, &team_names))
         .push("It is ")
-        .mention(&draft.current_picker.unwrap())
+        .mention(draft.current_picker.as_ref().unwrap())
         .push(" turn to pick")
         .build();
-    let remaining_users = get_remaining_users(context).await?;
+    let options = pick_options(&draft, &units, capacity);
     mci.create_interaction_response(&context, |r| {
         r.kind(InteractionResponseType::UpdateMessage)
             .interaction_response_data(|d| {
                 d.content(resp)
-                    .components(|c| c.add_action_row(create_user_action_row(remaining_users)))
+                    .components(|c| c.add_action_row(create_user_action_row(options)))
             })
     })
     .await?;
@@ -717,16 +2467,19 @@ async fn handle_captain_pick(
 }
 
 async fn handle_autodraft(
-    context: &Context<'_>,
+    context: &SetupContext<'_>,
     mci: &Arc<MessageComponentInteraction>,
 ) -> Result<()> {
-    let user_queue = context.data().user_queue.lock().await.clone();
+    let user_queue = context.lobby.user_queue.lock().await.clone();
     let steam_ids = context.data().steam_id_cache.lock().await.clone();
     let mut user_queue_steamids: HashMap<u64, String> = HashMap::new();
     let mut user_queue_user_ids: HashMap<String, u64> = HashMap::new();
     for user in user_queue.iter() {
-        let mut steamid = steam_ids.get(user.id.as_u64()).unwrap().to_string();
-        steamid = steamid.replacen("STEAM_0", "STEAM_1", 1);
+        // without a SteamID there are no stats, the player is picked manually
+        let Some(steam_id) = steam_ids.get(user.id.as_u64()) else {
+            continue;
+        };
+        let steamid = steam_id_2(*steam_id);
         user_queue_steamids.insert(*user.id.as_u64(), steamid.clone());
         user_queue_user_ids.insert(steamid.clone(), *user.id.as_u64());
     }
@@ -737,14 +2490,18 @@ async fn handle_autodraft(
 
     let config = context.data().config.clone();
     let Some(scrimbot_api_config) = config.scrimbot_api_config else {
-        context.send(|m| m.ephemeral(true).content("Sorry, the scrimbot-api user/password has not been configured. This option is unavailable.")).await?;
+        mci.create_interaction_response(&context, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| d.ephemeral(true).content("Sorry, the scrimbot-api user/password has not been configured. This option is unavailable."))
+        })
+        .await?;
         return Ok(());
     };
 
     let client = get_api_client(&scrimbot_api_config);
 
     let resp = client
-        .get(&format!(
+        .get(format!(
             "{}/stats",
             scrimbot_api_config.scrimbot_api_url.clone()
         ))
@@ -786,72 +2543,79 @@ async fn handle_autodraft(
         .await?;
         return Ok(());
     }
-    let captain_a_user = user_queue
-        .iter()
-        .find(|user| {
-            user.id.as_u64()
-                == user_queue_user_ids
-                    .get(&stats.get(0).unwrap().steamId)
-                    .unwrap()
-        })
-        .unwrap();
-    let captain_b_user = user_queue
+    let ranked: Vec<User> = stats
         .iter()
-        .find(|user| {
-            user.id.as_u64()
-                == user_queue_user_ids
-                    .get(&stats.get(1).unwrap().steamId)
-                    .unwrap()
-        })
-        .unwrap();
-    let draft = {
-        let mut draft = context.data().draft.lock().await.clone();
-        draft.captain_a = Some(captain_a_user.clone());
-        draft.team_a.push(captain_a_user.clone());
-        draft.captain_b = Some(captain_b_user.clone());
-        draft.team_b.push(captain_b_user.clone());
-        draft.current_picker = Some(draft.captain_b.as_ref().unwrap().clone());
-        for i in 2..stats.len() {
-            if i % 2 == 0 {
-                draft.team_b.push(
-                    user_queue
-                        .iter()
-                        .find(|user| {
-                            user.id.as_u64()
-                                == user_queue_user_ids
-                                    .get(&stats.get(i).unwrap().steamId)
-                                    .unwrap()
-                        })
-                        .unwrap()
-                        .clone(),
-                );
-                draft.current_picker = Some(draft.captain_a.as_ref().unwrap().clone())
-            } else {
-                draft.team_a.push(
-                    user_queue
-                        .iter()
-                        .find(|user| {
-                            user.id.as_u64()
-                                == user_queue_user_ids
-                                    .get(&stats.get(i).unwrap().steamId)
-                                    .unwrap()
-                        })
-                        .unwrap()
-                        .clone(),
-                );
-                draft.current_picker = Some(draft.captain_b.as_ref().unwrap().clone())
+        .filter_map(|s| user_queue_user_ids.get(&s.steamId))
+        .filter_map(|id| user_queue.iter().find(|user| user.id.0 == *id))
+        .cloned()
+        .collect();
+    let capacity = team_capacity(context).await;
+    let mut draft = context.lobby.draft.lock().await.clone();
+    let captain_a_user = ranked[0].clone();
+    draft.captain_a = Some(captain_a_user.clone());
+    draft
+        .team_a
+        .extend(queued_party(context, &captain_a_user).await);
+    // the best ranked player outside of captain A's party that leaves a valid draft
+    let mut captain_b_user = None;
+    for user in ranked.iter().skip(1) {
+        if draft.team_a.contains(user) {
+            continue;
+        }
+        let mut candidate = draft.clone();
+        candidate.team_b.extend(queued_party(context, user).await);
+        let units = remaining_units(context, &candidate).await;
+        let (slots_a, slots_b) = team_slots(&candidate, capacity, true);
+        if candidate.team_b.len() <= capacity && can_split(&units, slots_a, slots_b) {
+            captain_b_user = Some(user.clone());
+            draft = candidate;
+            break;
+        }
+    }
+    let Some(captain_b_user) = captain_b_user else {
+        mci.create_interaction_response(&context, |r| {
+            r.kind(InteractionResponseType::ChannelMessageWithSource)
+                .interaction_response_data(|d| {
+                    d.ephemeral(true).content(
+                        "Unable to find stats for 2 players outside of the same party. Please use another option",
+                    )
+                })
+        })
+        .await?;
+        return Ok(());
+    };
+    draft.captain_b = Some(captain_b_user);
+    // teams take turns, each ranked player brings their party along
+    let mut team_a_turn = false;
+    for user in ranked.iter() {
+        let units = remaining_units(context, &draft).await;
+        let Some(unit) = units.iter().find(|unit| unit.contains(user)) else {
+            continue;
+        };
+        for team_a in [team_a_turn, !team_a_turn] {
+            let (slots, other_slots) = team_slots(&draft, capacity, team_a);
+            if !pickable_units(&units, slots, other_slots).contains(unit) {
+                continue;
             }
+            match team_a {
+                true => draft.team_a.extend(unit.clone()),
+                false => draft.team_b.extend(unit.clone()),
+            }
+            team_a_turn = !team_a;
+            break;
         }
-        draft.clone()
-    };
+    }
+    let units = remaining_units(context, &draft).await;
+    draft.current_picker = next_picker(&draft, &units, capacity, team_a_turn);
+    *context.lobby.draft.lock().await = draft.clone();
     let team_names = context.data().team_names.lock().await.clone();
     if draft.team_a.len() + draft.team_b.len() != user_queue.len() {
         {
-            let mut state = context.data().state.lock().await;
+            let mut state = context.lobby.state.lock().await;
             *state = State::Draft;
         }
         let teams_str = list_teams(&draft, &team_names);
-        let remaining_users = get_remaining_users(context).await?;
+        let options = pick_options(&draft, &units, capacity);
         let resp= MessageBuilder::new()
             .push("Unable to find stats for all players. Continue draft and pick the remaining players manually.\n\n")
             .push(teams_str)
@@ -863,7 +2627,7 @@ async fn handle_autodraft(
             r.kind(InteractionResponseType::UpdateMessage)
                 .interaction_response_data(|d| {
                     d.content(resp)
-                        .components(|c| c.add_action_row(create_user_action_row(remaining_users)))
+                        .components(|c| c.add_action_row(create_user_action_row(options)))
                 })
         })
         .await?;
@@ -876,17 +2640,242 @@ async fn handle_autodraft(
     Ok(())
 }
 
-async fn start_server(context: &Context<'_>) -> Result<()> {
+async fn start_server(context: &SetupContext<'_>) -> Result<()> {
     println!("Launching server...");
     let response = MessageBuilder::new().push("Starting server...").build();
-    let msg = context.send(|m| m.content(response)).await?;
-    let draft = context.data().draft.lock().await.clone();
+    let mut msg = context
+        .channel_id
+        .send_message(context, |m| m.content(response))
+        .await?;
+    let draft = context.lobby.draft.lock().await.clone();
+    let gotv_url = loop {
+        match launch_match(context, &mut msg, &draft).await? {
+            Launch::Started {
+                dathost_match_id,
+                gotv_url,
+            } => {
+                if !draft.series_maps.is_empty() {
+                    start_series(context, &draft, dathost_match_id)?;
+                }
+                break gotv_url;
+            }
+            Launch::Failed => return Ok(()),
+            Launch::NoServer => {
+                if !wait_for_server(context, &mut msg).await? {
+                    return Ok(());
+                }
+            }
+        }
+    };
+
+    let guild = GuildId(context.lobby.guild_id)
+        .to_partial_guild(context)
+        .await?;
+    let guild_config = context.data().guild_config(context.lobby.guild_id)?;
+    if let Some(team_a_channel_id) = guild_config.team_a_channel_id {
+        for user in &draft.team_a {
+            if let Err(why) = guild
+                .move_member(&context, user.id, team_a_channel_id)
+                .await
+            {
+                println!("Cannot move user: {:?}", why);
+            }
+        }
+    }
+    if let Some(team_b_channel_id) = guild_config.team_b_channel_id {
+        for user in &draft.team_b {
+            if let Err(why) = guild
+                .move_member(&context, user.id, team_b_channel_id)
+                .await
+            {
+                println!("Cannot move user: {:?}", why);
+            }
+        }
+    }
+
+    reset_draft(&context.lobby).await?;
+    let promoted = clear_queue(&context.lobby).await?;
+    if let Some(response) = promotion_message(&context.lobby, &promoted).await {
+        context
+            .channel_id
+            .send_message(context, |m| m.content(response))
+            .await?;
+    }
+    // the connect buttons are served for a while, the panel shows the new queue now
+    refresh_panel(context, &context.lobby).await;
+
+    let mut cib = msg
+        .await_component_interactions(context.serenity)
+        .timeout(Duration::from_secs(60 * 5))
+        .build();
+    loop {
+        let opt = cib.next().await;
+        match opt {
+            Some(mci) => {
+                mci.create_interaction_response(context, |r| {
+                    r.kind(InteractionResponseType::ChannelMessageWithSource)
+                        .interaction_response_data(|d| {
+                            d.ephemeral(true)
+                                .content(format!("GOTV: ||`connect {}`||", &gotv_url))
+                        })
+                })
+                .await?;
+            }
+            None => {
+                // remove console cmds interaction on timeout
+                msg.edit(context, |m| {
+                    m.components(|c| c.add_action_row(create_server_conn_button_row(false)))
+                })
+                .await?;
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Records the best of three whose first map was just launched. The next maps
+/// are launched by the match end webhook or `watch_series`.
+fn start_series(
+    context: &SetupContext<'_>,
+    draft: &Draft,
+    dathost_match_id: Option<String>,
+) -> Result<()> {
+    let series = Series {
+        id: 0,
+        guild_id: context.lobby.guild_id,
+        queue_id: context.lobby.queue_id,
+        channel_id: context.channel_id.0,
+        draft: draft.clone(),
+        started_by: context.started_by,
+        score_a: 0,
+        score_b: 0,
+        maps_launched: 1,
+        dathost_match_id,
+        finished: false,
+        created_at: Utc::now(),
+    };
+    let series_id = context.data().storage.add_series(&series)?;
+    log::info!(
+        "Started series {} in guild {}",
+        series_id,
+        context.lobby.guild_id
+    );
+    Ok(())
+}
+
+/// Launches the next map of `series` in its channel. Returns `false` if every
+/// server is busy or DatHost refused the match.
+pub(crate) async fn launch_series_map(
+    serenity: &SerenityContext,
+    data: &Data,
+    series: &mut Series,
+) -> Result<bool> {
+    let index = series.maps_launched as usize;
+    let (Some(map), Some(side)) = (
+        series.draft.series_maps.get(index).cloned(),
+        series.draft.series_sides.get(index).cloned(),
+    ) else {
+        return Err(anyhow!("Series {} has no map {}", series.id, index + 1));
+    };
+    series.draft.selected_map = map;
+    series.draft.team_b_start_side = side;
+    let mut msg = ChannelId(series.channel_id)
+        .send_message(serenity, |m| m.content("Starting server..."))
+        .await?;
+    let context = SetupContext {
+        serenity,
+        data,
+        lobby: data.lobby_by_id(series.queue_id).await?,
+        channel_id: msg.channel_id,
+        message_id: msg.id,
+        started_by: series.started_by,
+    };
+    match launch_match(&context, &mut msg, &series.draft).await? {
+        Launch::Started {
+            dathost_match_id, ..
+        } => {
+            // only the setup flow answers the GOTV button
+            msg.edit(serenity, |m| {
+                m.components(|c| c.add_action_row(create_server_conn_button_row(false)))
+            })
+            .await?;
+            series.maps_launched += 1;
+            series.dathost_match_id = dathost_match_id;
+            Ok(true)
+        }
+        Launch::NoServer => {
+            msg.delete(serenity).await?;
+            Ok(false)
+        }
+        Launch::Failed => Ok(false),
+    }
+}
+
+/// `Authorization` header DatHost sends with the match end webhook.
+pub(crate) fn match_end_authorization(config: &Config) -> String {
+    match &config.scrimbot_api_config {
+        None => "".to_string(),
+        Some(c) => format!("TOKEN {}", c.scrimbot_api_token),
+    }
+}
+
+/// Whether team A won the finished match of `draft`. `None` if it was canceled
+/// or DatHost did not report a clear score.
+pub(crate) fn team_a_won(draft: &Draft, info: &MatchInfoResponse) -> Option<bool> {
+    if info.cancel_reason.is_some() {
+        return None;
+    }
+    let score = |team: &Option<MatchInfoTeam>| team.as_ref()?.stats.as_ref()?.score;
+    let (team1, team2) = (score(&info.team1)?, score(&info.team2)?);
+    if team1 == team2 {
+        return None;
+    }
+    // team A plays as team1 if team B starts as T, see `launch_match`
+    let team_a_is_team1 = draft.team_b_start_side == "t";
+    Some((team1 > team2) == team_a_is_team1)
+}
+
+/// Outcome of `launch_match`.
+enum Launch {
+    /// Every server is running a match.
+    NoServer,
+    /// DatHost refused the match or a player has no SteamID, the message says
+    /// why.
+    Failed,
+    Started {
+        dathost_match_id: Option<String>,
+        gotv_url: String,
+    },
+}
+
+/// Launches the selected map of `draft` on a free server, records it in the
+/// match history and shows the connect info in `msg`.
+async fn launch_match(
+    context: &SetupContext<'_>,
+    msg: &mut Message,
+    draft: &Draft,
+) -> Result<Launch> {
+    let map = selected_map(&context.lobby, draft).await;
     let steam_ids = context.data().steam_id_cache.lock().await.clone();
+    let unlinked = draft
+        .team_a
+        .iter()
+        .chain(&draft.team_b)
+        .find(|u| !steam_ids.contains_key(u.id.as_u64()));
+    if let Some(user) = unlinked {
+        let response = MessageBuilder::new()
+            .push("Unable to start the server, ")
+            .mention(user)
+            .push(" has no SteamID. Please link one with `/steamid`.")
+            .build();
+        msg.edit(context, |m| m.content(response)).await?;
+        return Ok(Launch::Failed);
+    }
     let team_a_players: Vec<Player> = draft
         .team_a
         .iter()
-        .map(|u| steam_ids.get(u.id.as_u64()).unwrap().to_string())
-        .map(|s| u64::from(SteamId::parse(s).unwrap()))
+        .map(|u| steam_ids[u.id.as_u64()])
         .map(|s| Player {
             steam_id_64: s.to_string(),
             team: match draft.team_b_start_side == "t" {
@@ -898,8 +2887,7 @@ async fn start_server(context: &Context<'_>) -> Result<()> {
     let team_b_players: Vec<Player> = draft
         .team_b
         .iter()
-        .map(|u| steam_ids.get(u.id.as_u64()).unwrap().to_string())
-        .map(|s| u64::from(SteamId::parse(s).unwrap()))
+        .map(|u| steam_ids[u.id.as_u64()])
         .map(|s| Player {
             steam_id_64: s.to_string(),
             team: match draft.team_b_start_side == "ct" {
@@ -911,22 +2899,18 @@ async fn start_server(context: &Context<'_>) -> Result<()> {
     let players: Vec<Player> = team_a_players.into_iter().chain(team_b_players).collect();
 
     let config = &context.data().config;
-    let server_id = &config.dathost.server_id;
-    let match_end_url = config.dathost.match_end_url.clone();
-    let authorization_header = match config.scrimbot_api_config.clone() {
-        None => "".to_string(),
-        Some(c) => format!("TOKEN {}", c.scrimbot_api_token),
+    let client = DathostClient::new(config).await?;
+    let Some(server_id) = reserve_server(context, &client).await? else {
+        return Ok(Launch::NoServer);
+    };
+    // the webhook relays the call to `match_end_url`
+    let match_end_url = match &config.webhook {
+        Some(webhook) => Some(webhook::match_end_url(webhook)),
+        None => config.dathost.match_end_url.clone(),
     };
+    let authorization_header = match_end_authorization(config);
 
-    let default_team_a_name = &format!("Team {}", &draft.captain_a.as_ref().unwrap().name);
-    let default_team_b_name = &format!("Team {}", &draft.captain_b.as_ref().unwrap().name);
-    let team_names = context.data().team_names.lock().await.clone();
-    let team_a_name = team_names
-        .get(draft.captain_a.as_ref().unwrap().id.as_u64())
-        .unwrap_or(default_team_a_name);
-    let team_b_name = team_names
-        .get(draft.captain_b.as_ref().unwrap().id.as_u64())
-        .unwrap_or(default_team_b_name);
+    let (team_a_name, team_b_name) = team_names(context.data(), draft).await;
     let team1_name = match draft.team_b_start_side == "t" {
         true => team_a_name.clone(),
         false => team_b_name.clone(),
@@ -941,7 +2925,8 @@ async fn start_server(context: &Context<'_>) -> Result<()> {
         team2: MatchTeam { name: team2_name },
         players,
         settings: MatchSettings {
-            map: draft.selected_map.clone(),
+            map: map.workshop_id().is_none().then(|| map.name.clone()),
+            workshop_map_id: map.workshop_id().map(String::from),
             connect_time: 60 * 10,
             match_begin_countdown: 20,
             password: "".to_string(),
@@ -956,21 +2941,66 @@ async fn start_server(context: &Context<'_>) -> Result<()> {
         "{}",
         serde_json::to_string(&body).unwrap_or("cannot deserialize body".to_string())
     );
-    let client = DathostClient::new(&config).await?;
-    let resp = client.start_match(body).await?;
+    let storage = &context.data().storage;
+    let resp = match client.start_match(body).await {
+        Ok(resp) => resp,
+        Err(err) => {
+            storage.release_server(&server_id)?;
+            return Err(err.into());
+        }
+    };
     println!("Start match response code - {}", &resp.status());
 
     if !resp.status().is_success() {
-        msg.edit(context.clone(), |m| {
-            m.content(&format!(
+        storage.release_server(&server_id)?;
+        msg.edit(context, |m| {
+            m.content(format!(
                 "Server failed to start, match POST response code: {}",
                 &resp.status().as_str()
             ))
         })
         .await?;
-        return Ok(());
+        return Ok(Launch::Failed);
+    }
+    let dathost_match_id = match resp.json::<StartMatchResponse>().await {
+        Ok(m) => Some(m.id),
+        Err(err) => {
+            log::warn!("Unable to parse start match response: {}", err);
+            None
+        }
+    };
+    // a match is running on the server even if its id is unknown
+    let busy_match_id = dathost_match_id.as_deref().unwrap_or_else(|| {
+        log::warn!(
+            "Server {} stays busy until released with `/admin server release`, its match id is unknown",
+            server_id
+        );
+        UNKNOWN_MATCH_ID
+    });
+    storage.set_server_busy(&server_id, busy_match_id)?;
+    let record = MatchRecord {
+        id: 0,
+        guild_id: context.lobby.guild_id,
+        dathost_match_id: dathost_match_id.clone(),
+        map: draft.selected_map.clone(),
+        team_a: MatchRecordTeam {
+            name: team_a_name.clone(),
+            captain: draft.captain_a.as_ref().unwrap().id.0,
+            players: match_players(&draft.team_a),
+        },
+        team_b: MatchRecordTeam {
+            name: team_b_name.clone(),
+            captain: draft.captain_b.as_ref().unwrap().id.0,
+            players: match_players(&draft.team_b),
+        },
+        team_b_start_side: draft.team_b_start_side.clone(),
+        launched_by: context.started_by,
+        created_at: Utc::now(),
+    };
+    if let Err(err) = context.data().storage.add_match(&record) {
+        log::error!("Unable to record match history: {:?}", err);
     }
-    let server = client.get_server(&config.dathost.server_id).await?;
+    let server = client.get_server(&server_id).await?;
     let host_name = match server.custom_domain {
         Some(s) => {
             if s.is_empty() {
@@ -1001,76 +3031,165 @@ async fn start_server(context: &Context<'_>) -> Result<()> {
     //     .await?
     //     .text()
     //     .await?;
-    let eos = MessageBuilder::new()
-        .push_line(list_teams(&draft, &team_names))
-        .push_line(format!("Map: `{}`\n", &draft.selected_map))
-        .push_line(format!("**Connect:** ||`connect {}`||", &game_url))
-        .build();
-    msg.edit(context.clone(), |m| {
+    let team_names = context.data().team_names.lock().await.clone();
+    let mut eos = MessageBuilder::new();
+    eos.push_line(list_teams(draft, &team_names));
+    if let Some(index) = draft
+        .series_maps
+        .iter()
+        .position(|m| *m == draft.selected_map)
+    {
+        eos.push_line(format!(
+            "Best of three, map {} of {}",
+            index + 1,
+            draft.series_maps.len()
+        ));
+    }
+    eos.push_line(format!("Map: `{}`\n", &map.display_name))
+        .push_line(format!("**Connect:** ||`connect {}`||", &game_url));
+    let eos = eos.build();
+    msg.edit(context, |m| {
         m.content(eos)
             .components(|c| c.add_action_row(create_server_conn_button_row(true)))
     })
     .await?;
+    Ok(Launch::Started {
+        dathost_match_id,
+        gotv_url,
+    })
+}
 
-    let draft = context.data().draft.lock().await.clone();
-    let guild = context.partial_guild().await.unwrap();
-    if let Some(team_a_channel_id) = config.discord.team_a_channel_id {
-        for user in &draft.team_a {
-            if let Err(why) = guild
-                .move_member(&context, user.id, team_a_channel_id)
-                .await
-            {
-                println!("Cannot move user: {:?}", why);
+/// Reserves the first configured server not running a match by marking it
+/// busy with [`STARTING_MATCH_ID`]. Servers whose match has finished since it
+/// was launched are released on the way, servers the bot has no match on are
+/// only picked if nobody is on them. The pool lock is only held while a server
+/// is picked, not while DatHost is asked about it.
+async fn reserve_server(
+    context: &SetupContext<'_>,
+    client: &DathostClient,
+) -> Result<Option<String>> {
+    let storage = &context.data().storage;
+    let mut checked = Vec::new();
+    loop {
+        let candidate = {
+            let _server_pool = context.data().server_pool.lock().await;
+            let busy_servers = storage.busy_servers()?;
+            let candidate = context
+                .data()
+                .config
+                .dathost
+                .servers()
+                .into_iter()
+                .filter(|server_id| !checked.contains(server_id))
+                .find_map(|server_id| match busy_servers.get(&server_id) {
+                    None => Some((server_id, None)),
+                    Some(match_id) if match_id == UNKNOWN_MATCH_ID => None,
+                    Some(match_id) if match_id == STARTING_MATCH_ID => None,
+                    Some(match_id) => Some((server_id, Some(match_id.clone()))),
+                });
+            if let Some((server_id, _)) = &candidate {
+                storage.set_server_busy(server_id, STARTING_MATCH_ID)?;
             }
+            candidate
+        };
+        let Some((server_id, match_id)) = candidate else {
+            return Ok(None);
+        };
+        checked.push(server_id.clone());
+        let free = match &match_id {
+            None => match client.get_server(&server_id).await {
+                Ok(server) => !server.booting && server.players_online == 0,
+                Err(err) => {
+                    log::warn!("Unable to check server {}: {}", server_id, err);
+                    false
+                }
+            },
+            Some(match_id) => match client.get_match(match_id).await {
+                Ok(m) => m.finished,
+                Err(err) if err.status() == Some(StatusCode::NOT_FOUND) => true,
+                Err(err) => {
+                    log::warn!(
+                        "Unable to check match {} on {}: {}",
+                        match_id,
+                        server_id,
+                        err
+                    );
+                    false
+                }
+            },
+        };
+        if free {
+            return Ok(Some(server_id));
         }
-    }
-    if let Some(team_b_channel_id) = config.discord.team_b_channel_id {
-        for user in &draft.team_b {
-            if let Err(why) = guild
-                .move_member(&context, user.id, team_b_channel_id)
-                .await
-            {
-                println!("Cannot move user: {:?}", why);
-            }
+        // nobody else picks a reserved server, it is handed back as it was
+        match &match_id {
+            Some(match_id) => storage.set_server_busy(&server_id, match_id)?,
+            None => storage.release_server(&server_id)?,
         }
     }
+}
 
-    reset_draft(context).await?;
-    clear_queue(context).await?;
-
-    let mut cib = msg
-        .clone()
-        .into_message()
-        .await?
-        .await_component_interactions(context)
-        .timeout(Duration::from_secs(60 * 5))
+/// Reports that every server is busy and waits for someone to retry. Returns
+/// `false` and cancels the setup if nobody does before the timeout.
+async fn wait_for_server(context: &SetupContext<'_>, msg: &mut Message) -> Result<bool> {
+    let server_count = context.data().config.dathost.servers().len();
+    let response = MessageBuilder::new()
+        .push("All ")
+        .push(server_count)
+        .push(" game server(s) are currently running a match. ")
+        .push("Press `Retry` once one of them has finished.")
         .build();
-    loop {
-        let opt = cib.next().await;
-        match opt {
-            Some(mci) => {
-                mci.create_interaction_response(context, |r| {
-                    r.kind(InteractionResponseType::ChannelMessageWithSource)
-                        .interaction_response_data(|d| {
-                            d.ephemeral(true)
-                                .content(format!("GOTV: ||`connect {}`||", &gotv_url))
-                        })
-                })
-                .await?;
-            }
-            None => {
-                // remove console cmds interaction on timeout
-                msg.into_message()
-                    .await?
-                    .edit(context, |m| {
-                        m.components(|c| c.add_action_row(create_server_conn_button_row(false)))
-                    })
-                    .await?;
-                break;
-            }
-        }
+    msg.edit(context, |m| {
+        m.content(response)
+            .components(|c| c.add_action_row(create_retry_action_row()))
+    })
+    .await?;
+    let mci = msg
+        .await_component_interaction(context.serenity)
+        .timeout(Duration::from_secs(60 * 30))
+        .await;
+    if setup_canceled(context)? {
+        msg.edit(context, |m| m.content("Setup canceled").components(|c| c))
+            .await?;
+        return Ok(false);
     }
-    Ok(())
+    let Some(mci) = mci else {
+        msg.edit(context, |m| {
+            m.content("No game server became available, setup canceled")
+                .components(|c| c)
+        })
+        .await?;
+        reset_draft(&context.lobby).await?;
+        return Ok(false);
+    };
+    mci.create_interaction_response(context, |r| {
+        r.kind(InteractionResponseType::DeferredUpdateMessage)
+    })
+    .await?;
+    msg.edit(context, |m| {
+        m.content("Starting server...").components(|c| c)
+    })
+    .await?;
+    Ok(true)
+}
+
+fn create_retry_action_row() -> CreateActionRow {
+    let mut ar = CreateActionRow::default();
+    let mut retry_button = CreateButton::default();
+    retry_button.custom_id("retry_server");
+    retry_button.label("Retry");
+    retry_button.style(ButtonStyle::Primary);
+    ar.add_button(retry_button);
+    ar
+}
+
+fn match_players(team: &[User]) -> Vec<MatchPlayer> {
+    team.iter()
+        .map(|u| MatchPlayer {
+            user_id: u.id.0,
+            name: u.name.clone(),
+        })
+        .collect()
 }
 
 pub fn create_server_conn_button_row(show_cmds: bool) -> CreateActionRow {
@@ -1085,3 +3204,240 @@ pub fn create_server_conn_button_row(show_cmds: bool) -> CreateActionRow {
     }
     ar
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use poise::serenity_prelude::UserId;
+
+    fn user(id: u64) -> User {
+        let mut user = User::default();
+        user.id = UserId(id);
+        user
+    }
+
+    fn units(sizes: &[usize]) -> Vec<Vec<User>> {
+        let mut next_id = 0;
+        sizes
+            .iter()
+            .map(|size| {
+                (0..*size)
+                    .map(|_| {
+                        next_id += 1;
+                        user(next_id)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn sizes(units: &[Vec<User>]) -> Vec<usize> {
+        units.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn can_split_keeps_parties_together() {
+        assert!(can_split(&units(&[2, 2, 1, 1, 1, 1, 1, 1]), 5, 5));
+        assert!(can_split(&units(&[3, 2, 3, 2]), 5, 5));
+        assert!(!can_split(&units(&[3, 3, 3, 1]), 5, 5));
+        assert!(!can_split(&units(&[4, 4, 2]), 5, 5));
+        assert!(!can_split(&units(&[1, 1, 1]), 1, 1));
+        assert!(can_split(&[], 0, 0));
+    }
+
+    #[test]
+    fn pickable_units_leave_room_for_the_rest() {
+        // the party of 3 does not fit on a team with 2 slots
+        assert_eq!(sizes(&pickable_units(&units(&[3, 1, 1]), 2, 3)), vec![1, 1]);
+        // picking the single would leave a party of 2 without a team
+        assert_eq!(sizes(&pickable_units(&units(&[2, 2, 1]), 2, 3)), vec![2, 2]);
+        assert!(pickable_units(&units(&[2]), 1, 2).is_empty());
+    }
+
+    #[test]
+    fn next_picker_skips_a_captain_without_picks() {
+        let draft = Draft {
+            captain_a: Some(user(100)),
+            captain_b: Some(user(200)),
+            team_a: vec![user(100)],
+            team_b: vec![user(200)],
+            ..Draft::default()
+        };
+        let remaining = units(&[2, 1, 1]);
+        let picker = |prefer_a| next_picker(&draft, &remaining, 3, prefer_a).map(|u| u.id.0);
+        assert_eq!(picker(true), Some(100));
+        assert_eq!(picker(false), Some(200));
+
+        // team A is full, so B picks even when it is A's turn
+        let full = Draft {
+            team_a: vec![user(100), user(1), user(2)],
+            ..draft.clone()
+        };
+        let remaining = units(&[1, 1]);
+        assert_eq!(
+            next_picker(&full, &remaining, 3, true).map(|u| u.id.0),
+            Some(200)
+        );
+    }
+
+    fn votes(ballots: &[&[&str]]) -> HashMap<u64, Vec<String>> {
+        ballots
+            .iter()
+            .enumerate()
+            .map(|(index, ballot)| {
+                let ranks = ballot.iter().map(|map| map.to_string()).collect();
+                (index as u64, ranks)
+            })
+            .collect()
+    }
+
+    fn maps() -> Vec<MapEntry> {
+        let mut dust = MapEntry::new("de_dust2");
+        dust.display_name = String::from("Dust II");
+        vec![dust, MapEntry::new("de_mirage")]
+    }
+
+    #[test]
+    fn tally_votes_counts_every_pick() {
+        let outcome = tally_votes(
+            &votes(&[&["de_dust2", "de_mirage"], &["de_dust2"], &["de_nuke"]]),
+            &maps(),
+            "Votes",
+        );
+        assert_eq!(outcome.winners, vec!["de_dust2"]);
+        assert_eq!(outcome.tally, "Votes: Dust II 2, de_mirage 1, de_nuke 1\n");
+
+        let outcome = tally_votes(&votes(&[&["de_nuke"], &["de_anubis"]]), &maps(), "Votes");
+        assert_eq!(outcome.winners, vec!["de_anubis", "de_nuke"]);
+
+        let outcome = tally_votes(&votes(&[&[""], &[]]), &maps(), "Votes");
+        assert!(outcome.winners.is_empty());
+        assert!(outcome.tally.is_empty());
+    }
+
+    #[test]
+    fn instant_runoff_majority_in_first_round() {
+        let outcome = instant_runoff(
+            &votes(&[&["a", "b"], &["a"], &["a", "a"], &["b", "a"]]),
+            &[],
+        );
+        assert_eq!(outcome.winners, vec!["a"]);
+        assert_eq!(outcome.tally, "Round 1: a 3, b 1, `a` has a majority\n");
+    }
+
+    #[test]
+    fn instant_runoff_transfers_eliminated_votes() {
+        let outcome = instant_runoff(
+            &votes(&[
+                &["a", "c"],
+                &["a", "c"],
+                &["b", "a"],
+                &["b", "c"],
+                &["c", "b"],
+            ]),
+            &[],
+        );
+        assert_eq!(outcome.winners, vec!["b"]);
+        assert_eq!(
+            outcome.tally,
+            "Round 1: a 2, b 2, c 1, `c` eliminated\nRound 2: b 3, a 2, `b` has a majority\n"
+        );
+    }
+
+    #[test]
+    fn instant_runoff_ties() {
+        // a map only ranked lower has no votes and goes first
+        let outcome = instant_runoff(&votes(&[&["a", "x"], &["b", "x"], &["c"]]), &[]);
+        assert_eq!(outcome.winners, vec!["a", "b", "c"]);
+        assert_eq!(
+            outcome.tally,
+            "Round 1: a 1, b 1, c 1, x 0, `x` eliminated\nRound 2: a 1, b 1, c 1, tied\n"
+        );
+
+        let outcome = instant_runoff(&votes(&[&[""], &[]]), &[]);
+        assert!(outcome.winners.is_empty());
+        assert!(outcome.tally.is_empty());
+    }
+
+    #[test]
+    fn select_page_splits_large_pools() {
+        let maps: Vec<usize> = (0..300).collect();
+        assert_eq!(select_page(&maps[..125], 3), (&maps[..125], 0, 1));
+        assert_eq!(select_page(&maps[..126], 0), (&maps[..100], 0, 2));
+        assert_eq!(select_page(&maps[..126], 1), (&maps[100..126], 1, 2));
+        // pages past the end show the last one
+        assert_eq!(select_page(&maps, 7), (&maps[200..], 2, 3));
+    }
+
+    fn veto_draft() -> Draft {
+        Draft {
+            captain_a: Some(user(1)),
+            captain_b: Some(user(2)),
+            ..Default::default()
+        }
+    }
+
+    fn veto(draft: &mut Draft, pick: bool) {
+        let captain = draft.captain_a.clone().unwrap();
+        draft.veto.push(VetoBan {
+            map: format!("map_{}", draft.veto.len()),
+            captain,
+            auto: false,
+            pick,
+        });
+    }
+
+    #[test]
+    fn veto_captain_follows_the_veto_order() {
+        let turns = |order: Option<&str>| {
+            let mut draft = veto_draft();
+            let mut captains = Vec::new();
+            for _ in 0..6 {
+                captains.push(veto_captain_in_order(order, &draft).unwrap().id.0);
+                veto(&mut draft, false);
+            }
+            captains
+        };
+        assert_eq!(turns(None), vec![1, 2, 1, 2, 1, 2]);
+        assert_eq!(turns(Some("")), vec![1, 2, 1, 2, 1, 2]);
+        assert_eq!(turns(Some("ABBA")), vec![1, 2, 2, 1, 1, 2]);
+        assert_eq!(turns(Some("abb")), vec![1, 2, 2, 1, 2, 2]);
+        assert!(veto_captain_in_order(None, &Draft::default()).is_err());
+    }
+
+    #[test]
+    fn veto_turn_picks_two_maps_before_the_decider() {
+        let sequence = |pool: usize| {
+            let mut draft = veto_draft();
+            let mut picks = Vec::new();
+            for remaining in (2..=pool).rev() {
+                let pick = veto_turn_picks(&draft, remaining);
+                picks.push(pick);
+                veto(&mut draft, pick);
+            }
+            picks
+        };
+        // ban to five maps, pick one each, ban down to the decider
+        assert_eq!(sequence(7), vec![false, false, true, true, false, false]);
+        assert_eq!(sequence(5), vec![true, true, false, false]);
+        assert_eq!(sequence(3), vec![true, true]);
+    }
+
+    #[test]
+    fn paged_collectors_only_take_their_own_ids() {
+        for id in ["vote", "map_page:2", "map_select:0", "map_rank:1"] {
+            assert!(map_vote_id(id), "{}", id);
+        }
+        for id in [
+            "voted",
+            "veto:de_dust2",
+            "runoff_select:0",
+            "history_next",
+            "team_a",
+        ] {
+            assert!(!map_vote_id(id), "{}", id);
+        }
+        assert!(runoff_id("runoff_page:1") && runoff_id("runoff_select:0"));
+        assert!(!runoff_id("map_page:1") && !runoff_id("vote"));
+    }
+}
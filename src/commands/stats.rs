@@ -1,7 +1,7 @@
 use poise::command;
 
 use crate::{
-    utils::{format_stats, get_api_client, Stats},
+    utils::{format_stats, get_api_client, steam_id_2, Stats},
     Context,
 };
 use anyhow::Result;
@@ -34,7 +34,7 @@ pub(crate) async fn stats(
     #[description = "Map name"] map: Option<String>,
 ) -> Result<()> {
     let config = &context.data().config;
-    let Some(api_config)  = &config.scrimbot_api_config else {
+    let Some(api_config) = &config.scrimbot_api_config else {
         context
             .say("The scrimbot-api integration has not been configured")
             .await?;
@@ -48,17 +48,16 @@ pub(crate) async fn stats(
             .await?;
         return Ok(());
     };
-    let mut steam_id = steam_id.clone();
-    steam_id.replace_range(6..7, "1");
+    let steam_id = steam_id_2(*steam_id);
     let mut options = Vec::new();
     let mut print_map = false;
     if let Some(type_option) = type_option {
-        match &type_option.as_str() {
-            &"maps" => {
+        match type_option.as_str() {
+            "maps" => {
                 options.push(("steamid", steam_id));
                 print_map = true;
             }
-            &"top10" => {
+            "top10" => {
                 options.push(("mapCountLimit", "10".to_string()));
             }
             _ => (),
@@ -74,7 +73,7 @@ pub(crate) async fn stats(
         options.push(("map", map_option.to_string()));
     };
     let resp = client
-        .get(&format!("{}/stats", &api_config.scrimbot_api_url))
+        .get(format!("{}/stats", &api_config.scrimbot_api_url))
         .query(options.as_slice())
         .send()
         .await
@@ -1,12 +1,10 @@
 use anyhow::Result;
 use poise::command;
-use regex::Regex;
+use poise::serenity_prelude::User;
 use serenity::utils::MessageBuilder;
 
-use crate::{
-    utils::{self, write_to_file},
-    Context,
-};
+use crate::steam_login::{login_url, LOGIN_TIMEOUT_MINUTES};
+use crate::{utils, Context};
 
 #[command(
     slash_command,
@@ -16,36 +14,235 @@ use crate::{
 )]
 pub(crate) async fn steam_id(
     context: Context<'_>,
-    #[description = "Your SteamID i.e. STEAM_0:1:12345678"] steam_id: String,
+    #[description = "Your SteamID, SteamID64, SteamID3 or steamcommunity.com/profiles/ link"]
+    steam_id: String,
 ) -> Result<()> {
-    let steam_id_regex = Regex::new("^STEAM_[0-5]:[01]:\\d+$").unwrap();
-    if !steam_id_regex.is_match(&steam_id) {
-        context.say("Invalid SteamID formatting. Please follow this example: `/steamid STEAM_0:1:12345678`").await?;
+    let Some(steam_id_64) = utils::parse_steam_id(&steam_id) else {
+        context.say("Invalid SteamID. Please use one of `STEAM_0:1:12345678`, `76561197984957085`, `[U:1:24691357]` or `https://steamcommunity.com/profiles/76561197984957085`. Custom `/id/` profile links are not supported.").await?;
         return Ok(());
+    };
+    match context.data().storage.steam_id_owner(steam_id_64)? {
+        Some(owner) if owner == context.author().id.0 => {
+            context
+                .say(format!("`{}` is already your SteamID", steam_id_64))
+                .await?;
+            return Ok(());
+        }
+        Some(_) => {
+            context.say("This SteamID is already linked to another Discord user. If it is your account, please contact a moderator, they can resolve this with `/admin steamid`.").await?;
+            return Ok(());
+        }
+        None => {}
     }
-    let steam_ids = {
+    context
+        .data()
+        .storage
+        .set_steam_id(*context.author().id.as_u64(), steam_id_64, false)?;
+    {
         let mut steam_ids = context.data().steam_id_cache.lock().await;
-        steam_ids.insert(*context.author().id.as_u64(), String::from(&steam_id));
-        steam_ids.clone()
-    };
-    write_to_file(
-        String::from("data/steam-ids.json"),
-        serde_json::to_string(&steam_ids).unwrap(),
-    )
-    .await;
-    let steamid_64 = utils::convert_steamid_to_64(&steam_id);
+        steam_ids.insert(*context.author().id.as_u64(), steam_id_64);
+    }
     let response = MessageBuilder::new()
         .push("Updated steamid for ")
         .mention(context.author())
         .push(" to `")
-        .push(&steam_id)
+        .push(steam_id_64)
         .push("`\n")
         .push_line("Your steam community profile (please double check this is correct):")
-        .push_line(format!(
-            "https://steamcommunity.com/profiles/{}",
-            steamid_64
-        ))
+        .push_line(profile_url(steam_id_64))
         .build();
     context.say(response).await?;
     Ok(())
 }
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "linksteam",
+    description_localized("en-US", "Link a verified SteamID by signing in through Steam")
+)]
+pub(crate) async fn link_steam(context: Context<'_>) -> Result<()> {
+    let Some(url) = login_url(context.data(), context.author().id.0).await? else {
+        context
+            .say("Steam sign-in is not set up for this bot, please use `/steamid` instead")
+            .await?;
+        return Ok(());
+    };
+    let message = format!(
+        "Sign in through Steam to link your account. The link works once and expires in {} minutes:\n{}",
+        LOGIN_TIMEOUT_MINUTES, url
+    );
+    let response = match context
+        .author()
+        .direct_message(context, |m| m.content(&message))
+        .await
+    {
+        Ok(_) => "Sent you a Steam sign-in link, please check your DMs".to_string(),
+        // DMs are closed, the reply is only visible to the author
+        Err(_) => message,
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "steamid",
+    subcommands("set_steam_id", "unlink", "lookup")
+)]
+pub(crate) async fn admin_steam_id(_context: Context<'_>) -> Result<()> {
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    rename = "set",
+    description_localized("en-US", "Link a SteamID to a user, unlinking it from anyone else")
+)]
+pub(crate) async fn set_steam_id(
+    context: Context<'_>,
+    #[description = "User"] user: User,
+    #[description = "SteamID, SteamID64, SteamID3 or steamcommunity.com/profiles/ link"]
+    steam_id: String,
+) -> Result<()> {
+    let Some(steam_id_64) = utils::parse_steam_id(&steam_id) else {
+        context.say("Invalid SteamID").await?;
+        return Ok(());
+    };
+    let storage = &context.data().storage;
+    if let Some(owner) = storage.steam_id_owner(steam_id_64)? {
+        if owner != user.id.0 {
+            if let Some(playing_in) = context.data().playing_in(owner).await? {
+                let response = format!(
+                    "`{}` is linked to <@{}>, it can't be moved while they are in {}",
+                    steam_id_64, owner, playing_in
+                );
+                context.say(response).await?;
+                return Ok(());
+            }
+        }
+    }
+    let mut response = MessageBuilder::new();
+    response
+        .push("Linked `")
+        .push(steam_id_64)
+        .push("` to ")
+        .mention(&user);
+    let mut steam_ids = context.data().steam_id_cache.lock().await;
+    if let Some(owner) = storage.steam_id_owner(steam_id_64)? {
+        if owner != user.id.0 {
+            storage.remove_steam_id(owner)?;
+            steam_ids.remove(&owner);
+            response.push(format!(", it was unlinked from <@{}>", owner));
+        }
+    }
+    storage.set_steam_id(user.id.0, steam_id_64, false)?;
+    steam_ids.insert(user.id.0, steam_id_64);
+    drop(steam_ids);
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Remove the SteamID of a user")
+)]
+pub(crate) async fn unlink(context: Context<'_>, #[description = "User"] user: User) -> Result<()> {
+    if let Some(playing_in) = context.data().playing_in(user.id.0).await? {
+        let response = MessageBuilder::new()
+            .mention(&user)
+            .push(format!(
+                " is in {}, their SteamID can't be removed until they are done",
+                playing_in
+            ))
+            .build();
+        context.say(response).await?;
+        return Ok(());
+    }
+    let removed = context.data().storage.remove_steam_id(user.id.0)?;
+    context
+        .data()
+        .steam_id_cache
+        .lock()
+        .await
+        .remove(&user.id.0);
+    let response = match removed {
+        true => MessageBuilder::new()
+            .push("Unlinked the SteamID of ")
+            .mention(&user)
+            .build(),
+        false => MessageBuilder::new()
+            .mention(&user)
+            .push(" has no SteamID")
+            .build(),
+    };
+    context.say(response).await?;
+    Ok(())
+}
+
+#[command(
+    slash_command,
+    guild_only,
+    ephemeral,
+    description_localized("en-US", "Show the SteamID of a user, or the user of a SteamID")
+)]
+pub(crate) async fn lookup(
+    context: Context<'_>,
+    #[description = "User"] user: Option<User>,
+    #[description = "SteamID, SteamID64, SteamID3 or steamcommunity.com/profiles/ link"]
+    steam_id: Option<String>,
+) -> Result<()> {
+    let mut response = MessageBuilder::new();
+    if let Some(user) = &user {
+        let steam_id = context
+            .data()
+            .steam_id_cache
+            .lock()
+            .await
+            .get(&user.id.0)
+            .copied();
+        match steam_id {
+            Some(steam_id) => {
+                let verified = context.data().storage.steam_id_verified(user.id.0)?;
+                response
+                    .mention(user)
+                    .push(" is linked to `")
+                    .push(steam_id)
+                    .push("`")
+                    .push_line(if verified { " (verified)" } else { "" })
+                    .push_line(profile_url(steam_id))
+            }
+            None => response.mention(user).push_line(" has no SteamID"),
+        };
+    }
+    if let Some(steam_id) = &steam_id {
+        match utils::parse_steam_id(steam_id) {
+            Some(steam_id) => {
+                response.push("`").push(steam_id).push("` ");
+                match context.data().storage.steam_id_owner(steam_id)? {
+                    Some(owner) => response.push(format!("is linked to <@{}>", owner)),
+                    None => response.push("is not linked to anyone"),
+                };
+            }
+            None => {
+                response.push("Invalid SteamID");
+            }
+        }
+    }
+    if user.is_none() && steam_id.is_none() {
+        response.push("Provide a user or a SteamID to look up");
+    }
+    context.say(response.build()).await?;
+    Ok(())
+}
+
+fn profile_url(steam_id_64: u64) -> String {
+    format!("https://steamcommunity.com/profiles/{}", steam_id_64)
+}
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use poise::command;
 
-use crate::{utils::write_to_file, Context};
+use crate::Context;
 
 #[command(
     slash_command,
@@ -22,16 +22,14 @@ pub(crate) async fn teamname(
             .await?;
         return Ok(());
     }
-    let team_names = {
+    context
+        .data()
+        .storage
+        .set_team_name(*context.author().id.as_u64(), &team_name)?;
+    {
         let mut team_names = context.data().team_names.lock().await;
         team_names.insert(*context.author().id.as_u64(), String::from(&team_name));
-        team_names.clone()
-    };
-    write_to_file(
-        String::from("data/teamnames.json"),
-        serde_json::to_string(&team_names).unwrap(),
-    )
-    .await;
+    }
     context
         .say(&format!("Team name successfully set to `{}`", &team_name))
         .await?;
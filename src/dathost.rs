@@ -1,4 +1,4 @@
-use crate::commands::start::{ServerInfoResponse, StartMatch};
+use crate::commands::start::{MatchInfoResponse, ServerInfoResponse, StartMatch};
 use crate::Config;
 use base64::engine::general_purpose;
 use base64::Engine;
@@ -32,20 +32,31 @@ impl DathostClient {
         Ok(Self(client))
     }
     pub async fn get_server(&self, server_id: &String) -> Result<ServerInfoResponse> {
-        Ok(self
-            .0
-            .get(&format!(
+        self.0
+            .get(format!(
                 "https://dathost.net/api/0.1/game-servers/{server_id}"
             ))
             .send()
             .await?
             .json()
-            .await?)
+            .await
+    }
+
+    pub async fn get_match(&self, match_id: &str) -> Result<MatchInfoResponse> {
+        self.0
+            .get(format!(
+                "https://dathost.net/api/0.1/cs2-matches/{match_id}"
+            ))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
     }
 
     pub async fn start_match(&self, body: &StartMatch) -> Result<Response> {
         self.0
-            .post(&"https://dathost.net/api/0.1/cs2-matches".to_string())
+            .post("https://dathost.net/api/0.1/cs2-matches".to_string())
             .json(&body)
             .send()
             .await
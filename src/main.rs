@@ -1,23 +1,40 @@
 use crate::commands::admin::admin;
+use crate::commands::config::Setting;
+use crate::commands::history::history;
 use crate::commands::maps::maps;
-use crate::commands::queue::queue;
+use crate::commands::panel::handle_panel_interaction;
+use crate::commands::queue::{expire_queue_entries, queue};
+use crate::commands::series::watch_series;
+use anyhow::anyhow;
 use anyhow::Error;
 use anyhow::Result;
-use commands::start::start;
+use commands::start::{resume_setups, start};
 use commands::stats::stats;
-use commands::steamid::steam_id;
+use commands::steamid::{link_steam, steam_id};
 use commands::teamname::teamname;
 use dotenvy::dotenv;
 use futures::lock::Mutex;
 use poise::{builtins::create_application_commands, Event, Framework, FrameworkOptions};
 use serde::{Deserialize, Serialize};
+use serenity::model::application::interaction::Interaction;
 use serenity::model::gateway::GatewayIntents;
 use serenity::model::user::User;
 use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use steam_login::{serve_steam_login, PendingLogin};
+use storage::{
+    import_json_files, MapEntry, MapPool, SqliteStorage, Storage, DEFAULT_MAP_POOL, DEFAULT_QUEUE,
+};
+use webhook::serve_webhook;
 
 mod commands;
 mod dathost;
+mod steam_login;
+mod storage;
 mod utils;
+mod webhook;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -25,7 +42,74 @@ pub struct Config {
     discord: DiscordConfig,
     post_setup_msg: Option<String>,
     autoclear_hour: Option<u32>,
+    database_path: Option<String>,
     scrimbot_api_config: Option<ScrimbotApiConfig>,
+    steam_login: Option<SteamLoginConfig>,
+    webhook: Option<WebhookConfig>,
+    /// Guild that state stored before it was kept per guild is moved to.
+    /// Defaults to the only guild when the bot is in just one.
+    legacy_guild_id: Option<u64>,
+    #[serde(default)]
+    guilds: HashMap<u64, GuildConfig>,
+}
+
+/// Per-guild overrides of the non-secret settings in [`Config`]. Unset fields
+/// fall back to the global value.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct GuildConfig {
+    post_setup_msg: Option<String>,
+    autoclear_hour: Option<u32>,
+    team_a_channel_id: Option<u64>,
+    team_b_channel_id: Option<u64>,
+    emote_ct_id: Option<u64>,
+    emote_t_id: Option<u64>,
+    emote_ct_name: Option<String>,
+    emote_t_name: Option<String>,
+    assign_role_id: Option<u64>,
+    require_verified_steam_id: Option<bool>,
+    /// Captains ban maps after the draft instead of everyone voting.
+    map_veto: Option<bool>,
+    /// Which captain bans on each veto turn, e.g. `ABBA`, repeated as needed.
+    veto_order: Option<String>,
+    /// Maps played in this many of the last matches are left out of votes.
+    map_cooldown_matches: Option<u32>,
+    /// Maps played within this many hours are left out of votes.
+    map_cooldown_hours: Option<u32>,
+    /// Players rank maps and the vote is decided by instant-runoff.
+    ranked_map_vote: Option<bool>,
+    /// Matches are a best of three, the captains ban and pick the maps.
+    best_of_three: Option<bool>,
+}
+
+impl Config {
+    /// The effective settings for `guild_id`.
+    pub fn guild(&self, guild_id: u64) -> GuildConfig {
+        let overrides = self.guilds.get(&guild_id).cloned().unwrap_or_default();
+        GuildConfig {
+            post_setup_msg: overrides.post_setup_msg.or(self.post_setup_msg.clone()),
+            autoclear_hour: overrides.autoclear_hour.or(self.autoclear_hour),
+            team_a_channel_id: overrides
+                .team_a_channel_id
+                .or(self.discord.team_a_channel_id),
+            team_b_channel_id: overrides
+                .team_b_channel_id
+                .or(self.discord.team_b_channel_id),
+            emote_ct_id: overrides.emote_ct_id.or(self.discord.emote_ct_id),
+            emote_t_id: overrides.emote_t_id.or(self.discord.emote_t_id),
+            emote_ct_name: overrides
+                .emote_ct_name
+                .or(self.discord.emote_ct_name.clone()),
+            emote_t_name: overrides.emote_t_name.or(self.discord.emote_t_name.clone()),
+            assign_role_id: overrides.assign_role_id.or(self.discord.assign_role_id),
+            require_verified_steam_id: overrides.require_verified_steam_id,
+            map_veto: overrides.map_veto,
+            veto_order: overrides.veto_order,
+            map_cooldown_matches: overrides.map_cooldown_matches,
+            map_cooldown_hours: overrides.map_cooldown_hours,
+            ranked_map_vote: overrides.ranked_map_vote,
+            best_of_three: overrides.best_of_three,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -34,12 +118,45 @@ pub struct ScrimbotApiConfig {
     scrimbot_api_token: String,
 }
 
+/// Verified SteamID linking, see `/linksteam`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SteamLoginConfig {
+    /// Address the callback endpoint listens on, e.g. `0.0.0.0:8080`.
+    listen_address: String,
+    /// URL under which the endpoint is reachable from the internet.
+    public_url: String,
+}
+
+/// Endpoint DatHost calls when a match ends, see `webhook::serve_webhook`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Address the endpoint listens on, e.g. `0.0.0.0:8081`.
+    listen_address: String,
+    /// URL under which the endpoint is reachable from the internet.
+    public_url: String,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct DathostConfig {
     username: String,
     password: String,
     match_end_url: Option<String>,
-    server_id: String,
+    server_id: Option<String>,
+    #[serde(default)]
+    server_ids: Vec<String>,
+}
+
+impl DathostConfig {
+    /// Every game server matches can be launched on, `server_id` first.
+    pub fn servers(&self) -> Vec<String> {
+        let mut servers: Vec<String> = self.server_id.iter().cloned().collect();
+        for server_id in &self.server_ids {
+            if !servers.contains(server_id) {
+                servers.push(server_id.clone());
+            }
+        }
+        servers
+    }
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -55,7 +172,7 @@ pub struct DiscordConfig {
     assign_role_id: Option<u64>,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default, Serialize, Deserialize)]
 pub struct Draft {
     captain_a: Option<User>,
     captain_b: Option<User>,
@@ -63,17 +180,41 @@ pub struct Draft {
     team_b: Vec<User>,
     team_b_start_side: String,
     current_picker: Option<User>,
-    map_votes: HashMap<User, Vec<String>>,
+    map_votes: HashMap<u64, Vec<String>>,
+    /// Players who pressed Vote, the map vote closes once everyone did.
+    #[serde(default)]
+    map_voted: Vec<u64>,
     selected_map: String,
+    /// Maps banned so far in a map veto, in order.
+    #[serde(default)]
+    veto: Vec<VetoBan>,
+    /// Maps of a best of three in the order they are played.
+    #[serde(default)]
+    series_maps: Vec<String>,
+    /// Team B's starting side on each of `series_maps`, as far as picked.
+    #[serde(default)]
+    series_sides: Vec<String>,
 }
 
-#[derive(Clone, PartialEq)]
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VetoBan {
+    map: String,
+    captain: User,
+    /// Banned at random because the captain ran out of time.
+    auto: bool,
+    /// Picked for a best of three instead of banned.
+    #[serde(default)]
+    pick: bool,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub enum State {
     Queue,
     MapPick,
     CaptainPick,
     DraftTypePick,
     Draft,
+    MapVeto,
     SidePick,
     Ready,
 }
@@ -107,19 +248,165 @@ pub enum Command {
 }
 
 pub struct Data {
+    pub config: Config,
+    pub storage: Arc<dyn Storage>,
+    /// SteamID64s of the users.
+    pub steam_id_cache: Mutex<HashMap<u64, u64>>,
+    pub team_names: Mutex<HashMap<u64, String>>,
+    pub lobbies: Mutex<HashMap<i64, Arc<Lobby>>>,
+    /// Held while a server is picked and reserved for a launch, so two lobbies
+    /// cannot pick the same server.
+    pub server_pool: Mutex<()>,
+    /// Set once the queue expiry task runs, `Ready` fires again on reconnects.
+    pub queue_expiry_started: AtomicBool,
+    /// Steam sign-in links by token.
+    pub steam_logins: Mutex<HashMap<String, PendingLogin>>,
+    pub steam_login_started: AtomicBool,
+    pub webhook_started: AtomicBool,
+    /// Set once the task launching the maps of best of three series runs.
+    pub series_watch_started: AtomicBool,
+    /// Held while a series records a result or launches a map, see
+    /// `commands::series::lock_series`.
+    pub series_locks: Mutex<HashMap<i64, Arc<Mutex<()>>>>,
+}
+
+/// Players and setup state of a single named queue.
+pub struct Lobby {
+    pub queue_id: i64,
+    pub guild_id: u64,
+    pub name: String,
+    pub storage: Arc<dyn Storage>,
     pub user_queue: Mutex<Vec<User>>,
+    pub waitlist: Mutex<Vec<User>>,
+    /// Party membership, mapped to the party leader.
+    pub parties: Mutex<HashMap<u64, u64>>,
     pub ready_queue: Mutex<Vec<User>>,
     pub queue_messages: Mutex<HashMap<u64, String>>,
-    pub config: Config,
-    pub steam_id_cache: Mutex<HashMap<u64, String>>,
-    pub team_names: Mutex<HashMap<u64, String>>,
     pub state: Mutex<State>,
-    pub maps: Mutex<Vec<String>>,
+    pub map_pools: Mutex<Vec<MapPool>>,
+    /// Pool activated with `/admin pool activate`, see [`Lobby::map_pool`].
+    pub active_map_pool: Mutex<Option<String>>,
     pub draft: Mutex<Draft>,
     pub team_size: Mutex<u8>,
 }
 
-type Context<'a> = poise::Context<'a, Data, Error>;
+impl Data {
+    /// The effective settings for `guild_id`: `config.yaml` with the changes
+    /// made through `/admin config` on top.
+    pub fn guild_config(&self, guild_id: u64) -> Result<GuildConfig> {
+        let mut config = self.config.guild(guild_id);
+        for (key, value) in self.storage.guild_settings(guild_id)? {
+            let Some(setting) = Setting::from_key(&key) else {
+                continue;
+            };
+            if let Err(err) = setting.apply(&mut config, &value) {
+                log::warn!("Ignoring invalid setting {}={}: {}", key, value, err);
+            }
+        }
+        Ok(config)
+    }
+
+    /// The lobby of the queue bound to `channel_id`, or the guild's default queue
+    /// if the channel is not bound to one.
+    pub async fn lobby(&self, guild_id: u64, channel_id: u64) -> Result<Arc<Lobby>> {
+        let queue_id = match self.storage.channel_queue(channel_id)? {
+            Some(queue_id) => queue_id,
+            None => self.storage.create_queue(guild_id, DEFAULT_QUEUE)?,
+        };
+        self.lobby_by_id(queue_id).await
+    }
+
+    /// Where `user_id` plays, if anywhere: the queue or setup of a loaded lobby,
+    /// or a best of three with maps left to launch. Their SteamID has to stay
+    /// linked until they are done.
+    pub async fn playing_in(&self, user_id: u64) -> Result<Option<String>> {
+        let lobbies: Vec<_> = self.lobbies.lock().await.values().cloned().collect();
+        for lobby in lobbies {
+            if lobby
+                .user_queue
+                .lock()
+                .await
+                .iter()
+                .any(|u| u.id.0 == user_id)
+            {
+                return Ok(Some(format!("the `{}` queue", lobby.name)));
+            }
+        }
+        for series in self.storage.active_series()? {
+            let draft = &series.draft;
+            if draft
+                .team_a
+                .iter()
+                .chain(&draft.team_b)
+                .any(|u| u.id.0 == user_id)
+            {
+                return Ok(Some("a best of three".to_string()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The lobby of `queue_id`, loaded from storage on first use.
+    pub async fn lobby_by_id(&self, queue_id: i64) -> Result<Arc<Lobby>> {
+        let mut lobbies = self.lobbies.lock().await;
+        if let Some(lobby) = lobbies.get(&queue_id) {
+            return Ok(lobby.clone());
+        }
+        let storage = self.storage.clone();
+        let Some(info) = storage.queue_info(queue_id)? else {
+            return Err(anyhow!("Queue {} does not exist", queue_id));
+        };
+        let lobby = Arc::new(Lobby {
+            queue_id,
+            guild_id: info.guild_id,
+            name: info.name,
+            state: Mutex::new(State::Queue),
+            draft: Mutex::new(Draft::default()),
+            map_pools: Mutex::new(storage.map_pools(queue_id)?),
+            active_map_pool: Mutex::new(storage.active_map_pool(queue_id)?),
+            queue_messages: Mutex::new(storage.queue_messages(queue_id)?),
+            ready_queue: Mutex::new(Vec::new()),
+            user_queue: Mutex::new(storage.queue(queue_id)?),
+            waitlist: Mutex::new(storage.waitlist(queue_id)?),
+            parties: Mutex::new(storage.parties(queue_id)?),
+            team_size: Mutex::new(storage.team_size(queue_id)?.unwrap_or(5)),
+            storage,
+        });
+        lobbies.insert(queue_id, lobby.clone());
+        Ok(lobby)
+    }
+}
+
+impl Lobby {
+    /// The map pool votes use: the activated pool, else the pool for the
+    /// current team size, else the `default` pool.
+    pub async fn map_pool(&self) -> Option<MapPool> {
+        let active = self.active_map_pool.lock().await.clone();
+        let team_size = *self.team_size.lock().await;
+        let pools = self.map_pools.lock().await;
+        active
+            .and_then(|name| pools.iter().find(|p| p.name == name))
+            .or_else(|| pools.iter().find(|p| p.team_size == Some(team_size)))
+            .or_else(|| pools.iter().find(|p| p.name == DEFAULT_MAP_POOL))
+            .cloned()
+    }
+
+    /// Maps of the pool in use.
+    pub async fn maps(&self) -> Vec<MapEntry> {
+        self.map_pool()
+            .await
+            .map(|pool| pool.maps)
+            .unwrap_or_default()
+    }
+
+    /// Reloads the map pools after a change in storage.
+    pub async fn reload_map_pools(&self) -> Result<()> {
+        *self.map_pools.lock().await = self.storage.map_pools(self.queue_id)?;
+        Ok(())
+    }
+}
+
+type Context<'a> = poise::Context<'a, Arc<Data>, Error>;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -131,6 +418,13 @@ async fn main() -> Result<()> {
         .init();
 
     let config = read_config().await?;
+    let storage: Arc<dyn Storage> = Arc::new(SqliteStorage::open(
+        config
+            .database_path
+            .as_deref()
+            .unwrap_or("data/scrimbot.db"),
+    )?);
+    import_json_files(storage.as_ref(), "data")?;
 
     let framework = Framework::<_, Error>::builder()
         .options(FrameworkOptions {
@@ -138,12 +432,14 @@ async fn main() -> Result<()> {
                 queue(),
                 admin(),
                 steam_id(),
+                link_steam(),
                 teamname(),
                 start(),
                 stats(),
                 maps(),
+                history(),
             ],
-            event_handler: move |context, event, framework, _data| {
+            event_handler: move |context, event, framework, data| {
                 Box::pin(async move {
                     if let Event::Ready { data_about_bot } = event {
                         let commands_builder =
@@ -167,6 +463,42 @@ async fn main() -> Result<()> {
                                 guild.name
                             );
                         }
+                        let legacy_guild_id = match data_about_bot.guilds.as_slice() {
+                            [guild] => data.config.legacy_guild_id.or(Some(guild.id.0)),
+                            _ => data.config.legacy_guild_id,
+                        };
+                        if let Some(guild_id) = legacy_guild_id {
+                            if data.storage.adopt_unscoped(guild_id)? {
+                                log::info!("Moved state without a guild to guild {}", guild_id);
+                            }
+                        }
+                        // the tasks run until the bot stops, nothing waits for them
+                        let (serenity, shared) = (context.clone(), data.clone());
+                        spawn_task("resume setups", async move {
+                            resume_setups(&serenity, &shared).await
+                        });
+                        let (serenity, shared) = (context.clone(), data.clone());
+                        spawn_task("queue expiry", async move {
+                            expire_queue_entries(&serenity, &shared).await
+                        });
+                        spawn_task(
+                            "Steam sign-in",
+                            serve_steam_login(context.clone(), data.clone()),
+                        );
+                        spawn_task(
+                            "match end webhook",
+                            serve_webhook(context.clone(), data.clone()),
+                        );
+                        let (serenity, shared) = (context.clone(), data.clone());
+                        spawn_task("series watch", async move {
+                            watch_series(&serenity, &shared).await
+                        });
+                    }
+                    if let Event::InteractionCreate {
+                        interaction: Interaction::MessageComponent(mci),
+                    } = event
+                    {
+                        handle_panel_interaction(context, data, mci).await?;
                     }
                     Ok(())
                 })
@@ -177,27 +509,20 @@ async fn main() -> Result<()> {
         .intents(GatewayIntents::empty())
         .setup(move |_context, _ready, _framework| {
             Box::pin(async move {
-                Ok(Data {
-                    state: Mutex::new(State::Queue),
+                Ok(Arc::new(Data {
                     config: read_config().await?,
-                    draft: Mutex::new(Draft {
-                        captain_a: None,
-                        captain_b: None,
-                        current_picker: None,
-                        team_a: Vec::new(),
-                        team_b: Vec::new(),
-                        team_b_start_side: String::from(""),
-                        map_votes: HashMap::new(),
-                        selected_map: String::new(),
-                    }),
-                    maps: Mutex::new(read_maps().await?),
-                    queue_messages: Mutex::new(read_queue_msgs().await?),
-                    steam_id_cache: Mutex::new(read_steam_ids().await?),
-                    team_names: Mutex::new(read_teamnames().await?),
-                    ready_queue: Mutex::new(Vec::new()),
-                    user_queue: Mutex::new(read_queue().await?),
-                    team_size: Mutex::new(5),
-                })
+                    steam_id_cache: Mutex::new(storage.steam_ids()?),
+                    team_names: Mutex::new(storage.team_names()?),
+                    lobbies: Mutex::new(HashMap::new()),
+                    server_pool: Mutex::new(()),
+                    queue_expiry_started: AtomicBool::new(false),
+                    steam_logins: Mutex::new(HashMap::new()),
+                    steam_login_started: AtomicBool::new(false),
+                    webhook_started: AtomicBool::new(false),
+                    series_watch_started: AtomicBool::new(false),
+                    series_locks: Mutex::new(HashMap::new()),
+                    storage,
+                }))
             })
         });
 
@@ -209,58 +534,17 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
+/// Runs a background task, logging its error since nothing awaits it.
+fn spawn_task(name: &'static str, task: impl Future<Output = Result<()>> + Send + 'static) {
+    tokio::spawn(async move {
+        if let Err(err) = task.await {
+            log::error!("Background task {} failed: {:?}", name, err);
+        }
+    });
+}
+
 pub async fn read_config() -> Result<Config, serde_yaml::Error> {
     let yaml = std::fs::read_to_string("config/config.yaml").unwrap();
     let config: Config = serde_yaml::from_str(&yaml)?;
     Ok(config)
 }
-
-async fn read_steam_ids() -> Result<HashMap<u64, String>, serde_json::Error> {
-    if std::fs::read("data/steam-ids.json").is_ok() {
-        let json_str = std::fs::read_to_string("data/steam-ids.json").unwrap();
-        let json = serde_json::from_str(&json_str).unwrap();
-        Ok(json)
-    } else {
-        Ok(HashMap::new())
-    }
-}
-
-async fn read_teamnames() -> Result<HashMap<u64, String>, serde_json::Error> {
-    if std::fs::read("data/teamnames.json").is_ok() {
-        let json_str = std::fs::read_to_string("data/teamnames.json").unwrap();
-        let json = serde_json::from_str(&json_str).unwrap();
-        Ok(json)
-    } else {
-        Ok(HashMap::new())
-    }
-}
-
-async fn read_maps() -> Result<Vec<String>, serde_json::Error> {
-    if std::fs::read("data/maps.json").is_ok() {
-        let json_str = std::fs::read_to_string("data/maps.json").unwrap();
-        let json = serde_json::from_str(&json_str).unwrap();
-        Ok(json)
-    } else {
-        Ok(Vec::new())
-    }
-}
-
-async fn read_queue() -> Result<Vec<User>, serde_json::Error> {
-    if std::fs::read("data/queue.json").is_ok() {
-        let json_str = std::fs::read_to_string("data/queue.json").unwrap();
-        let json = serde_json::from_str(&json_str).unwrap();
-        Ok(json)
-    } else {
-        Ok(Vec::new())
-    }
-}
-
-async fn read_queue_msgs() -> Result<HashMap<u64, String>, serde_json::Error> {
-    if std::fs::read("data/queue-messages.json").is_ok() {
-        let json_str = std::fs::read_to_string("data/queue-messages.json").unwrap();
-        let json: HashMap<u64, String> = serde_json::from_str(&json_str).unwrap();
-        Ok(json)
-    } else {
-        Ok(HashMap::new())
-    }
-}
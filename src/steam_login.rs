@@ -0,0 +1,273 @@
+use crate::{Data, SteamLoginConfig};
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use poise::serenity_prelude::{Context as SerenityContext, UserId};
+use rand::distributions::{Alphanumeric, DistString};
+use reqwest::Url;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const STEAM_OPENID_URL: &str = "https://steamcommunity.com/openid/login";
+const OPENID_NS: &str = "http://specs.openid.net/auth/2.0";
+const IDENTIFIER_SELECT: &str = "http://specs.openid.net/auth/2.0/identifier_select";
+const CLAIMED_ID_PREFIX: &str = "https://steamcommunity.com/openid/id/";
+const CALLBACK_PATH: &str = "/steam/callback";
+/// How long a sign-in link can be used.
+pub(crate) const LOGIN_TIMEOUT_MINUTES: i64 = 15;
+/// The callback is a single GET request, anything larger is not from Steam.
+const MAX_REQUEST_BYTES: usize = 8192;
+
+/// A sign-in link that was sent to a user and not used yet.
+pub struct PendingLogin {
+    user_id: u64,
+    created_at: DateTime<Utc>,
+}
+
+/// Creates a single use Steam sign-in link for the user, or `None` if
+/// `steam_login` is not configured.
+pub(crate) async fn login_url(data: &Data, user_id: u64) -> Result<Option<String>> {
+    let Some(config) = &data.config.steam_login else {
+        return Ok(None);
+    };
+    let token = Alphanumeric.sample_string(&mut rand::thread_rng(), 32);
+    let now = Utc::now();
+    {
+        let mut logins = data.steam_logins.lock().await;
+        logins.retain(|_, login| !expired(login, now));
+        logins.insert(
+            token.clone(),
+            PendingLogin {
+                user_id,
+                created_at: now,
+            },
+        );
+    }
+    let url = Url::parse_with_params(
+        STEAM_OPENID_URL,
+        &[
+            ("openid.ns", OPENID_NS),
+            ("openid.mode", "checkid_setup"),
+            ("openid.return_to", &return_to(config, &token)),
+            ("openid.realm", config.public_url.trim_end_matches('/')),
+            ("openid.identity", IDENTIFIER_SELECT),
+            ("openid.claimed_id", IDENTIFIER_SELECT),
+        ],
+    )?;
+    Ok(Some(url.to_string()))
+}
+
+fn return_to(config: &SteamLoginConfig, token: &str) -> String {
+    format!(
+        "{}{}?token={}",
+        config.public_url.trim_end_matches('/'),
+        CALLBACK_PATH,
+        token
+    )
+}
+
+fn expired(login: &PendingLogin, now: DateTime<Utc>) -> bool {
+    login.created_at + ChronoDuration::minutes(LOGIN_TIMEOUT_MINUTES) < now
+}
+
+/// Serves the endpoint Steam redirects to after a sign-in. Runs until the bot
+/// stops, does nothing if `steam_login` is not configured.
+pub(crate) async fn serve_steam_login(serenity: SerenityContext, data: Arc<Data>) -> Result<()> {
+    let Some(config) = &data.config.steam_login else {
+        return Ok(());
+    };
+    if data.steam_login_started.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(&config.listen_address).await?;
+    log::info!("Listening for Steam sign-ins on {}", config.listen_address);
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("Unable to accept connection: {}", err);
+                continue;
+            }
+        };
+        let (serenity, data) = (serenity.clone(), data.clone());
+        tokio::spawn(async move {
+            // a client that never finishes its request must not keep the task around
+            let handled = tokio::time::timeout(
+                Duration::from_secs(30),
+                handle_connection(&serenity, &data, stream),
+            )
+            .await;
+            match handled {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::warn!("Unable to answer Steam sign-in request: {}", err),
+                Err(_) => log::warn!("Steam sign-in request timed out"),
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    serenity: &SerenityContext,
+    data: &Data,
+    mut stream: TcpStream,
+) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(());
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let target = request
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("GET "))
+        .and_then(|line| line.split(' ').next());
+    let (status, body) = match target {
+        Some(target) if target.starts_with(CALLBACK_PATH) => {
+            match verify(serenity, data, target).await {
+                Ok(body) => ("200 OK", body),
+                Err(err) => {
+                    log::info!("Rejected Steam sign-in: {}", err);
+                    ("400 Bad Request", format!("Steam sign-in failed: {}", err))
+                }
+            }
+        }
+        _ => ("404 Not Found", "Not found".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Checks a callback from Steam and links the signed in account. Returns the
+/// page shown to the user.
+async fn verify(serenity: &SerenityContext, data: &Data, target: &str) -> Result<String> {
+    let config = data
+        .config
+        .steam_login
+        .as_ref()
+        .ok_or_else(|| anyhow!("Steam sign-in is not configured"))?;
+    let url = Url::parse(&format!("http://localhost{}", target))?;
+    let params: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let param = |key: &str| {
+        params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    };
+    let token = param("token").ok_or_else(|| anyhow!("the link is incomplete"))?;
+    let login = data
+        .steam_logins
+        .lock()
+        .await
+        .remove(token)
+        .ok_or_else(|| anyhow!("the link is unknown or was already used"))?;
+    if expired(&login, Utc::now()) {
+        bail!("the link expired, please use `/linksteam` again");
+    }
+    if param("openid.mode") != Some("id_res") {
+        bail!("the sign-in was canceled, please use `/linksteam` again");
+    }
+    if param("openid.op_endpoint") != Some(STEAM_OPENID_URL)
+        || param("openid.return_to") != Some(return_to(config, token).as_str())
+    {
+        bail!("the response was not meant for this link");
+    }
+    let claimed_id = param("openid.claimed_id").unwrap_or_default();
+    if param("openid.identity") != Some(claimed_id) {
+        bail!("the response has an invalid identity");
+    }
+    let steam_id: u64 = claimed_id
+        .strip_prefix(CLAIMED_ID_PREFIX)
+        .and_then(|id| id.parse().ok())
+        .ok_or_else(|| anyhow!("the response has an invalid identity"))?;
+
+    // only Steam can tell whether it signed these parameters
+    let mut check: Vec<(String, String)> = params
+        .iter()
+        .filter(|(k, _)| k.starts_with("openid.") && k != "openid.mode")
+        .cloned()
+        .collect();
+    check.push(("openid.mode".into(), "check_authentication".into()));
+    let body = reqwest::Client::new()
+        .post(STEAM_OPENID_URL)
+        .form(&check)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .text()
+        .await?;
+    if !body.lines().any(|line| line.trim() == "is_valid:true") {
+        bail!("Steam could not confirm the sign-in");
+    }
+    link(serenity, data, login.user_id, steam_id).await
+}
+
+async fn link(
+    serenity: &SerenityContext,
+    data: &Data,
+    user_id: u64,
+    steam_id: u64,
+) -> Result<String> {
+    let storage = &data.storage;
+    if let Some(owner) = storage.steam_id_owner(steam_id)? {
+        if owner != user_id
+            && !storage.steam_id_verified(owner)?
+            && data.playing_in(owner).await?.is_some()
+        {
+            bail!("this Steam account is linked to another Discord user who is queued or playing, please try again once they are done");
+        }
+    }
+    {
+        let mut steam_ids = data.steam_id_cache.lock().await;
+        if let Some(owner) = storage.steam_id_owner(steam_id)? {
+            if owner != user_id {
+                if storage.steam_id_verified(owner)? {
+                    bail!("this Steam account is verified by another Discord user, please contact a moderator");
+                }
+                // the sign-in proves ownership, an unverified claim gives way
+                storage.remove_steam_id(owner)?;
+                steam_ids.remove(&owner);
+                log::info!(
+                    "Unlinked SteamID {} from user {}, user {} verified it",
+                    steam_id,
+                    owner,
+                    user_id
+                );
+            }
+        }
+        storage.set_steam_id(user_id, steam_id, true)?;
+        steam_ids.insert(user_id, steam_id);
+    }
+    let message = format!(
+        "Your Steam account is verified and linked: https://steamcommunity.com/profiles/{}",
+        steam_id
+    );
+    let confirmation = async {
+        UserId(user_id)
+            .create_dm_channel(serenity)
+            .await?
+            .say(serenity, message)
+            .await
+    };
+    if let Err(err) = confirmation.await {
+        log::warn!(
+            "Unable to confirm Steam sign-in to user {}: {}",
+            user_id,
+            err
+        );
+    }
+    Ok("Your Steam account is verified, you can close this page and return to Discord.".into())
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+
+use super::{LegacyData, MapEntry, Storage};
+use crate::utils::parse_steam_id;
+
+/// Imports the `data/*.json` files written by earlier versions of the bot.
+///
+/// This runs once: the import is recorded in the database, so later boots (or a
+/// partially written json file from an old crash) never overwrite newer data.
+pub fn import_json_files<P: AsRef<Path>>(storage: &dyn Storage, dir: P) -> Result<()> {
+    if storage.legacy_imported()? {
+        return Ok(());
+    }
+    let dir = dir.as_ref();
+    let (steam_ids, unconverted_steam_ids) = read_steam_ids(&dir.join("steam-ids.json"))?;
+    let legacy = LegacyData {
+        steam_ids,
+        unconverted_steam_ids,
+        team_names: read_json(&dir.join("teamnames.json"))?,
+        maps: read_maps(&dir.join("maps.json"))?,
+        queue: read_json(&dir.join("queue.json"))?,
+        queue_messages: read_json(&dir.join("queue-messages.json"))?,
+    };
+    if storage.import_legacy(&legacy)? {
+        log::info!(
+            "Imported {} steam ids, {} team names, {} maps and {} queued users from {}",
+            legacy.steam_ids.len(),
+            legacy.team_names.len(),
+            legacy.maps.len(),
+            legacy.queue.len(),
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// Splits the `STEAM_X:Y:Z` values of the json file into SteamID64s and the
+/// values that are not a valid SteamID.
+fn read_steam_ids(path: &Path) -> Result<(HashMap<u64, u64>, HashMap<u64, String>)> {
+    let steam_ids: HashMap<u64, String> = read_json(path)?;
+    let mut converted = HashMap::new();
+    let mut unconverted = HashMap::new();
+    for (user_id, steam_id) in steam_ids {
+        match parse_steam_id(&steam_id) {
+            Some(steam_id) => {
+                converted.insert(user_id, steam_id);
+            }
+            None => {
+                unconverted.insert(user_id, steam_id);
+            }
+        }
+    }
+    Ok((converted, unconverted))
+}
+
+/// `maps.json` only has map names, they double as display names.
+fn read_maps(path: &Path) -> Result<Vec<MapEntry>> {
+    let maps: Vec<String> = read_json(path)?;
+    Ok(maps.iter().map(|name| MapEntry::new(name)).collect())
+}
+
+fn read_json<T: DeserializeOwned + Default>(path: &Path) -> Result<T> {
+    if !path.exists() {
+        return Ok(T::default());
+    }
+    let json_str = std::fs::read_to_string(path)
+        .with_context(|| format!("Unable to read {}", path.display()))?;
+    serde_json::from_str(&json_str).with_context(|| format!("Unable to parse {}", path.display()))
+}
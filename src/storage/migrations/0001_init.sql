@@ -0,0 +1,30 @@
+CREATE TABLE steam_ids (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    steam_id TEXT NOT NULL
+);
+
+CREATE TABLE team_names (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    team_name TEXT NOT NULL
+);
+
+CREATE TABLE maps (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    user_id INTEGER NOT NULL UNIQUE,
+    user TEXT NOT NULL
+);
+
+CREATE TABLE queue_messages (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    message TEXT NOT NULL
+);
+
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
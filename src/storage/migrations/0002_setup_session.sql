@@ -0,0 +1,9 @@
+CREATE TABLE setup_session (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    session TEXT NOT NULL
+);
+
+CREATE TABLE settings (
+    key TEXT PRIMARY KEY NOT NULL,
+    value TEXT NOT NULL
+);
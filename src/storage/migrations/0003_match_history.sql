@@ -0,0 +1,24 @@
+CREATE TABLE matches (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    dathost_match_id TEXT,
+    map TEXT NOT NULL,
+    team_a_name TEXT NOT NULL,
+    team_a_captain INTEGER NOT NULL,
+    team_b_name TEXT NOT NULL,
+    team_b_captain INTEGER NOT NULL,
+    team_b_start_side TEXT NOT NULL,
+    launched_by INTEGER,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX matches_map ON matches (map);
+
+CREATE TABLE match_players (
+    match_id INTEGER NOT NULL REFERENCES matches (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    team TEXT NOT NULL CHECK (team IN ('a', 'b')),
+    PRIMARY KEY (match_id, user_id)
+);
+
+CREATE INDEX match_players_user_id ON match_players (user_id);
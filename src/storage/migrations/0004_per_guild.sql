@@ -0,0 +1,57 @@
+-- Everything below is keyed by guild. Existing rows are assigned guild 0 until
+-- `adopt_unscoped` moves them to the bot's guild. Setup sessions already carry
+-- their guild id.
+
+CREATE TABLE maps_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (guild_id, name)
+);
+INSERT INTO maps_new (id, guild_id, name) SELECT id, 0, name FROM maps;
+DROP TABLE maps;
+ALTER TABLE maps_new RENAME TO maps;
+
+CREATE TABLE queue_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    UNIQUE (guild_id, user_id)
+);
+INSERT INTO queue_new (id, guild_id, user_id, user) SELECT id, 0, user_id, user FROM queue;
+DROP TABLE queue;
+ALTER TABLE queue_new RENAME TO queue;
+
+CREATE TABLE queue_messages_new (
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (guild_id, user_id)
+);
+INSERT INTO queue_messages_new (guild_id, user_id, message)
+    SELECT 0, user_id, message FROM queue_messages;
+DROP TABLE queue_messages;
+ALTER TABLE queue_messages_new RENAME TO queue_messages;
+
+CREATE TABLE setup_session_new (
+    guild_id INTEGER PRIMARY KEY NOT NULL,
+    session TEXT NOT NULL
+);
+INSERT INTO setup_session_new (guild_id, session)
+    SELECT json_extract(session, '$.guild_id'), session FROM setup_session;
+DROP TABLE setup_session;
+ALTER TABLE setup_session_new RENAME TO setup_session;
+
+CREATE TABLE guild_settings (
+    guild_id INTEGER NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (guild_id, key)
+);
+INSERT INTO guild_settings (guild_id, key, value)
+    SELECT 0, key, value FROM settings WHERE key = 'team_size';
+DELETE FROM settings WHERE key = 'team_size';
+
+ALTER TABLE matches ADD COLUMN guild_id INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX matches_guild_id ON matches (guild_id);
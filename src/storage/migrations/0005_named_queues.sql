@@ -0,0 +1,81 @@
+-- Queue state moves from being keyed by guild to being keyed by a named queue.
+-- Every guild gets a `default` queue holding its existing state; other queues
+-- are bound to channels through `queue_channels`.
+
+CREATE TABLE queues (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    UNIQUE (guild_id, name)
+);
+
+CREATE TABLE queue_channels (
+    channel_id INTEGER PRIMARY KEY NOT NULL,
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE
+);
+
+INSERT INTO queues (guild_id, name)
+    SELECT guild_id, 'default' FROM maps
+    UNION SELECT guild_id, 'default' FROM queue
+    UNION SELECT guild_id, 'default' FROM queue_messages
+    UNION SELECT guild_id, 'default' FROM guild_settings
+    UNION SELECT guild_id, 'default' FROM setup_session;
+
+CREATE TABLE maps_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    UNIQUE (queue_id, name)
+);
+INSERT INTO maps_new (id, queue_id, name)
+    SELECT m.id, q.id, m.name FROM maps m
+    JOIN queues q ON q.guild_id = m.guild_id AND q.name = 'default';
+DROP TABLE maps;
+ALTER TABLE maps_new RENAME TO maps;
+
+CREATE TABLE queue_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    UNIQUE (queue_id, user_id)
+);
+INSERT INTO queue_new (id, queue_id, user_id, user)
+    SELECT u.id, q.id, u.user_id, u.user FROM queue u
+    JOIN queues q ON q.guild_id = u.guild_id AND q.name = 'default';
+DROP TABLE queue;
+ALTER TABLE queue_new RENAME TO queue;
+
+CREATE TABLE queue_messages_new (
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    message TEXT NOT NULL,
+    PRIMARY KEY (queue_id, user_id)
+);
+INSERT INTO queue_messages_new (queue_id, user_id, message)
+    SELECT q.id, m.user_id, m.message FROM queue_messages m
+    JOIN queues q ON q.guild_id = m.guild_id AND q.name = 'default';
+DROP TABLE queue_messages;
+ALTER TABLE queue_messages_new RENAME TO queue_messages;
+
+CREATE TABLE setup_session_new (
+    queue_id INTEGER PRIMARY KEY NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    session TEXT NOT NULL
+);
+INSERT INTO setup_session_new (queue_id, session)
+    SELECT q.id, json_set(s.session, '$.queue_id', q.id) FROM setup_session s
+    JOIN queues q ON q.guild_id = s.guild_id AND q.name = 'default';
+DROP TABLE setup_session;
+ALTER TABLE setup_session_new RENAME TO setup_session;
+
+CREATE TABLE queue_settings (
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (queue_id, key)
+);
+INSERT INTO queue_settings (queue_id, key, value)
+    SELECT q.id, s.key, s.value FROM guild_settings s
+    JOIN queues q ON q.guild_id = s.guild_id AND q.name = 'default'
+    WHERE s.key = 'team_size';
+DELETE FROM guild_settings WHERE key = 'team_size';
@@ -0,0 +1,5 @@
+CREATE TABLE busy_servers (
+    server_id TEXT PRIMARY KEY NOT NULL,
+    dathost_match_id TEXT NOT NULL,
+    started_at INTEGER NOT NULL
+);
@@ -0,0 +1,7 @@
+CREATE TABLE waitlist (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    UNIQUE (queue_id, user_id)
+);
@@ -0,0 +1,9 @@
+CREATE TABLE queue_expiry (
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    expires_at INTEGER NOT NULL,
+    PRIMARY KEY (queue_id, user_id)
+);
+
+CREATE INDEX queue_expiry_expires_at ON queue_expiry (expires_at);
@@ -0,0 +1,5 @@
+CREATE TABLE queue_panels (
+    queue_id INTEGER PRIMARY KEY NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    channel_id INTEGER NOT NULL,
+    message_id INTEGER NOT NULL UNIQUE
+);
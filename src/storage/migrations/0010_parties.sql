@@ -0,0 +1,6 @@
+CREATE TABLE parties (
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL,
+    leader_id INTEGER NOT NULL,
+    PRIMARY KEY (queue_id, user_id)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE penalties (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    cooldown_until INTEGER NOT NULL
+);
+
+CREATE INDEX penalties_user ON penalties (guild_id, user_id);
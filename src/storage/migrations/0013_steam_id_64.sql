@@ -0,0 +1,48 @@
+-- SteamIDs were stored as `STEAM_X:Y:Z`, store the SteamID64 instead. Rows
+-- already holding a SteamID64 or SteamID3 are converted too, anything else is
+-- kept in `unconverted_steam_ids`.
+CREATE TEMP TABLE steam_id_conversion AS
+SELECT user_id,
+       steam_id AS original,
+       CASE
+           WHEN value GLOB 'STEAM_[0-9]:[01]:[0-9]*'
+               AND substr(value, 11) NOT GLOB '*[^0-9]*'
+               AND length(substr(value, 11)) <= 10
+               AND CAST(substr(value, 11) AS INTEGER) <= 2147483647
+               THEN CAST(substr(value, 11) AS INTEGER) * 2
+                   + CAST(substr(value, 9, 1) AS INTEGER)
+                   + 76561197960265728
+           WHEN trim(value, '[]') GLOB 'U:1:[0-9]*'
+               AND substr(trim(value, '[]'), 5) NOT GLOB '*[^0-9]*'
+               AND length(substr(trim(value, '[]'), 5)) <= 10
+               AND CAST(substr(trim(value, '[]'), 5) AS INTEGER) <= 4294967295
+               THEN CAST(substr(trim(value, '[]'), 5) AS INTEGER) + 76561197960265728
+           WHEN value NOT GLOB '*[^0-9]*'
+               AND length(value) = 17
+               AND CAST(value AS INTEGER) BETWEEN 76561197960265728 AND 76561202255233023
+               THEN CAST(value AS INTEGER)
+       END AS steam_id
+FROM (SELECT user_id, steam_id, upper(trim(steam_id)) AS value FROM steam_ids);
+
+CREATE TABLE steam_ids_64 (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    steam_id INTEGER NOT NULL
+);
+
+INSERT INTO steam_ids_64 (user_id, steam_id)
+SELECT user_id, steam_id FROM steam_id_conversion WHERE steam_id IS NOT NULL;
+
+-- logged on migration, the users have to link their SteamID again
+CREATE TABLE unconverted_steam_ids (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    steam_id TEXT NOT NULL
+);
+
+INSERT INTO unconverted_steam_ids (user_id, steam_id)
+SELECT user_id, original FROM steam_id_conversion WHERE steam_id IS NULL;
+
+DROP TABLE steam_id_conversion;
+
+DROP TABLE steam_ids;
+
+ALTER TABLE steam_ids_64 RENAME TO steam_ids;
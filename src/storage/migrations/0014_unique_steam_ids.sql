@@ -0,0 +1,19 @@
+-- a SteamID can only be linked to one user, the lowest user id keeps it. The
+-- other links are kept in `duplicate_steam_ids` and logged on migration.
+CREATE TABLE duplicate_steam_ids (
+    user_id INTEGER PRIMARY KEY NOT NULL,
+    steam_id INTEGER NOT NULL,
+    kept_by INTEGER NOT NULL
+);
+
+INSERT INTO duplicate_steam_ids (user_id, steam_id, kept_by)
+SELECT s.user_id, s.steam_id, k.user_id
+FROM steam_ids s
+JOIN (SELECT steam_id, MIN(user_id) AS user_id FROM steam_ids GROUP BY steam_id) k
+    ON k.steam_id = s.steam_id
+WHERE s.user_id != k.user_id;
+
+DELETE FROM steam_ids
+WHERE user_id IN (SELECT user_id FROM duplicate_steam_ids);
+
+CREATE UNIQUE INDEX steam_ids_steam_id ON steam_ids (steam_id);
@@ -0,0 +1 @@
+ALTER TABLE steam_ids ADD COLUMN verified INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+ALTER TABLE maps ADD COLUMN display_name TEXT NOT NULL DEFAULT '';
+ALTER TABLE maps ADD COLUMN thumbnail_url TEXT;
+ALTER TABLE maps ADD COLUMN enabled INTEGER NOT NULL DEFAULT 1;
+UPDATE maps SET display_name = name;
@@ -0,0 +1,28 @@
+-- Maps move from queues into named map pools. Every queue gets a `default`
+-- pool holding its existing maps.
+
+CREATE TABLE map_pools (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL REFERENCES queues (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- the pool is used automatically while the queue has this team size
+    team_size INTEGER,
+    UNIQUE (queue_id, name)
+);
+
+INSERT INTO map_pools (queue_id, name) SELECT id, 'default' FROM queues;
+
+CREATE TABLE maps_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    pool_id INTEGER NOT NULL REFERENCES map_pools (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    display_name TEXT NOT NULL,
+    thumbnail_url TEXT,
+    enabled INTEGER NOT NULL DEFAULT 1,
+    UNIQUE (pool_id, name)
+);
+INSERT INTO maps_new (id, pool_id, name, display_name, thumbnail_url, enabled)
+    SELECT m.id, p.id, m.name, m.display_name, m.thumbnail_url, m.enabled FROM maps m
+    JOIN map_pools p ON p.queue_id = m.queue_id AND p.name = 'default';
+DROP TABLE maps;
+ALTER TABLE maps_new RENAME TO maps;
@@ -0,0 +1,7 @@
+-- Matches launched before `reset_at` no longer put `map` on a cooldown.
+CREATE TABLE map_cooldown_resets (
+    guild_id INTEGER NOT NULL,
+    map TEXT NOT NULL,
+    reset_at INTEGER NOT NULL,
+    PRIMARY KEY (guild_id, map)
+);
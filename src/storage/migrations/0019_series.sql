@@ -0,0 +1,15 @@
+-- Best of three series, `draft` holds the teams and the maps as JSON.
+CREATE TABLE series (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    guild_id INTEGER NOT NULL,
+    queue_id INTEGER NOT NULL,
+    channel_id INTEGER NOT NULL,
+    draft TEXT NOT NULL,
+    started_by INTEGER,
+    score_a INTEGER NOT NULL DEFAULT 0,
+    score_b INTEGER NOT NULL DEFAULT 0,
+    maps_launched INTEGER NOT NULL DEFAULT 0,
+    dathost_match_id TEXT,
+    finished INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
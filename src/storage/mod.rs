@@ -0,0 +1,377 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::model::user::User;
+
+use crate::{Draft, State};
+
+mod import;
+mod sqlite;
+
+pub use import::import_json_files;
+pub use sqlite::SqliteStorage;
+
+/// Name of the queue used in channels that are not bound to a named queue.
+pub const DEFAULT_QUEUE: &str = "default";
+/// Name of the map pool used when no other pool applies.
+pub const DEFAULT_MAP_POOL: &str = "default";
+
+/// Persistent state of the bot.
+///
+/// Every write is applied immediately, so the in-memory caches in `Data` can be
+/// rebuilt from storage at any point.
+pub trait Storage: Send + Sync {
+    /// SteamID64s of the users.
+    fn steam_ids(&self) -> Result<HashMap<u64, u64>>;
+    /// Links `steam_id` to the user. Fails if another user linked it already.
+    /// `verified` is set when the user proved ownership by signing in through
+    /// Steam.
+    fn set_steam_id(&self, user_id: u64, steam_id: u64, verified: bool) -> Result<()>;
+    fn steam_id_verified(&self, user_id: u64) -> Result<bool>;
+    /// The user `steam_id` is linked to, if any.
+    fn steam_id_owner(&self, steam_id: u64) -> Result<Option<u64>>;
+    fn remove_steam_id(&self, user_id: u64) -> Result<bool>;
+
+    fn team_names(&self) -> Result<HashMap<u64, String>>;
+    fn set_team_name(&self, user_id: u64, team_name: &str) -> Result<()>;
+
+    /// Named queues of the guild, ordered by name.
+    fn queues(&self, guild_id: u64) -> Result<Vec<QueueInfo>>;
+    fn queue_info(&self, queue_id: i64) -> Result<Option<QueueInfo>>;
+    fn queue_id(&self, guild_id: u64, name: &str) -> Result<Option<i64>>;
+    /// Returns the id of the queue named `name`, creating it if needed.
+    fn create_queue(&self, guild_id: u64, name: &str) -> Result<i64>;
+    /// Deletes the queue together with its users, maps, settings and channels.
+    fn delete_queue(&self, queue_id: i64) -> Result<()>;
+    /// The queue `channel_id` is bound to, if any.
+    fn channel_queue(&self, channel_id: u64) -> Result<Option<i64>>;
+    /// Binds `channel_id` to the queue, replacing any previous binding.
+    fn bind_channel(&self, queue_id: i64, channel_id: u64) -> Result<()>;
+    fn unbind_channel(&self, channel_id: u64) -> Result<bool>;
+
+    /// The panel message of the queue, see `/admin queue panel`.
+    fn queue_panel(&self, queue_id: i64) -> Result<Option<QueuePanel>>;
+    fn queue_panel_by_message(&self, message_id: u64) -> Result<Option<QueuePanel>>;
+    /// Replaces the panel of `panel.queue_id`.
+    fn set_queue_panel(&self, panel: &QueuePanel) -> Result<()>;
+
+    /// Map pools of the queue with their maps, ordered by name.
+    fn map_pools(&self, queue_id: i64) -> Result<Vec<MapPool>>;
+    /// Returns the id of the pool named `name`, creating it if needed.
+    fn create_map_pool(&self, queue_id: i64, name: &str) -> Result<i64>;
+    /// Deletes the pool together with its maps.
+    fn delete_map_pool(&self, pool_id: i64) -> Result<()>;
+    fn set_map_pool_team_size(&self, pool_id: i64, team_size: Option<u8>) -> Result<()>;
+    /// The pool activated with `/admin pool activate`, overriding the
+    /// selection by team size.
+    fn active_map_pool(&self, queue_id: i64) -> Result<Option<String>>;
+    fn set_active_map_pool(&self, queue_id: i64, name: Option<&str>) -> Result<()>;
+    fn add_map(&self, pool_id: i64, map: &MapEntry) -> Result<()>;
+    /// Updates the map with the same `name`.
+    fn update_map(&self, pool_id: i64, map: &MapEntry) -> Result<()>;
+    fn remove_map(&self, pool_id: i64, map_name: &str) -> Result<()>;
+
+    fn queue(&self, queue_id: i64) -> Result<Vec<User>>;
+    fn add_to_queue(&self, queue_id: i64, user: &User) -> Result<()>;
+    fn remove_from_queue(&self, queue_id: i64, user_id: u64) -> Result<()>;
+
+    fn queue_messages(&self, queue_id: i64) -> Result<HashMap<u64, String>>;
+    fn set_queue_message(&self, queue_id: i64, user_id: u64, message: &str) -> Result<()>;
+    fn remove_queue_message(&self, queue_id: i64, user_id: u64) -> Result<()>;
+
+    /// Players waiting for a spot in the full queue, in the order they joined.
+    fn waitlist(&self, queue_id: i64) -> Result<Vec<User>>;
+    fn add_to_waitlist(&self, queue_id: i64, user: &User) -> Result<()>;
+    fn remove_from_waitlist(&self, queue_id: i64, user_id: u64) -> Result<()>;
+    /// Removes every waitlisted user and their queue messages, parties are
+    /// disbanded.
+    fn clear_waitlist(&self, queue_id: i64) -> Result<()>;
+
+    /// When a player's queue or waitlist entry expires. Setting `None` keeps the
+    /// entry until the player leaves.
+    fn set_queue_expiry(
+        &self,
+        queue_id: i64,
+        user_id: u64,
+        expiry: Option<&QueueExpiry>,
+    ) -> Result<()>;
+    fn queue_expiries(&self, queue_id: i64) -> Result<HashMap<u64, DateTime<Utc>>>;
+    /// Entries of every queue that expired at or before `now`.
+    fn expired_queue_entries(&self, now: DateTime<Utc>) -> Result<Vec<QueueExpiry>>;
+
+    /// Party membership of queued and waitlisted players, mapped to the party
+    /// leader. Leaders map to themselves.
+    fn parties(&self, queue_id: i64) -> Result<HashMap<u64, u64>>;
+    /// Replaces the members of `leader_id`'s party. Fewer than two members
+    /// disbands it.
+    fn set_party(&self, queue_id: i64, leader_id: u64, members: &[u64]) -> Result<()>;
+
+    /// Removes every user of the queue and their queue messages in a single
+    /// transaction. The waitlist is kept, parties are disbanded.
+    fn clear_queue(&self, queue_id: i64) -> Result<()>;
+
+    fn team_size(&self, queue_id: i64) -> Result<Option<u8>>;
+    fn set_team_size(&self, queue_id: i64, team_size: u8) -> Result<()>;
+
+    /// Settings changed through `/admin config`, keyed by setting name.
+    fn guild_settings(&self, guild_id: u64) -> Result<HashMap<String, String>>;
+    fn set_guild_setting(&self, guild_id: u64, key: &str, value: &str) -> Result<()>;
+    /// Returns whether the setting had been changed.
+    fn remove_guild_setting(&self, guild_id: u64, key: &str) -> Result<bool>;
+
+    /// Penalties of the user in the guild, most recent first.
+    fn penalties(&self, guild_id: u64, user_id: u64) -> Result<Vec<Penalty>>;
+    /// Penalties of the guild whose cooldown ends after `now`, ending last first.
+    fn active_penalties(&self, guild_id: u64, now: DateTime<Utc>) -> Result<Vec<Penalty>>;
+    fn add_penalty(&self, penalty: &Penalty) -> Result<()>;
+    /// Removes every penalty of the user in the guild and returns how many
+    /// there were.
+    fn forgive_penalties(&self, guild_id: u64, user_id: u64) -> Result<usize>;
+
+    /// The user's ban from the guild's queues, unless it expired before `now`.
+    fn queue_ban(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<QueueBan>>;
+    /// Bans of the guild that did not expire before `now`, most recent first.
+    fn queue_bans(&self, guild_id: u64, now: DateTime<Utc>) -> Result<Vec<QueueBan>>;
+    /// Bans `ban.user_id`, replacing their previous ban.
+    fn ban_from_queue(&self, ban: &QueueBan) -> Result<()>;
+    fn unban_from_queue(&self, guild_id: u64, user_id: u64) -> Result<bool>;
+
+    /// Every `/start` setup in progress.
+    fn setup_sessions(&self) -> Result<Vec<SetupSession>>;
+    fn save_setup_session(&self, session: &SetupSession) -> Result<()>;
+    fn clear_setup_session(&self, queue_id: i64) -> Result<()>;
+
+    /// Records a launched match and returns its id. `record.id` is ignored.
+    fn add_match(&self, record: &MatchRecord) -> Result<i64>;
+    /// Matches matching `filter`, most recent first.
+    fn matches(&self, filter: &MatchFilter, limit: u32, offset: u32) -> Result<Vec<MatchRecord>>;
+    fn match_count(&self, filter: &MatchFilter) -> Result<u32>;
+    /// When an admin last ended the cooldown of each map of the guild.
+    fn map_cooldown_resets(&self, guild_id: u64) -> Result<HashMap<String, DateTime<Utc>>>;
+    fn reset_map_cooldown(&self, guild_id: u64, map: &str, at: DateTime<Utc>) -> Result<()>;
+
+    /// Records a series and returns its id. `series.id` is ignored.
+    fn add_series(&self, series: &Series) -> Result<i64>;
+    /// Series that are not decided yet, oldest first.
+    fn active_series(&self) -> Result<Vec<Series>>;
+    fn update_series(&self, series: &Series) -> Result<()>;
+
+    /// DatHost servers running a match launched by the bot, mapped to that
+    /// match's DatHost id.
+    fn busy_servers(&self) -> Result<HashMap<String, String>>;
+    fn set_server_busy(&self, server_id: &str, dathost_match_id: &str) -> Result<()>;
+    fn release_server(&self, server_id: &str) -> Result<()>;
+
+    /// Assigns state stored before it was kept per guild (and anything imported
+    /// from the legacy json files) to `guild_id`. Returns whether there was any.
+    /// A queue whose name the guild already uses is renamed, a setting the
+    /// guild already has is logged and left without a guild.
+    fn adopt_unscoped(&self, guild_id: u64) -> Result<bool>;
+
+    fn legacy_imported(&self) -> Result<bool>;
+
+    /// Imports the legacy `data/*.json` state. Returns `false` without writing
+    /// anything if an import has already been recorded.
+    fn import_legacy(&self, legacy: &LegacyData) -> Result<bool>;
+}
+
+/// Snapshot of a `/start` setup, saved after every step so it can be resumed on
+/// the same Discord message after a restart.
+#[derive(Serialize, Deserialize)]
+pub struct SetupSession {
+    pub guild_id: u64,
+    pub queue_id: i64,
+    pub channel_id: u64,
+    pub message_id: u64,
+    pub state: State,
+    pub draft: Draft,
+    pub ready_queue: Vec<User>,
+    #[serde(default)]
+    pub started_by: Option<u64>,
+}
+
+/// A named queue with its own players, map pool, team size and setup.
+pub struct QueueInfo {
+    pub id: i64,
+    pub guild_id: u64,
+    pub name: String,
+    pub channel_ids: Vec<u64>,
+}
+
+/// A named set of maps of a queue, e.g. one for 5v5 and one for wingman.
+#[derive(Clone)]
+pub struct MapPool {
+    pub id: i64,
+    pub name: String,
+    /// The pool is used automatically while the queue has this team size.
+    pub team_size: Option<u8>,
+    pub maps: Vec<MapEntry>,
+}
+
+/// A map of a map pool.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MapEntry {
+    /// Internal map name like `de_dust2`, or a Steam workshop id.
+    pub name: String,
+    pub display_name: String,
+    pub thumbnail_url: Option<String>,
+    /// Disabled maps stay in the pool but are left out of map votes.
+    pub enabled: bool,
+}
+
+impl MapEntry {
+    pub fn new(name: &str) -> Self {
+        MapEntry {
+            name: name.to_string(),
+            display_name: name.to_string(),
+            thumbnail_url: None,
+            enabled: true,
+        }
+    }
+
+    /// The workshop id, if the map is a workshop map.
+    pub fn workshop_id(&self) -> Option<&str> {
+        let is_id = !self.name.is_empty() && self.name.chars().all(|c| c.is_ascii_digit());
+        is_id.then_some(self.name.as_str())
+    }
+}
+
+/// Message showing a queue's players with buttons to join and leave.
+pub struct QueuePanel {
+    pub queue_id: i64,
+    pub channel_id: u64,
+    pub message_id: u64,
+}
+
+/// Expiry of a timed `/queue join`.
+pub struct QueueExpiry {
+    pub queue_id: i64,
+    pub user_id: u64,
+    /// Where the player joined, the expiry notice is posted there.
+    pub channel_id: u64,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A dodge or no-show, blocking the user from joining a queue of the guild
+/// until `cooldown_until`.
+pub struct Penalty {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub kind: PenaltyKind,
+    pub created_at: DateTime<Utc>,
+    pub cooldown_until: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PenaltyKind {
+    /// Did not ready up in time.
+    MissedReadyCheck,
+    /// Left the queue after the teams were posted.
+    EarlyLeave,
+    /// Reported by an admin for not showing up to a match.
+    NoShow,
+}
+
+impl PenaltyKind {
+    pub fn key(&self) -> &'static str {
+        match self {
+            PenaltyKind::MissedReadyCheck => "missed_ready_check",
+            PenaltyKind::EarlyLeave => "early_leave",
+            PenaltyKind::NoShow => "no_show",
+        }
+    }
+
+    pub fn from_key(key: &str) -> Option<PenaltyKind> {
+        [
+            PenaltyKind::MissedReadyCheck,
+            PenaltyKind::EarlyLeave,
+            PenaltyKind::NoShow,
+        ]
+        .into_iter()
+        .find(|kind| kind.key() == key)
+    }
+}
+
+/// A ban from joining the queues of the guild, see `/admin queue ban`.
+pub struct QueueBan {
+    pub guild_id: u64,
+    pub user_id: u64,
+    pub reason: Option<String>,
+    pub banned_by: u64,
+    pub created_at: DateTime<Utc>,
+    /// `None` for a permanent ban.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// A match launched through `/start`.
+pub struct MatchRecord {
+    pub id: i64,
+    pub guild_id: u64,
+    pub dathost_match_id: Option<String>,
+    pub map: String,
+    pub team_a: MatchRecordTeam,
+    pub team_b: MatchRecordTeam,
+    pub team_b_start_side: String,
+    pub launched_by: Option<u64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A best of three launched through `/start`. Its maps are launched one after
+/// the other, each once the previous one has ended.
+pub struct Series {
+    pub id: i64,
+    pub guild_id: u64,
+    pub queue_id: i64,
+    /// Where the score is posted.
+    pub channel_id: u64,
+    /// Teams and captains, with the maps of the series and their sides.
+    pub draft: Draft,
+    pub started_by: Option<u64>,
+    pub score_a: u32,
+    pub score_b: u32,
+    /// One ahead of the maps played while a map is running.
+    pub maps_launched: u32,
+    /// Match of the running map, `None` if its result has to be entered by an
+    /// admin.
+    pub dathost_match_id: Option<String>,
+    pub finished: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct MatchRecordTeam {
+    pub name: String,
+    pub captain: u64,
+    pub players: Vec<MatchPlayer>,
+}
+
+pub struct MatchPlayer {
+    pub user_id: u64,
+    pub name: String,
+}
+
+pub struct MatchFilter {
+    pub guild_id: u64,
+    pub user_id: Option<u64>,
+    /// Internal map name, compared case-insensitively.
+    pub map: Option<String>,
+}
+
+/// Contents of the `data/*.json` files used before the SQLite storage.
+#[derive(Default)]
+pub struct LegacyData {
+    /// SteamID64s, converted from the `STEAM_X:Y:Z` format of the json file.
+    pub steam_ids: HashMap<u64, u64>,
+    /// Values of the json file that are not a valid SteamID.
+    pub unconverted_steam_ids: HashMap<u64, String>,
+    pub team_names: HashMap<u64, String>,
+    pub maps: Vec<MapEntry>,
+    pub queue: Vec<User>,
+    pub queue_messages: HashMap<u64, String>,
+}
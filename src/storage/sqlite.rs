@@ -0,0 +1,1510 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serenity::model::user::User;
+
+use super::{
+    LegacyData, MapEntry, MapPool, MatchFilter, MatchPlayer, MatchRecord, MatchRecordTeam, Penalty,
+    PenaltyKind, QueueBan, QueueExpiry, QueueInfo, QueuePanel, Series, SetupSession, Storage,
+    DEFAULT_MAP_POOL, DEFAULT_QUEUE,
+};
+use crate::Draft;
+
+/// Schema migrations, applied in order, each with the side table its rows go to
+/// when they can't be carried over. The index of the last applied migration is
+/// tracked in `PRAGMA user_version`.
+const MIGRATIONS: &[(&str, Option<SetAside>)] = &[
+    (include_str!("migrations/0001_init.sql"), None),
+    (include_str!("migrations/0002_setup_session.sql"), None),
+    (include_str!("migrations/0003_match_history.sql"), None),
+    (include_str!("migrations/0004_per_guild.sql"), None),
+    (include_str!("migrations/0005_named_queues.sql"), None),
+    (include_str!("migrations/0006_server_pool.sql"), None),
+    (include_str!("migrations/0007_waitlist.sql"), None),
+    (include_str!("migrations/0008_queue_expiry.sql"), None),
+    (include_str!("migrations/0009_queue_panels.sql"), None),
+    (include_str!("migrations/0010_parties.sql"), None),
+    (include_str!("migrations/0011_penalties.sql"), None),
+    (include_str!("migrations/0012_queue_bans.sql"), None),
+    (
+        include_str!("migrations/0013_steam_id_64.sql"),
+        Some(UNCONVERTED_STEAM_IDS),
+    ),
+    (
+        include_str!("migrations/0014_unique_steam_ids.sql"),
+        Some(DUPLICATE_STEAM_IDS),
+    ),
+    (include_str!("migrations/0015_verified_steam_ids.sql"), None),
+    (include_str!("migrations/0016_map_entries.sql"), None),
+    (include_str!("migrations/0017_map_pools.sql"), None),
+    (
+        include_str!("migrations/0018_map_cooldown_resets.sql"),
+        None,
+    ),
+    (include_str!("migrations/0019_series.sql"), None),
+];
+
+/// Side table of SteamID links that were not carried over, and why.
+type SetAside = (&'static str, &'static str);
+const UNCONVERTED_STEAM_IDS: SetAside = ("unconverted_steam_ids", "is not a valid SteamID");
+const DUPLICATE_STEAM_IDS: SetAside = ("duplicate_steam_ids", "is linked to another user");
+
+const LEGACY_IMPORT_KEY: &str = "legacy_json_imported";
+const TEAM_SIZE_KEY: &str = "team_size";
+const ACTIVE_MAP_POOL_KEY: &str = "active_map_pool";
+
+pub struct SqliteStorage(Mutex<Connection>);
+
+impl SqliteStorage {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(Self(Mutex::new(conn)))
+    }
+
+    fn conn(&self) -> Result<std::sync::MutexGuard<'_, Connection>> {
+        self.0
+            .lock()
+            .map_err(|_| anyhow!("SQLite connection mutex poisoned"))
+    }
+}
+
+/// `WHERE` clause for a [`MatchFilter`], bound as `?1` (guild id), `?2` (map)
+/// and `?3` (user id).
+const MATCH_FILTER: &str = "guild_id = ?1 AND (?2 IS NULL OR map = ?2 COLLATE NOCASE)
+    AND (?3 IS NULL OR id IN (SELECT match_id FROM match_players WHERE user_id = ?3))";
+
+fn match_from_row(row: &Row) -> rusqlite::Result<MatchRecord> {
+    Ok(MatchRecord {
+        id: row.get(0)?,
+        dathost_match_id: row.get(1)?,
+        map: row.get(2)?,
+        team_a: MatchRecordTeam {
+            name: row.get(3)?,
+            captain: row.get(4)?,
+            players: Vec::new(),
+        },
+        team_b: MatchRecordTeam {
+            name: row.get(5)?,
+            captain: row.get(6)?,
+            players: Vec::new(),
+        },
+        team_b_start_side: row.get(7)?,
+        launched_by: row.get(8)?,
+        created_at: timestamp(row.get(9)?),
+        guild_id: row.get(10)?,
+    })
+}
+
+fn panel_from_row(row: &Row) -> rusqlite::Result<QueuePanel> {
+    Ok(QueuePanel {
+        queue_id: row.get(0)?,
+        channel_id: row.get(1)?,
+        message_id: row.get(2)?,
+    })
+}
+
+fn map_from_row(row: &Row) -> rusqlite::Result<MapEntry> {
+    Ok(MapEntry {
+        name: row.get(0)?,
+        display_name: row.get(1)?,
+        thumbnail_url: row.get(2)?,
+        enabled: row.get(3)?,
+    })
+}
+
+fn penalty_from_row(row: &Row) -> rusqlite::Result<Penalty> {
+    let kind: String = row.get(2)?;
+    let Some(kind) = PenaltyKind::from_key(&kind) else {
+        return Err(rusqlite::Error::InvalidColumnType(
+            2,
+            "kind".to_string(),
+            rusqlite::types::Type::Text,
+        ));
+    };
+    Ok(Penalty {
+        guild_id: row.get(0)?,
+        user_id: row.get(1)?,
+        kind,
+        created_at: timestamp(row.get(3)?),
+        cooldown_until: timestamp(row.get(4)?),
+    })
+}
+
+fn ban_from_row(row: &Row) -> rusqlite::Result<QueueBan> {
+    Ok(QueueBan {
+        guild_id: row.get(0)?,
+        user_id: row.get(1)?,
+        reason: row.get(2)?,
+        banned_by: row.get(3)?,
+        created_at: timestamp(row.get(4)?),
+        expires_at: row.get::<_, Option<i64>>(5)?.map(timestamp),
+    })
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(seconds, 0).single().unwrap_or_default()
+}
+
+fn queue_info(conn: &Connection, queue_id: i64) -> Result<Option<QueueInfo>> {
+    let queue = conn
+        .query_row(
+            "SELECT guild_id, name FROM queues WHERE id = ?1",
+            params![queue_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((guild_id, name)) = queue else {
+        return Ok(None);
+    };
+    let mut stmt = conn.prepare("SELECT channel_id FROM queue_channels WHERE queue_id = ?1")?;
+    let channel_ids = stmt
+        .query_map(params![queue_id], |row| row.get(0))?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(Some(QueueInfo {
+        id: queue_id,
+        guild_id,
+        name,
+        channel_ids,
+    }))
+}
+
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    for (index, (migration, set_aside)) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", index + 1)?;
+        tx.commit()?;
+        log::info!("Applied database migration {}", index + 1);
+        if let Some(set_aside) = set_aside {
+            report_set_aside(conn, *set_aside)?;
+        }
+    }
+    Ok(())
+}
+
+/// Logs the rows a migration kept in its side table, so nothing is lost
+/// silently.
+fn report_set_aside(conn: &Connection, (table, reason): SetAside) -> Result<()> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT user_id, CAST(steam_id AS TEXT) FROM {} ORDER BY user_id",
+        table
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (user_id, steam_id) = row?;
+        log_set_aside((table, reason), user_id, &steam_id);
+    }
+    Ok(())
+}
+
+fn log_set_aside((table, reason): SetAside, user_id: u64, steam_id: &str) {
+    log::warn!(
+        "SteamID `{}` of user {} {} and was moved to the `{}` table, the user has to link it again",
+        steam_id,
+        user_id,
+        reason,
+        table
+    );
+}
+
+impl Storage for SqliteStorage {
+    fn steam_ids(&self) -> Result<HashMap<u64, u64>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT user_id, steam_id FROM steam_ids")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_steam_id(&self, user_id: u64, steam_id: u64, verified: bool) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO steam_ids (user_id, steam_id, verified) VALUES (?1, ?2, ?3)
+             ON CONFLICT (user_id) DO UPDATE
+             SET steam_id = excluded.steam_id, verified = excluded.verified",
+            params![user_id, steam_id, verified],
+        )?;
+        Ok(())
+    }
+
+    fn steam_id_verified(&self, user_id: u64) -> Result<bool> {
+        let verified = self
+            .conn()?
+            .query_row(
+                "SELECT verified FROM steam_ids WHERE user_id = ?1",
+                params![user_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(verified.unwrap_or(false))
+    }
+
+    fn steam_id_owner(&self, steam_id: u64) -> Result<Option<u64>> {
+        let owner = self
+            .conn()?
+            .query_row(
+                "SELECT user_id FROM steam_ids WHERE steam_id = ?1",
+                params![steam_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(owner)
+    }
+
+    fn remove_steam_id(&self, user_id: u64) -> Result<bool> {
+        let removed = self
+            .conn()?
+            .execute("DELETE FROM steam_ids WHERE user_id = ?1", params![user_id])?;
+        Ok(removed > 0)
+    }
+
+    fn team_names(&self) -> Result<HashMap<u64, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT user_id, team_name FROM team_names")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_team_name(&self, user_id: u64, team_name: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO team_names (user_id, team_name) VALUES (?1, ?2)
+             ON CONFLICT (user_id) DO UPDATE SET team_name = excluded.team_name",
+            params![user_id, team_name],
+        )?;
+        Ok(())
+    }
+
+    fn queues(&self, guild_id: u64) -> Result<Vec<QueueInfo>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id FROM queues WHERE guild_id = ?1 ORDER BY name")?;
+        let ids = stmt
+            .query_map(params![guild_id], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+        drop(stmt);
+        let mut queues = Vec::new();
+        for id in ids {
+            queues.extend(queue_info(&conn, id)?);
+        }
+        Ok(queues)
+    }
+
+    fn queue_info(&self, queue_id: i64) -> Result<Option<QueueInfo>> {
+        let conn = self.conn()?;
+        queue_info(&conn, queue_id)
+    }
+
+    fn queue_id(&self, guild_id: u64, name: &str) -> Result<Option<i64>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT id FROM queues WHERE guild_id = ?1 AND name = ?2",
+                params![guild_id, name],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn create_queue(&self, guild_id: u64, name: &str) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT OR IGNORE INTO queues (guild_id, name) VALUES (?1, ?2)",
+            params![guild_id, name],
+        )?;
+        Ok(conn.query_row(
+            "SELECT id FROM queues WHERE guild_id = ?1 AND name = ?2",
+            params![guild_id, name],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn delete_queue(&self, queue_id: i64) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM queues WHERE id = ?1", params![queue_id])?;
+        Ok(())
+    }
+
+    fn channel_queue(&self, channel_id: u64) -> Result<Option<i64>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT queue_id FROM queue_channels WHERE channel_id = ?1",
+                params![channel_id],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn bind_channel(&self, queue_id: i64, channel_id: u64) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO queue_channels (channel_id, queue_id) VALUES (?1, ?2)
+             ON CONFLICT (channel_id) DO UPDATE SET queue_id = excluded.queue_id",
+            params![channel_id, queue_id],
+        )?;
+        Ok(())
+    }
+
+    fn unbind_channel(&self, channel_id: u64) -> Result<bool> {
+        let removed = self.conn()?.execute(
+            "DELETE FROM queue_channels WHERE channel_id = ?1",
+            params![channel_id],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn queue_panel(&self, queue_id: i64) -> Result<Option<QueuePanel>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT queue_id, channel_id, message_id FROM queue_panels WHERE queue_id = ?1",
+                params![queue_id],
+                panel_from_row,
+            )
+            .optional()?)
+    }
+
+    fn queue_panel_by_message(&self, message_id: u64) -> Result<Option<QueuePanel>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT queue_id, channel_id, message_id FROM queue_panels WHERE message_id = ?1",
+                params![message_id],
+                panel_from_row,
+            )
+            .optional()?)
+    }
+
+    fn set_queue_panel(&self, panel: &QueuePanel) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO queue_panels (queue_id, channel_id, message_id)
+             VALUES (?1, ?2, ?3)",
+            params![panel.queue_id, panel.channel_id, panel.message_id],
+        )?;
+        Ok(())
+    }
+
+    fn map_pools(&self, queue_id: i64) -> Result<Vec<MapPool>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, name, team_size FROM map_pools WHERE queue_id = ?1 ORDER BY name",
+        )?;
+        let rows = stmt.query_map(params![queue_id], |row| {
+            Ok(MapPool {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                team_size: row.get(2)?,
+                maps: Vec::new(),
+            })
+        })?;
+        let mut pools = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut stmt = conn.prepare(
+            "SELECT name, display_name, thumbnail_url, enabled FROM maps
+             WHERE pool_id = ?1 ORDER BY id",
+        )?;
+        for pool in &mut pools {
+            let rows = stmt.query_map(params![pool.id], map_from_row)?;
+            pool.maps = rows.collect::<rusqlite::Result<_>>()?;
+        }
+        Ok(pools)
+    }
+
+    fn create_map_pool(&self, queue_id: i64, name: &str) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT OR IGNORE INTO map_pools (queue_id, name) VALUES (?1, ?2)",
+            params![queue_id, name],
+        )?;
+        Ok(conn.query_row(
+            "SELECT id FROM map_pools WHERE queue_id = ?1 AND name = ?2",
+            params![queue_id, name],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn delete_map_pool(&self, pool_id: i64) -> Result<()> {
+        self.conn()?
+            .execute("DELETE FROM map_pools WHERE id = ?1", params![pool_id])?;
+        Ok(())
+    }
+
+    fn set_map_pool_team_size(&self, pool_id: i64, team_size: Option<u8>) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE map_pools SET team_size = ?2 WHERE id = ?1",
+            params![pool_id, team_size],
+        )?;
+        Ok(())
+    }
+
+    fn active_map_pool(&self, queue_id: i64) -> Result<Option<String>> {
+        Ok(self
+            .conn()?
+            .query_row(
+                "SELECT value FROM queue_settings WHERE queue_id = ?1 AND key = ?2",
+                params![queue_id, ACTIVE_MAP_POOL_KEY],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    fn set_active_map_pool(&self, queue_id: i64, name: Option<&str>) -> Result<()> {
+        let conn = self.conn()?;
+        match name {
+            Some(name) => conn.execute(
+                "INSERT INTO queue_settings (queue_id, key, value) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (queue_id, key) DO UPDATE SET value = excluded.value",
+                params![queue_id, ACTIVE_MAP_POOL_KEY, name],
+            )?,
+            None => conn.execute(
+                "DELETE FROM queue_settings WHERE queue_id = ?1 AND key = ?2",
+                params![queue_id, ACTIVE_MAP_POOL_KEY],
+            )?,
+        };
+        Ok(())
+    }
+
+    fn add_map(&self, pool_id: i64, map: &MapEntry) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR IGNORE INTO maps (pool_id, name, display_name, thumbnail_url, enabled)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pool_id,
+                map.name,
+                map.display_name,
+                map.thumbnail_url,
+                map.enabled
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn update_map(&self, pool_id: i64, map: &MapEntry) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE maps SET display_name = ?3, thumbnail_url = ?4, enabled = ?5
+             WHERE pool_id = ?1 AND name = ?2",
+            params![
+                pool_id,
+                map.name,
+                map.display_name,
+                map.thumbnail_url,
+                map.enabled
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn remove_map(&self, pool_id: i64, map_name: &str) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM maps WHERE pool_id = ?1 AND name = ?2",
+            params![pool_id, map_name],
+        )?;
+        Ok(())
+    }
+
+    fn queue(&self, queue_id: i64) -> Result<Vec<User>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT user FROM queue WHERE queue_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![queue_id], |row| row.get::<_, String>(0))?;
+        let mut queue = Vec::new();
+        for user in rows {
+            queue.push(serde_json::from_str(&user?)?);
+        }
+        Ok(queue)
+    }
+
+    fn add_to_queue(&self, queue_id: i64, user: &User) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR IGNORE INTO queue (queue_id, user_id, user) VALUES (?1, ?2, ?3)",
+            params![queue_id, user.id.0, serde_json::to_string(user)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_from_queue(&self, queue_id: i64, user_id: u64) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM queue WHERE queue_id = ?1 AND user_id = ?2",
+            params![queue_id, user_id],
+        )?;
+        Ok(())
+    }
+
+    fn queue_messages(&self, queue_id: i64) -> Result<HashMap<u64, String>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT user_id, message FROM queue_messages WHERE queue_id = ?1")?;
+        let rows = stmt.query_map(params![queue_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_queue_message(&self, queue_id: i64, user_id: u64, message: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO queue_messages (queue_id, user_id, message) VALUES (?1, ?2, ?3)
+             ON CONFLICT (queue_id, user_id) DO UPDATE SET message = excluded.message",
+            params![queue_id, user_id, message],
+        )?;
+        Ok(())
+    }
+
+    fn remove_queue_message(&self, queue_id: i64, user_id: u64) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM queue_messages WHERE queue_id = ?1 AND user_id = ?2",
+            params![queue_id, user_id],
+        )?;
+        Ok(())
+    }
+
+    fn waitlist(&self, queue_id: i64) -> Result<Vec<User>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT user FROM waitlist WHERE queue_id = ?1 ORDER BY id")?;
+        let rows = stmt.query_map(params![queue_id], |row| row.get::<_, String>(0))?;
+        let mut waitlist = Vec::new();
+        for user in rows {
+            waitlist.push(serde_json::from_str(&user?)?);
+        }
+        Ok(waitlist)
+    }
+
+    fn add_to_waitlist(&self, queue_id: i64, user: &User) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR IGNORE INTO waitlist (queue_id, user_id, user) VALUES (?1, ?2, ?3)",
+            params![queue_id, user.id.0, serde_json::to_string(user)?],
+        )?;
+        Ok(())
+    }
+
+    fn remove_from_waitlist(&self, queue_id: i64, user_id: u64) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM waitlist WHERE queue_id = ?1 AND user_id = ?2",
+            params![queue_id, user_id],
+        )?;
+        Ok(())
+    }
+
+    fn set_queue_expiry(
+        &self,
+        queue_id: i64,
+        user_id: u64,
+        expiry: Option<&QueueExpiry>,
+    ) -> Result<()> {
+        let conn = self.conn()?;
+        match expiry {
+            Some(expiry) => conn.execute(
+                "INSERT INTO queue_expiry (queue_id, user_id, channel_id, expires_at)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (queue_id, user_id) DO UPDATE
+                 SET channel_id = excluded.channel_id, expires_at = excluded.expires_at",
+                params![
+                    queue_id,
+                    user_id,
+                    expiry.channel_id,
+                    expiry.expires_at.timestamp()
+                ],
+            )?,
+            None => conn.execute(
+                "DELETE FROM queue_expiry WHERE queue_id = ?1 AND user_id = ?2",
+                params![queue_id, user_id],
+            )?,
+        };
+        Ok(())
+    }
+
+    fn queue_expiries(&self, queue_id: i64) -> Result<HashMap<u64, DateTime<Utc>>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT user_id, expires_at FROM queue_expiry WHERE queue_id = ?1")?;
+        let rows = stmt.query_map(params![queue_id], |row| {
+            Ok((row.get(0)?, timestamp(row.get(1)?)))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn expired_queue_entries(&self, now: DateTime<Utc>) -> Result<Vec<QueueExpiry>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT queue_id, user_id, channel_id, expires_at FROM queue_expiry
+             WHERE expires_at <= ?1 ORDER BY expires_at",
+        )?;
+        let rows = stmt.query_map(params![now.timestamp()], |row| {
+            Ok(QueueExpiry {
+                queue_id: row.get(0)?,
+                user_id: row.get(1)?,
+                channel_id: row.get(2)?,
+                expires_at: timestamp(row.get(3)?),
+            })
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn parties(&self, queue_id: i64) -> Result<HashMap<u64, u64>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT user_id, leader_id FROM parties WHERE queue_id = ?1")?;
+        let rows = stmt.query_map(params![queue_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_party(&self, queue_id: i64, leader_id: u64, members: &[u64]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM parties WHERE queue_id = ?1 AND leader_id = ?2",
+            params![queue_id, leader_id],
+        )?;
+        if members.len() > 1 {
+            for member in members {
+                tx.execute(
+                    "INSERT OR REPLACE INTO parties (queue_id, user_id, leader_id)
+                     VALUES (?1, ?2, ?3)",
+                    params![queue_id, member, leader_id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn clear_waitlist(&self, queue_id: i64) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM queue_messages WHERE queue_id = ?1
+             AND user_id IN (SELECT user_id FROM waitlist WHERE queue_id = ?1)",
+            params![queue_id],
+        )?;
+        tx.execute(
+            "DELETE FROM waitlist WHERE queue_id = ?1",
+            params![queue_id],
+        )?;
+        tx.execute("DELETE FROM parties WHERE queue_id = ?1", params![queue_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn clear_queue(&self, queue_id: i64) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM queue WHERE queue_id = ?1", params![queue_id])?;
+        tx.execute(
+            "DELETE FROM queue_messages WHERE queue_id = ?1
+             AND user_id NOT IN (SELECT user_id FROM waitlist WHERE queue_id = ?1)",
+            params![queue_id],
+        )?;
+        tx.execute("DELETE FROM parties WHERE queue_id = ?1", params![queue_id])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn team_size(&self, queue_id: i64) -> Result<Option<u8>> {
+        let team_size: Option<String> = self
+            .conn()?
+            .query_row(
+                "SELECT value FROM queue_settings WHERE queue_id = ?1 AND key = ?2",
+                params![queue_id, TEAM_SIZE_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(team_size.map(|t| t.parse()).transpose()?)
+    }
+
+    fn set_team_size(&self, queue_id: i64, team_size: u8) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO queue_settings (queue_id, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (queue_id, key) DO UPDATE SET value = excluded.value",
+            params![queue_id, TEAM_SIZE_KEY, team_size.to_string()],
+        )?;
+        Ok(())
+    }
+
+    fn guild_settings(&self, guild_id: u64) -> Result<HashMap<String, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT key, value FROM guild_settings WHERE guild_id = ?1")?;
+        let rows = stmt.query_map(params![guild_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_guild_setting(&self, guild_id: u64, key: &str, value: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO guild_settings (guild_id, key, value) VALUES (?1, ?2, ?3)
+             ON CONFLICT (guild_id, key) DO UPDATE SET value = excluded.value",
+            params![guild_id, key, value],
+        )?;
+        Ok(())
+    }
+
+    fn remove_guild_setting(&self, guild_id: u64, key: &str) -> Result<bool> {
+        let removed = self.conn()?.execute(
+            "DELETE FROM guild_settings WHERE guild_id = ?1 AND key = ?2",
+            params![guild_id, key],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn penalties(&self, guild_id: u64, user_id: u64) -> Result<Vec<Penalty>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT guild_id, user_id, kind, created_at, cooldown_until FROM penalties
+             WHERE guild_id = ?1 AND user_id = ?2 ORDER BY created_at DESC",
+        )?;
+        let rows = stmt.query_map(params![guild_id, user_id], penalty_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn active_penalties(&self, guild_id: u64, now: DateTime<Utc>) -> Result<Vec<Penalty>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT guild_id, user_id, kind, created_at, cooldown_until FROM penalties
+             WHERE guild_id = ?1 AND cooldown_until > ?2 ORDER BY cooldown_until DESC",
+        )?;
+        let rows = stmt.query_map(params![guild_id, now.timestamp()], penalty_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn add_penalty(&self, penalty: &Penalty) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO penalties (guild_id, user_id, kind, created_at, cooldown_until)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                penalty.guild_id,
+                penalty.user_id,
+                penalty.kind.key(),
+                penalty.created_at.timestamp(),
+                penalty.cooldown_until.timestamp(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn forgive_penalties(&self, guild_id: u64, user_id: u64) -> Result<usize> {
+        let removed = self.conn()?.execute(
+            "DELETE FROM penalties WHERE guild_id = ?1 AND user_id = ?2",
+            params![guild_id, user_id],
+        )?;
+        Ok(removed)
+    }
+
+    fn queue_ban(
+        &self,
+        guild_id: u64,
+        user_id: u64,
+        now: DateTime<Utc>,
+    ) -> Result<Option<QueueBan>> {
+        let ban = self
+            .conn()?
+            .query_row(
+                "SELECT guild_id, user_id, reason, banned_by, created_at, expires_at
+                 FROM queue_bans WHERE guild_id = ?1 AND user_id = ?2
+                 AND (expires_at IS NULL OR expires_at > ?3)",
+                params![guild_id, user_id, now.timestamp()],
+                ban_from_row,
+            )
+            .optional()?;
+        Ok(ban)
+    }
+
+    fn queue_bans(&self, guild_id: u64, now: DateTime<Utc>) -> Result<Vec<QueueBan>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT guild_id, user_id, reason, banned_by, created_at, expires_at
+             FROM queue_bans WHERE guild_id = ?1 AND (expires_at IS NULL OR expires_at > ?2)
+             ORDER BY created_at DESC",
+        )?;
+        let rows = stmt.query_map(params![guild_id, now.timestamp()], ban_from_row)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn ban_from_queue(&self, ban: &QueueBan) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO queue_bans
+                (guild_id, user_id, reason, banned_by, created_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                ban.guild_id,
+                ban.user_id,
+                ban.reason,
+                ban.banned_by,
+                ban.created_at.timestamp(),
+                ban.expires_at.map(|t| t.timestamp()),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn unban_from_queue(&self, guild_id: u64, user_id: u64) -> Result<bool> {
+        let removed = self.conn()?.execute(
+            "DELETE FROM queue_bans WHERE guild_id = ?1 AND user_id = ?2",
+            params![guild_id, user_id],
+        )?;
+        Ok(removed > 0)
+    }
+
+    fn setup_sessions(&self) -> Result<Vec<SetupSession>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT session FROM setup_session")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        let mut sessions = Vec::new();
+        for session in rows {
+            sessions.push(serde_json::from_str(&session?)?);
+        }
+        Ok(sessions)
+    }
+
+    fn save_setup_session(&self, session: &SetupSession) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT INTO setup_session (queue_id, session) VALUES (?1, ?2)
+             ON CONFLICT (queue_id) DO UPDATE SET session = excluded.session",
+            params![session.queue_id, serde_json::to_string(session)?],
+        )?;
+        Ok(())
+    }
+
+    fn clear_setup_session(&self, queue_id: i64) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM setup_session WHERE queue_id = ?1",
+            params![queue_id],
+        )?;
+        Ok(())
+    }
+
+    fn add_match(&self, record: &MatchRecord) -> Result<i64> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO matches (dathost_match_id, map, team_a_name, team_a_captain,
+                team_b_name, team_b_captain, team_b_start_side, launched_by, created_at,
+                guild_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                record.dathost_match_id,
+                record.map,
+                record.team_a.name,
+                record.team_a.captain,
+                record.team_b.name,
+                record.team_b.captain,
+                record.team_b_start_side,
+                record.launched_by,
+                record.created_at.timestamp(),
+                record.guild_id,
+            ],
+        )?;
+        let match_id = tx.last_insert_rowid();
+        for (team, players) in [("a", &record.team_a.players), ("b", &record.team_b.players)] {
+            for player in players {
+                tx.execute(
+                    "INSERT INTO match_players (match_id, user_id, name, team)
+                     VALUES (?1, ?2, ?3, ?4)",
+                    params![match_id, player.user_id, player.name, team],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(match_id)
+    }
+
+    fn matches(&self, filter: &MatchFilter, limit: u32, offset: u32) -> Result<Vec<MatchRecord>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, dathost_match_id, map, team_a_name, team_a_captain, team_b_name,
+                team_b_captain, team_b_start_side, launched_by, created_at, guild_id
+             FROM matches WHERE {MATCH_FILTER}
+             ORDER BY id DESC LIMIT ?4 OFFSET ?5"
+        ))?;
+        let rows = stmt.query_map(
+            params![filter.guild_id, filter.map, filter.user_id, limit, offset],
+            match_from_row,
+        )?;
+        let mut matches = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut stmt = conn.prepare(
+            "SELECT user_id, name, team FROM match_players WHERE match_id = ?1 ORDER BY rowid",
+        )?;
+        for record in &mut matches {
+            let rows = stmt.query_map(params![record.id], |row| {
+                let team: String = row.get(2)?;
+                let player = MatchPlayer {
+                    user_id: row.get(0)?,
+                    name: row.get(1)?,
+                };
+                Ok((team, player))
+            })?;
+            for row in rows {
+                let (team, player) = row?;
+                match team.as_str() {
+                    "a" => record.team_a.players.push(player),
+                    _ => record.team_b.players.push(player),
+                }
+            }
+        }
+        Ok(matches)
+    }
+
+    fn match_count(&self, filter: &MatchFilter) -> Result<u32> {
+        Ok(self.conn()?.query_row(
+            &format!("SELECT COUNT(*) FROM matches WHERE {MATCH_FILTER}"),
+            params![filter.guild_id, filter.map, filter.user_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    fn map_cooldown_resets(&self, guild_id: u64) -> Result<HashMap<String, DateTime<Utc>>> {
+        let conn = self.conn()?;
+        let mut stmt =
+            conn.prepare("SELECT map, reset_at FROM map_cooldown_resets WHERE guild_id = ?1")?;
+        let rows = stmt.query_map(params![guild_id], |row| {
+            Ok((row.get(0)?, timestamp(row.get(1)?)))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn reset_map_cooldown(&self, guild_id: u64, map: &str, at: DateTime<Utc>) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO map_cooldown_resets (guild_id, map, reset_at)
+             VALUES (?1, ?2, ?3)",
+            params![guild_id, map, at.timestamp()],
+        )?;
+        Ok(())
+    }
+
+    fn add_series(&self, series: &Series) -> Result<i64> {
+        let conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO series (guild_id, queue_id, channel_id, draft, started_by, score_a,
+                score_b, maps_launched, dathost_match_id, finished, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                series.guild_id,
+                series.queue_id,
+                series.channel_id,
+                serde_json::to_string(&series.draft)?,
+                series.started_by,
+                series.score_a,
+                series.score_b,
+                series.maps_launched,
+                series.dathost_match_id,
+                series.finished,
+                series.created_at.timestamp(),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn active_series(&self) -> Result<Vec<Series>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, guild_id, queue_id, channel_id, draft, started_by, score_a, score_b,
+                maps_launched, dathost_match_id, created_at
+             FROM series WHERE finished = 0 ORDER BY created_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                Series {
+                    id: row.get(0)?,
+                    guild_id: row.get(1)?,
+                    queue_id: row.get(2)?,
+                    channel_id: row.get(3)?,
+                    draft: Draft::default(),
+                    started_by: row.get(5)?,
+                    score_a: row.get(6)?,
+                    score_b: row.get(7)?,
+                    maps_launched: row.get(8)?,
+                    dathost_match_id: row.get(9)?,
+                    finished: false,
+                    created_at: timestamp(row.get(10)?),
+                },
+                row.get::<_, String>(4)?,
+            ))
+        })?;
+        let mut series = Vec::new();
+        for row in rows {
+            let (mut entry, draft) = row?;
+            entry.draft = serde_json::from_str(&draft)?;
+            series.push(entry);
+        }
+        Ok(series)
+    }
+
+    fn update_series(&self, series: &Series) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE series SET draft = ?2, score_a = ?3, score_b = ?4, maps_launched = ?5,
+                dathost_match_id = ?6, finished = ?7
+             WHERE id = ?1",
+            params![
+                series.id,
+                serde_json::to_string(&series.draft)?,
+                series.score_a,
+                series.score_b,
+                series.maps_launched,
+                series.dathost_match_id,
+                series.finished,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn busy_servers(&self) -> Result<HashMap<String, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT server_id, dathost_match_id FROM busy_servers")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_server_busy(&self, server_id: &str, dathost_match_id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO busy_servers (server_id, dathost_match_id, started_at)
+             VALUES (?1, ?2, ?3)",
+            params![server_id, dathost_match_id, Utc::now().timestamp()],
+        )?;
+        Ok(())
+    }
+
+    fn release_server(&self, server_id: &str) -> Result<()> {
+        self.conn()?.execute(
+            "DELETE FROM busy_servers WHERE server_id = ?1",
+            params![server_id],
+        )?;
+        Ok(())
+    }
+
+    fn adopt_unscoped(&self, guild_id: u64) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let mut adopted = 0;
+        // a guild queue with the same name keeps it, the moved queue is renamed
+        let queues = tx
+            .prepare("SELECT id, name FROM queues WHERE guild_id = 0")?
+            .query_map([], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (queue_id, name) in queues {
+            let taken = |name: &str| -> rusqlite::Result<bool> {
+                tx.query_row(
+                    "SELECT EXISTS (SELECT 1 FROM queues WHERE guild_id = ?1 AND name = ?2)",
+                    params![guild_id, name],
+                    |row| row.get(0),
+                )
+            };
+            let mut moved_name = name.clone();
+            if taken(&name)? {
+                moved_name = format!("{name}-legacy");
+                if taken(&moved_name)? {
+                    moved_name = format!("{name}-legacy-{queue_id}");
+                }
+                log::warn!(
+                    "Guild {} already has a `{}` queue, the queue without a guild was moved to it as `{}`",
+                    guild_id,
+                    name,
+                    moved_name
+                );
+            }
+            adopted += tx.execute(
+                "UPDATE queues SET guild_id = ?1, name = ?2 WHERE id = ?3",
+                params![guild_id, moved_name, queue_id],
+            )?;
+        }
+        // settings the guild already has are kept without a guild and logged
+        let conflicting = tx
+            .prepare(
+                "SELECT key, value FROM guild_settings WHERE guild_id = 0
+                 AND key IN (SELECT key FROM guild_settings WHERE guild_id = ?1)",
+            )?
+            .query_map(params![guild_id], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (key, value) in conflicting {
+            log::warn!(
+                "Guild {} already sets `{}`, its value `{}` without a guild was left in guild_settings with guild_id 0",
+                guild_id,
+                key,
+                value
+            );
+        }
+        adopted += tx.execute(
+            "UPDATE OR IGNORE guild_settings SET guild_id = ?1 WHERE guild_id = 0",
+            params![guild_id],
+        )?;
+        adopted += tx.execute(
+            "UPDATE matches SET guild_id = ?1 WHERE guild_id = 0",
+            params![guild_id],
+        )?;
+        tx.commit()?;
+        Ok(adopted > 0)
+    }
+
+    fn legacy_imported(&self) -> Result<bool> {
+        let imported: Option<String> = self
+            .conn()?
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![LEGACY_IMPORT_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(imported.is_some())
+    }
+
+    fn import_legacy(&self, legacy: &LegacyData) -> Result<bool> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let imported: Option<String> = tx
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![LEGACY_IMPORT_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if imported.is_some() {
+            return Ok(false);
+        }
+        // as in migration 0014, a SteamID claimed by several users stays with the
+        // lowest user id (or whoever linked it already) and the other links are
+        // kept in `duplicate_steam_ids`
+        let mut steam_ids: Vec<_> = legacy.steam_ids.iter().collect();
+        steam_ids.sort();
+        let mut duplicates = Vec::new();
+        for (user_id, steam_id) in steam_ids {
+            let owner: Option<u64> = tx
+                .query_row(
+                    "SELECT user_id FROM steam_ids WHERE steam_id = ?1",
+                    params![steam_id],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            match owner {
+                Some(owner) if owner != *user_id => {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO duplicate_steam_ids (user_id, steam_id, kept_by)
+                         VALUES (?1, ?2, ?3)",
+                        params![user_id, steam_id, owner],
+                    )?;
+                    duplicates.push((*user_id, *steam_id));
+                }
+                _ => {
+                    tx.execute(
+                        "INSERT OR IGNORE INTO steam_ids (user_id, steam_id) VALUES (?1, ?2)",
+                        params![user_id, steam_id],
+                    )?;
+                }
+            }
+        }
+        for (user_id, steam_id) in &legacy.unconverted_steam_ids {
+            tx.execute(
+                "INSERT OR REPLACE INTO unconverted_steam_ids (user_id, steam_id) VALUES (?1, ?2)",
+                params![user_id, steam_id],
+            )?;
+        }
+        for (user_id, team_name) in &legacy.team_names {
+            tx.execute(
+                "INSERT OR REPLACE INTO team_names (user_id, team_name) VALUES (?1, ?2)",
+                params![user_id, team_name],
+            )?;
+        }
+        tx.execute(
+            "INSERT OR IGNORE INTO queues (guild_id, name) VALUES (0, ?1)",
+            params![DEFAULT_QUEUE],
+        )?;
+        let queue_id: i64 = tx.query_row(
+            "SELECT id FROM queues WHERE guild_id = 0 AND name = ?1",
+            params![DEFAULT_QUEUE],
+            |row| row.get(0),
+        )?;
+        tx.execute(
+            "INSERT OR IGNORE INTO map_pools (queue_id, name) VALUES (?1, ?2)",
+            params![queue_id, DEFAULT_MAP_POOL],
+        )?;
+        let pool_id: i64 = tx.query_row(
+            "SELECT id FROM map_pools WHERE queue_id = ?1 AND name = ?2",
+            params![queue_id, DEFAULT_MAP_POOL],
+            |row| row.get(0),
+        )?;
+        for map in &legacy.maps {
+            tx.execute(
+                "INSERT OR IGNORE INTO maps (pool_id, name, display_name, thumbnail_url, enabled)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    pool_id,
+                    map.name,
+                    map.display_name,
+                    map.thumbnail_url,
+                    map.enabled
+                ],
+            )?;
+        }
+        for user in &legacy.queue {
+            tx.execute(
+                "INSERT OR IGNORE INTO queue (queue_id, user_id, user) VALUES (?1, ?2, ?3)",
+                params![queue_id, user.id.0, serde_json::to_string(user)?],
+            )?;
+        }
+        for (user_id, message) in &legacy.queue_messages {
+            tx.execute(
+                "INSERT OR REPLACE INTO queue_messages (queue_id, user_id, message)
+                 VALUES (?1, ?2, ?3)",
+                params![queue_id, user_id, message],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, datetime('now'))",
+            params![LEGACY_IMPORT_KEY],
+        )?;
+        tx.commit()?;
+        for (user_id, steam_id) in &legacy.unconverted_steam_ids {
+            log_set_aside(UNCONVERTED_STEAM_IDS, *user_id, steam_id);
+        }
+        for (user_id, steam_id) in duplicates {
+            log_set_aside(DUPLICATE_STEAM_IDS, user_id, &steam_id.to_string());
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::parse_steam_id;
+    use crate::State;
+    use serenity::model::id::UserId;
+
+    /// In-memory database with the first `version` migrations applied.
+    fn database_at(version: usize) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for (migration, _) in &MIGRATIONS[..version] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", version).unwrap();
+        conn
+    }
+
+    fn steam_ids(conn: &Connection, table: &str) -> HashMap<u64, String> {
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT user_id, CAST(steam_id AS TEXT) FROM {}",
+                table
+            ))
+            .unwrap();
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        rows.collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[test]
+    fn migrate_converts_steam_ids_to_steam_id_64() {
+        let mut conn = database_at(12);
+        let converted = [
+            (1, "STEAM_1:1:12345678"),
+            (2, " steam_0:0:7 "),
+            (3, "76561198000000000"),
+            (4, "[U:1:1234]"),
+        ];
+        let unconverted = [
+            (5, "not a steam id"),
+            (6, "STEAM_1:2:5"),
+            (7, "7656119800000000"),
+            (8, "STEAM_1:1:99999999999"),
+        ];
+        for (user_id, steam_id) in converted.iter().chain(&unconverted) {
+            conn.execute(
+                "INSERT INTO steam_ids (user_id, steam_id) VALUES (?1, ?2)",
+                params![user_id, steam_id],
+            )
+            .unwrap();
+        }
+        migrate(&mut conn).unwrap();
+
+        let expected: HashMap<u64, String> = converted
+            .iter()
+            .map(|(user_id, steam_id)| (*user_id, parse_steam_id(steam_id).unwrap().to_string()))
+            .collect();
+        assert_eq!(steam_ids(&conn, "steam_ids"), expected);
+        let expected: HashMap<u64, String> = unconverted
+            .iter()
+            .map(|(user_id, steam_id)| (*user_id, steam_id.to_string()))
+            .collect();
+        assert_eq!(steam_ids(&conn, "unconverted_steam_ids"), expected);
+    }
+
+    #[test]
+    fn migrate_keeps_duplicate_steam_ids_aside() {
+        let mut conn = database_at(13);
+        for (user_id, steam_id) in [(3, 100), (1, 100), (2, 100), (4, 200)] {
+            conn.execute(
+                "INSERT INTO steam_ids (user_id, steam_id) VALUES (?1, ?2)",
+                params![user_id, steam_id],
+            )
+            .unwrap();
+        }
+        migrate(&mut conn).unwrap();
+
+        let kept = HashMap::from([(1, "100".to_string()), (4, "200".to_string())]);
+        assert_eq!(steam_ids(&conn, "steam_ids"), kept);
+        let mut stmt = conn
+            .prepare("SELECT user_id, steam_id, kept_by FROM duplicate_steam_ids ORDER BY user_id")
+            .unwrap();
+        let duplicates: Vec<(u64, u64, u64)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(duplicates, vec![(2, 100, 1), (3, 100, 1)]);
+    }
+
+    fn storage() -> SqliteStorage {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        SqliteStorage(Mutex::new(conn))
+    }
+
+    fn user(id: u64) -> User {
+        let mut user = User::default();
+        user.id = UserId(id);
+        user
+    }
+
+    #[test]
+    fn import_legacy_sets_duplicates_and_invalid_steam_ids_aside() {
+        let storage = storage();
+        let legacy = LegacyData {
+            steam_ids: HashMap::from([(3, 100), (1, 100), (2, 200)]),
+            unconverted_steam_ids: HashMap::from([(4, "not a steam id".to_string())]),
+            team_names: HashMap::from([(1, "Team One".to_string())]),
+            maps: vec![MapEntry::new("de_dust2")],
+            queue: vec![user(1), user(2)],
+            ..Default::default()
+        };
+        assert!(storage.import_legacy(&legacy).unwrap());
+        assert!(storage.legacy_imported().unwrap());
+        // a second import is a no-op, even with different data
+        let again = LegacyData {
+            steam_ids: HashMap::from([(5, 500)]),
+            ..Default::default()
+        };
+        assert!(!storage.import_legacy(&again).unwrap());
+
+        assert_eq!(
+            storage.steam_ids().unwrap(),
+            HashMap::from([(1, 100), (2, 200)])
+        );
+        assert_eq!(
+            storage.team_names().unwrap(),
+            HashMap::from([(1, "Team One".to_string())])
+        );
+        let conn = storage.conn().unwrap();
+        assert_eq!(
+            steam_ids(&conn, "unconverted_steam_ids"),
+            HashMap::from([(4, "not a steam id".to_string())])
+        );
+        let duplicates: (u64, u64, u64) = conn
+            .query_row(
+                "SELECT user_id, steam_id, kept_by FROM duplicate_steam_ids",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(duplicates, (3, 100, 1));
+        drop(conn);
+
+        let queue = storage.queues(0).unwrap();
+        assert_eq!(queue.len(), 1);
+        let players: Vec<u64> = storage
+            .queue(queue[0].id)
+            .unwrap()
+            .iter()
+            .map(|u| u.id.0)
+            .collect();
+        assert_eq!(players, vec![1, 2]);
+        let pools = storage.map_pools(queue[0].id).unwrap();
+        assert_eq!(pools[0].maps.len(), 1);
+        assert_eq!(pools[0].maps[0].name, "de_dust2");
+    }
+
+    #[test]
+    fn setup_session_round_trip() {
+        let storage = storage();
+        let queue_id = storage.create_queue(1, "main").unwrap();
+        let mut session = SetupSession {
+            guild_id: 1,
+            queue_id,
+            channel_id: 2,
+            message_id: 3,
+            state: State::MapPick,
+            draft: Draft::default(),
+            ready_queue: vec![user(4), user(5)],
+            started_by: Some(4),
+        };
+        storage.save_setup_session(&session).unwrap();
+        // saving again replaces the session of the queue
+        session.state = State::Draft;
+        session.draft.selected_map = "de_inferno".to_string();
+        storage.save_setup_session(&session).unwrap();
+
+        let sessions = storage.setup_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let loaded = &sessions[0];
+        assert_eq!(
+            (
+                loaded.guild_id,
+                loaded.queue_id,
+                loaded.channel_id,
+                loaded.message_id
+            ),
+            (1, queue_id, 2, 3)
+        );
+        assert!(loaded.state == State::Draft);
+        assert_eq!(loaded.draft.selected_map, "de_inferno");
+        let ready: Vec<u64> = loaded.ready_queue.iter().map(|u| u.id.0).collect();
+        assert_eq!(ready, vec![4, 5]);
+        assert_eq!(loaded.started_by, Some(4));
+
+        storage.clear_setup_session(queue_id).unwrap();
+        assert!(storage.setup_sessions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn adopt_unscoped_keeps_conflicting_rows() {
+        let storage = storage();
+        storage.create_queue(0, "default").unwrap();
+        storage.create_queue(0, "mixed").unwrap();
+        storage.create_queue(7, "default").unwrap();
+        storage
+            .set_guild_setting(0, "post_setup_msg", "old")
+            .unwrap();
+        storage.set_guild_setting(0, "autoclear_hour", "4").unwrap();
+        storage
+            .set_guild_setting(7, "post_setup_msg", "new")
+            .unwrap();
+
+        assert!(storage.adopt_unscoped(7).unwrap());
+        let mut names: Vec<String> = storage
+            .queues(7)
+            .unwrap()
+            .into_iter()
+            .map(|q| q.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["default", "default-legacy", "mixed"]);
+        assert!(storage.queues(0).unwrap().is_empty());
+        assert_eq!(
+            storage.guild_settings(7).unwrap(),
+            HashMap::from([
+                ("post_setup_msg".to_string(), "new".to_string()),
+                ("autoclear_hour".to_string(), "4".to_string()),
+            ])
+        );
+        assert_eq!(
+            storage.guild_settings(0).unwrap(),
+            HashMap::from([("post_setup_msg".to_string(), "old".to_string())])
+        );
+        // only the conflicting setting is left, there is nothing more to adopt
+        assert!(!storage.adopt_unscoped(7).unwrap());
+    }
+}
@@ -1,9 +1,11 @@
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use anyhow::Result;
+use chrono::Duration;
 
-use crate::{Context, Draft, ScrimbotApiConfig, State};
-use poise::serenity_prelude::{Guild, InteractionResponseType, MessageComponentInteraction, User};
+use crate::{Context, Draft, Lobby, ScrimbotApiConfig, State};
+use poise::serenity_prelude::{Guild, User};
 use reqwest::header;
 use serde::{Deserialize, Serialize};
 use serenity::{http::CacheHttp, utils::MessageBuilder};
@@ -28,21 +30,21 @@ pub struct Stats {
 pub(crate) async fn format_stats(
     stats: &Vec<Stats>,
     context: &Context<'_>,
-    steam_id_cache: &HashMap<u64, String>,
+    steam_id_cache: &HashMap<u64, u64>,
     &guild_id: &u64,
     print_map: bool,
 ) -> Result<String> {
     let mut top_ten_str: String = String::from("");
     top_ten_str.push_str("```md\n");
     if stats.len() == 1 {
-        let mut map = String::from(stats[0].map.clone());
-        if map != "" {
+        let mut map = stats[0].map.clone();
+        if !map.is_empty() {
             map = map.replace("de_", "");
             if map.len() > 12 {
                 map = map[0..9].to_string();
                 map.push_str("...");
             }
-            top_ten_str.push_str(&*format!(
+            top_ten_str.push_str(&format!(
                 "Map: {:<12} K/D    ADR      RWS     Rating   HS%      Win% (# Games)\n",
                 map
             ));
@@ -68,7 +70,7 @@ pub(crate) async fn format_stats(
     for stat in stats {
         count += 1;
         let user_id: Option<u64> = steam_id_cache.iter().find_map(|(key, val)| {
-            if format!("STEAM_1{}", &val[7..]) == stat.steamId {
+            if steam_id_2(*val) == stat.steamId {
                 Some(*key)
             } else {
                 None
@@ -93,13 +95,17 @@ pub(crate) async fn format_stats(
                     user_name = user_name[0..9].to_string();
                     user_name.push_str("...");
                 }
+                let rank = format!("{}.", count);
+                let user_name = format!("{: <12}", user_name);
+                let adr = format!("{:.2}", &stat.adr);
+                let rws = format!("{:.2}", &stat.rws);
                 top_ten_str.push_str(&format!(
                     "{:>3} @{} {:3.2}  {: >6}   {: >6}   {:3.2}     {:3.1}%    {:3.2}% ({})\n",
-                    format!("{}.", count.to_string()),
-                    format!("{: <12}", user_name.to_owned()),
+                    rank,
+                    user_name,
                     stat.kdRatio,
-                    format!("{:.2}", &stat.adr),
-                    format!("{:.2}", &stat.rws),
+                    adr,
+                    rws,
                     stat.rating,
                     stat.hs,
                     stat.winPercentage,
@@ -112,13 +118,17 @@ pub(crate) async fn format_stats(
                     map = map[0..9].to_string();
                     map.push_str("...");
                 }
+                let rank = format!("{}.", count);
+                let map = format!("{: <12}", map);
+                let adr = format!("{:.2}", &stat.adr);
+                let rws = format!("{:.2}", &stat.rws);
                 top_ten_str.push_str(&format!(
                     "{:>3}  {} {:3.2}   {: >6}  {: >6}   {:3.2}     {:3.1}%    {:3.2}% ({})\n",
-                    format!("{}.", count.to_string()),
-                    format!("{: <12}", map.to_owned()),
+                    rank,
+                    map,
                     stat.kdRatio,
-                    format!("{:.2}", &stat.adr),
-                    format!("{:.2}", &stat.rws),
+                    adr,
+                    rws,
                     stat.rating,
                     stat.hs,
                     stat.winPercentage,
@@ -137,12 +147,49 @@ pub(crate) async fn format_stats(
     Ok(top_ten_str)
 }
 
-pub(crate) fn convert_steamid_to_64(steamid: &String) -> u64 {
-    let steamid_split: Vec<&str> = steamid.split(":").collect();
-    let y = steamid_split[1].parse::<i64>().unwrap();
-    let z = steamid_split[2].parse::<i64>().unwrap();
-    let steamid_64 = (z * 2) + y + 76561197960265728;
-    return steamid_64 as u64;
+/// SteamID64 of the individual account with account number 0.
+const STEAM_ID_64_BASE: u64 = 76561197960265728;
+const PROFILE_URL_PATH: &str = "steamcommunity.com/profiles/";
+
+/// Parses a SteamID64 (`76561198...`), SteamID (`STEAM_0:1:12345678`),
+/// SteamID3 (`[U:1:24691357]`) or `steamcommunity.com/profiles/...` URL into a
+/// SteamID64.
+pub(crate) fn parse_steam_id(value: &str) -> Option<u64> {
+    let value = value.trim().trim_end_matches('/');
+    if let Some(index) = value.find(PROFILE_URL_PATH) {
+        return parse_steam_id_64(&value[index + PROFILE_URL_PATH.len()..]);
+    }
+    let upper = value.to_uppercase();
+    if let Some(steam_id) = upper.strip_prefix("STEAM_") {
+        let parts: Vec<&str> = steam_id.split(':').collect();
+        let [universe, y, z] = parts[..] else {
+            return None;
+        };
+        universe.parse::<u8>().ok()?;
+        let y: u64 = y.parse().ok().filter(|y| *y <= 1)?;
+        let z: u64 = z.parse().ok()?;
+        return account_to_steam_id_64(z.checked_mul(2)? + y);
+    }
+    let steam_id_3 = upper.trim_start_matches('[').trim_end_matches(']');
+    if let Some(account) = steam_id_3.strip_prefix("U:1:") {
+        return account_to_steam_id_64(account.parse().ok()?);
+    }
+    parse_steam_id_64(value)
+}
+
+fn parse_steam_id_64(value: &str) -> Option<u64> {
+    let steam_id: u64 = value.parse().ok()?;
+    account_to_steam_id_64(steam_id.checked_sub(STEAM_ID_64_BASE)?)
+}
+
+fn account_to_steam_id_64(account: u64) -> Option<u64> {
+    (account <= u32::MAX as u64).then_some(STEAM_ID_64_BASE + account)
+}
+
+/// Formats a SteamID64 as `STEAM_1:Y:Z`, the format used by scrimbot-api.
+pub(crate) fn steam_id_2(steam_id_64: u64) -> String {
+    let account = steam_id_64.saturating_sub(STEAM_ID_64_BASE);
+    format!("STEAM_1:{}:{}", account % 2, account / 2)
 }
 
 pub(crate) fn list_teams(draft: &Draft, team_names: &HashMap<u64, String>) -> String {
@@ -171,50 +218,40 @@ pub(crate) fn list_teams(draft: &Draft, team_names: &HashMap<u64, String>) -> St
     response
 }
 
-pub(crate) async fn write_to_file(path: String, content: String) {
-    let mut error_string = String::from("Error writing to ");
-    error_string.push_str(&path);
-    std::fs::write(path, content).expect(&error_string);
+/// The lobby of the queue the command's channel belongs to.
+pub(crate) async fn lobby(context: &Context<'_>) -> Result<Arc<Lobby>> {
+    context
+        .data()
+        .lobby(context.guild_id().unwrap().0, context.channel_id().0)
+        .await
 }
-pub(crate) async fn user_in_queue(
-    context: &Context<'_>,
-    mci: Option<&MessageComponentInteraction>,
-) -> Result<bool> {
-    let queue = context.data().user_queue.lock().await.clone();
+
+pub(crate) async fn user_in_queue(context: &Context<'_>) -> Result<bool> {
+    let queue = lobby(context).await?.user_queue.lock().await.clone();
     let uids: Vec<u64> = queue.into_iter().map(|u| u.id.0).collect();
     let member_id = &context.author().id.0;
     if !uids.contains(member_id) {
-        if let Some(mci) = mci {
-            mci.create_interaction_response(context, |r| {
-                r.kind(InteractionResponseType::ChannelMessageWithSource)
-                    .interaction_response_data(|d| {
-                        d.ephemeral(true).content("You are not in the queue")
-                    })
-            })
+        context
+            .send(|m| m.ephemeral(true).content("You are not in the queue"))
             .await?;
-        } else {
-            context
-                .send(|m| m.ephemeral(true).content("You are not in the queue"))
-                .await?;
-        }
         return Ok(false);
     }
-    return Ok(true);
+    Ok(true)
 }
 
 pub fn get_api_client(config: &ScrimbotApiConfig) -> reqwest::Client {
     let mut headers = header::HeaderMap::new();
     let auth_str = format!("TOKEN {}", &config.scrimbot_api_token);
     headers.insert("Authorization", auth_str.parse().unwrap());
-    let client = reqwest::Client::builder()
+
+    reqwest::Client::builder()
         .default_headers(headers)
         .build()
-        .unwrap();
-    client
+        .unwrap()
 }
-pub async fn reset_draft(context: &Context<'_>) -> Result<()> {
+pub async fn reset_draft(lobby: &Lobby) -> Result<()> {
     {
-        let mut draft = context.data().draft.lock().await;
+        let mut draft = lobby.draft.lock().await;
         draft.captain_a = None;
         draft.captain_b = None;
         draft.current_picker = None;
@@ -222,37 +259,294 @@ pub async fn reset_draft(context: &Context<'_>) -> Result<()> {
         draft.team_b = Vec::new();
         draft.team_b_start_side = String::from("");
         draft.map_votes = HashMap::new();
+        draft.map_voted = Vec::new();
         draft.selected_map = String::new();
+        draft.veto = Vec::new();
+        draft.series_maps = Vec::new();
+        draft.series_sides = Vec::new();
     }
     {
-        let mut ready_queue = context.data().ready_queue.lock().await;
+        let mut ready_queue = lobby.ready_queue.lock().await;
         ready_queue.clear();
     }
     {
-        let mut state = context.data().state.lock().await;
+        let mut state = lobby.state.lock().await;
         *state = State::Queue;
     }
+    lobby.storage.clear_setup_session(lobby.queue_id)?;
     Ok(())
 }
 
-pub async fn clear_queue(context: &Context<'_>) -> Result<()> {
-    {
-        let mut user_queue = context.data().user_queue.lock().await;
-        user_queue.clear();
-        write_to_file(
-            String::from("data/queue.json"),
-            serde_json::to_string(&user_queue.clone()).unwrap(),
-        )
-        .await;
+/// Empties the queue and moves waitlisted players up into it. Returns the
+/// promoted players.
+pub async fn clear_queue(lobby: &Lobby) -> Result<Vec<User>> {
+    lobby.storage.clear_queue(lobby.queue_id)?;
+    lobby.parties.lock().await.clear();
+    let user_queue: Vec<User> = lobby.user_queue.lock().await.drain(..).collect();
+    let mut queue_messages = lobby.queue_messages.lock().await;
+    for user in user_queue {
+        queue_messages.remove(user.id.as_u64());
     }
-    {
-        let mut queue_messages = context.data().queue_messages.lock().await;
-        queue_messages.clear();
-        write_to_file(
-            String::from("data/queue-messages.json"),
-            serde_json::to_string(&queue_messages.clone()).unwrap(),
-        )
-        .await;
+    drop(queue_messages);
+    promote_waitlist(lobby).await
+}
+
+pub async fn clear_waitlist(lobby: &Lobby) -> Result<()> {
+    lobby.storage.clear_waitlist(lobby.queue_id)?;
+    lobby.parties.lock().await.clear();
+    let waitlist: Vec<User> = lobby.waitlist.lock().await.drain(..).collect();
+    let mut queue_messages = lobby.queue_messages.lock().await;
+    for user in waitlist {
+        queue_messages.remove(user.id.as_u64());
+    }
+    Ok(())
+}
+
+/// Removes `user_id` from the queue together with their queue message and
+/// expiry. Returns whether they were queued.
+pub async fn remove_from_queue(lobby: &Lobby, user_id: u64) -> Result<bool> {
+    let removed = {
+        let mut user_queue = lobby.user_queue.lock().await;
+        let len = user_queue.len();
+        user_queue.retain(|u| u.id.0 != user_id);
+        user_queue.len() != len
+    };
+    if removed {
+        lobby.storage.remove_from_queue(lobby.queue_id, user_id)?;
+        forget_entry(lobby, user_id).await?;
     }
+    Ok(removed)
+}
+
+/// Removes `user_id` from the waitlist together with their queue message and
+/// expiry. Returns whether they were waitlisted.
+pub async fn remove_from_waitlist(lobby: &Lobby, user_id: u64) -> Result<bool> {
+    let removed = {
+        let mut waitlist = lobby.waitlist.lock().await;
+        let len = waitlist.len();
+        waitlist.retain(|u| u.id.0 != user_id);
+        waitlist.len() != len
+    };
+    if removed {
+        lobby
+            .storage
+            .remove_from_waitlist(lobby.queue_id, user_id)?;
+        forget_entry(lobby, user_id).await?;
+    }
+    Ok(removed)
+}
+
+async fn forget_entry(lobby: &Lobby, user_id: u64) -> Result<()> {
+    lobby
+        .storage
+        .remove_queue_message(lobby.queue_id, user_id)?;
+    lobby
+        .storage
+        .set_queue_expiry(lobby.queue_id, user_id, None)?;
+    lobby.queue_messages.lock().await.remove(&user_id);
+    leave_party(lobby, user_id).await?;
+    Ok(())
+}
+
+/// Ids of the members of `user_id`'s party, or just `user_id` if they are not
+/// in one.
+pub async fn party_members(lobby: &Lobby, user_id: u64) -> Vec<u64> {
+    let parties = lobby.parties.lock().await;
+    let Some(leader_id) = parties.get(&user_id) else {
+        return vec![user_id];
+    };
+    let mut members: Vec<u64> = parties
+        .iter()
+        .filter(|(_, leader)| *leader == leader_id)
+        .map(|(member, _)| *member)
+        .collect();
+    members.sort();
+    members
+}
+
+/// Adds `user_id` to the party led by `leader_id`, creating it if needed.
+pub async fn join_party(lobby: &Lobby, leader_id: u64, user_id: u64) -> Result<()> {
+    let mut parties = lobby.parties.lock().await;
+    parties.insert(leader_id, leader_id);
+    parties.insert(user_id, leader_id);
+    let members: Vec<u64> = parties
+        .iter()
+        .filter(|(_, leader)| **leader == leader_id)
+        .map(|(member, _)| *member)
+        .collect();
+    lobby
+        .storage
+        .set_party(lobby.queue_id, leader_id, &members)?;
     Ok(())
 }
+
+/// Removes `user_id` from their party. A party is disbanded when its leader
+/// leaves or only one member is left. Returns whether they were in a party.
+pub async fn leave_party(lobby: &Lobby, user_id: u64) -> Result<bool> {
+    let mut parties = lobby.parties.lock().await;
+    let Some(leader_id) = parties.remove(&user_id) else {
+        return Ok(false);
+    };
+    let mut members: Vec<u64> = parties
+        .iter()
+        .filter(|(_, leader)| **leader == leader_id)
+        .map(|(member, _)| *member)
+        .collect();
+    if leader_id == user_id || members.len() < 2 {
+        for member in &members {
+            parties.remove(member);
+        }
+        members.clear();
+    }
+    lobby
+        .storage
+        .set_party(lobby.queue_id, leader_id, &members)?;
+    Ok(true)
+}
+
+/// Longest duration `parse_duration` accepts, anything longer is a typo or
+/// meant to be permanent.
+pub const MAX_DURATION_DAYS: i64 = 3650;
+
+/// Parses a duration such as `2h`, `45m`, `1h30m`, `3d` or `for 2h`. Returns
+/// `None` for anything else and for durations over `MAX_DURATION_DAYS`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim().to_lowercase();
+    let mut value = value.strip_prefix("for").unwrap_or(&value).trim();
+    let mut duration = Duration::zero();
+    while !value.is_empty() {
+        let digits = value.find(|c: char| !c.is_ascii_digit())?;
+        let amount: i64 = value[..digits].parse().ok()?;
+        let unit_end = value[digits..]
+            .find(|c: char| c.is_ascii_digit())
+            .map_or(value.len(), |i| digits + i);
+        let part = match value[digits..unit_end].trim() {
+            "m" | "min" | "mins" | "minute" | "minutes" => Duration::try_minutes(amount),
+            "h" | "hr" | "hrs" | "hour" | "hours" => Duration::try_hours(amount),
+            "d" | "day" | "days" => Duration::try_days(amount),
+            _ => return None,
+        }?;
+        duration = duration.checked_add(&part)?;
+        value = value[unit_end..].trim_start();
+    }
+    (duration > Duration::zero() && duration <= Duration::days(MAX_DURATION_DAYS))
+        .then_some(duration)
+}
+
+/// Fills open spots in the queue from the front of the waitlist. Returns the
+/// promoted players.
+pub async fn promote_waitlist(lobby: &Lobby) -> Result<Vec<User>> {
+    let max_queue_size = (*lobby.team_size.lock().await * 2).into();
+    let mut user_queue = lobby.user_queue.lock().await;
+    let mut waitlist = lobby.waitlist.lock().await;
+    let mut promoted = Vec::new();
+    while user_queue.len() < max_queue_size && !waitlist.is_empty() {
+        let user = waitlist.remove(0);
+        lobby
+            .storage
+            .remove_from_waitlist(lobby.queue_id, user.id.0)?;
+        lobby.storage.add_to_queue(lobby.queue_id, &user)?;
+        user_queue.push(user.clone());
+        promoted.push(user);
+    }
+    Ok(promoted)
+}
+
+/// Mentions `promoted` players, if any, with the new queue size.
+pub async fn promotion_message(lobby: &Lobby, promoted: &[User]) -> Option<String> {
+    if promoted.is_empty() {
+        return None;
+    }
+    let queue_size = lobby.user_queue.lock().await.len();
+    let max_queue_size = *lobby.team_size.lock().await * 2;
+    let mut response = MessageBuilder::new();
+    for user in promoted {
+        response.mention(user).push(" ");
+    }
+    response
+        .push("moved up from the waitlist. Queue size: ")
+        .push(queue_size)
+        .push(format!("/{}", max_queue_size));
+    Some(response.build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("45m"), Some(Duration::minutes(45)));
+        assert_eq!(parse_duration("2h"), Some(Duration::hours(2)));
+        assert_eq!(parse_duration("3 days"), Some(Duration::days(3)));
+        assert_eq!(parse_duration("for 1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration(" 1D 12H "), Some(Duration::hours(36)));
+    }
+
+    #[test]
+    fn parse_duration_rejects_invalid() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("2w"), None);
+        assert_eq!(parse_duration("-2h"), None);
+    }
+
+    #[test]
+    fn parse_duration_rejects_overflow() {
+        assert_eq!(parse_duration("99999999999999d"), None);
+        assert_eq!(parse_duration("99999999999999999999m"), None);
+        assert_eq!(
+            parse_duration(&format!("{}m{}m", i64::MAX / 120, i64::MAX / 120)),
+            None
+        );
+        assert_eq!(
+            parse_duration(&format!("{}d", MAX_DURATION_DAYS)),
+            Some(Duration::days(MAX_DURATION_DAYS))
+        );
+        assert_eq!(parse_duration(&format!("{}d1m", MAX_DURATION_DAYS)), None);
+    }
+
+    /// Account 24691357, `STEAM_1:1:12345678`.
+    const STEAM_ID_64: u64 = 76561197984957085;
+
+    #[test]
+    fn parse_steam_id_formats() {
+        for value in [
+            "76561197984957085",
+            "STEAM_1:1:12345678",
+            "steam_0:1:12345678",
+            "[U:1:24691357]",
+            "U:1:24691357",
+            "https://steamcommunity.com/profiles/76561197984957085/",
+            " 76561197984957085 ",
+        ] {
+            assert_eq!(parse_steam_id(value), Some(STEAM_ID_64), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_steam_id_rejects_invalid() {
+        for value in [
+            "",
+            "12345",
+            "STEAM_1:2:12345678",
+            "STEAM_1:1",
+            "STEAM_1:1:x",
+            "STEAM_1:1:99999999999",
+            "[U:1:4294967296]",
+            "76561197960265727",
+            "76561202255233024",
+            "https://steamcommunity.com/id/someone",
+        ] {
+            assert_eq!(parse_steam_id(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn steam_id_2_round_trips() {
+        assert_eq!(steam_id_2(STEAM_ID_64), "STEAM_1:1:12345678");
+        assert_eq!(parse_steam_id(&steam_id_2(STEAM_ID_64)), Some(STEAM_ID_64));
+    }
+}
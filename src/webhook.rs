@@ -0,0 +1,164 @@
+use crate::commands::series::match_ended;
+use crate::commands::start::match_end_authorization;
+use crate::{Data, WebhookConfig};
+use anyhow::Result;
+use poise::serenity_prelude::Context as SerenityContext;
+use serde::Deserialize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const MATCH_END_PATH: &str = "/dathost/match-end";
+/// DatHost posts the whole match, including the stats of every player.
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// The part of DatHost's match end payload the bot needs.
+#[derive(Deserialize)]
+struct MatchEnd {
+    id: String,
+}
+
+/// URL DatHost calls when a match ends, instead of `dathost.match_end_url`.
+pub(crate) fn match_end_url(config: &WebhookConfig) -> String {
+    format!(
+        "{}{}",
+        config.public_url.trim_end_matches('/'),
+        MATCH_END_PATH
+    )
+}
+
+/// Serves the match end webhook. Each call is relayed to
+/// `dathost.match_end_url` and ends the map if the match belongs to a best of
+/// three. Runs until the bot stops, does nothing if `webhook` is not configured.
+pub(crate) async fn serve_webhook(serenity: SerenityContext, data: Arc<Data>) -> Result<()> {
+    let Some(config) = &data.config.webhook else {
+        return Ok(());
+    };
+    if data.webhook_started.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let listener = TcpListener::bind(&config.listen_address).await?;
+    log::info!(
+        "Listening for match end webhooks on {}",
+        config.listen_address
+    );
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                log::warn!("Unable to accept connection: {}", err);
+                continue;
+            }
+        };
+        let (serenity, data) = (serenity.clone(), data.clone());
+        tokio::spawn(async move {
+            // a client that never finishes its request must not keep the task around
+            let body = match tokio::time::timeout(
+                Duration::from_secs(30),
+                handle_connection(&data, stream),
+            )
+            .await
+            {
+                Ok(Ok(body)) => body,
+                Ok(Err(err)) => {
+                    log::warn!("Unable to answer match end webhook: {}", err);
+                    return;
+                }
+                Err(_) => {
+                    log::warn!("Match end webhook request timed out");
+                    return;
+                }
+            };
+            if let Some(body) = body {
+                if let Err(err) = match_end(&serenity, &data, body).await {
+                    log::error!("Unable to handle match end webhook: {:?}", err);
+                }
+            }
+        });
+    }
+}
+
+/// Answers the request, returns the body of an accepted match end call.
+async fn handle_connection(data: &Data, mut stream: TcpStream) -> Result<Option<Vec<u8>>> {
+    let mut request = Vec::new();
+    let mut buffer = [0; 4096];
+    let header_end = loop {
+        if let Some(index) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+            break index + 4;
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 || request.len() + read > MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        request.extend_from_slice(&buffer[..read]);
+    };
+    let head = String::from_utf8_lossy(&request[..header_end]).into_owned();
+    let header = |name: &str| {
+        head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| value.trim().to_string())
+        })
+    };
+    let target = head
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("POST "))
+        .and_then(|line| line.split(' ').next());
+    let authorization = match_end_authorization(&data.config);
+    let (status, body) = if target != Some(MATCH_END_PATH) {
+        ("404 Not Found", None)
+    } else if !authorization.is_empty()
+        && header("Authorization").as_deref() != Some(authorization.as_str())
+    {
+        ("401 Unauthorized", None)
+    } else {
+        let length: usize = header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .unwrap_or_default();
+        if length > MAX_REQUEST_BYTES - header_end {
+            return Ok(None);
+        }
+        let mut body = request.split_off(header_end);
+        while body.len() < length {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            body.extend_from_slice(&buffer[..read]);
+        }
+        body.truncate(length);
+        ("200 OK", Some(body))
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(body)
+}
+
+/// Relays the call to `dathost.match_end_url` as DatHost would have made it,
+/// then ends the map of the series playing the match.
+async fn match_end(serenity: &SerenityContext, data: &Data, body: Vec<u8>) -> Result<()> {
+    let match_id = serde_json::from_slice::<MatchEnd>(&body).map(|m| m.id);
+    if let Some(url) = &data.config.dathost.match_end_url {
+        let authorization = match_end_authorization(&data.config);
+        let mut request = reqwest::Client::new()
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .timeout(Duration::from_secs(30))
+            .body(body);
+        if !authorization.is_empty() {
+            request = request.header(reqwest::header::AUTHORIZATION, authorization);
+        }
+        if let Err(err) = request.send().await.and_then(|r| r.error_for_status()) {
+            log::warn!("Unable to relay match end webhook to {}: {}", url, err);
+        }
+    }
+    match_ended(serenity, data, &match_id?).await
+}